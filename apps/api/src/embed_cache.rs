@@ -0,0 +1,195 @@
+//! 向量缓存的持久化层：sled（单机）与 Redis（多实例共享）
+
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+use kb_llm::EmbeddingStore;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// 向量缓存配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmbedCacheCfg {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 进程内 LRU 条数
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    /// 持久化层：sled / redis，为空时仅使用进程内缓存
+    pub store: Option<String>,
+    /// sled 数据目录
+    pub path: Option<String>,
+    /// Redis 地址，为空时使用环境变量 REDIS_URL
+    pub redis_url: Option<String>,
+    /// Redis 键过期时间（秒），为空时不过期
+    pub ttl_secs: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_capacity() -> usize {
+    10_000
+}
+
+impl Default for EmbedCacheCfg {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            capacity: default_capacity(),
+            store: None,
+            path: None,
+            redis_url: None,
+            ttl_secs: None,
+        }
+    }
+}
+
+/// 按配置构建持久化层
+pub fn build_store(cfg: &EmbedCacheCfg) -> anyhow::Result<Option<Arc<dyn EmbeddingStore>>> {
+    match cfg.store.as_deref() {
+        None | Some("") | Some("memory") => Ok(None),
+        Some("sled") => {
+            let path = cfg.path.as_deref().unwrap_or("data/embed_cache");
+            Ok(Some(Arc::new(SledEmbeddingStore::open(path)?)))
+        }
+        Some("redis") => {
+            let url = cfg
+                .redis_url
+                .clone()
+                .or_else(|| std::env::var("REDIS_URL").ok())
+                .ok_or_else(|| anyhow::anyhow!("embedding_cache.store=redis requires REDIS_URL"))?;
+            Ok(Some(Arc::new(RedisEmbeddingStore::new(
+                &url,
+                cfg.ttl_secs,
+            )?)))
+        }
+        Some(other) => Err(anyhow::anyhow!(
+            "unsupported embedding_cache.store: {}",
+            other
+        )),
+    }
+}
+
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn decode(bytes: &[u8]) -> Option<Vec<f32>> {
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
+fn store_error(e: impl std::fmt::Display) -> KbError {
+    KbError::Database {
+        message: format!("embedding cache store: {}", e),
+        context: None,
+    }
+}
+
+pub struct SledEmbeddingStore {
+    tree: sled::Tree,
+}
+
+impl SledEmbeddingStore {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            tree: db.open_tree("embeddings")?,
+        })
+    }
+}
+
+#[async_trait]
+impl EmbeddingStore for SledEmbeddingStore {
+    async fn get(&self, keys: &[String]) -> Result<Vec<Option<Vec<f32>>>> {
+        keys.iter()
+            .map(|k| {
+                let value = self.tree.get(k).map_err(store_error)?;
+                Ok(value.and_then(|v| decode(&v)))
+            })
+            .collect()
+    }
+
+    async fn put(&self, entries: &[(String, Vec<f32>)]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for (key, vector) in entries {
+            batch.insert(key.as_bytes(), encode(vector));
+        }
+        self.tree.apply_batch(batch).map_err(store_error)
+    }
+}
+
+pub struct RedisEmbeddingStore {
+    client: redis::Client,
+    ttl_secs: Option<u64>,
+}
+
+impl RedisEmbeddingStore {
+    pub fn new(url: &str, ttl_secs: Option<u64>) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            ttl_secs,
+        })
+    }
+
+    fn redis_key(key: &str) -> String {
+        format!("embed:{}", key)
+    }
+}
+
+#[async_trait]
+impl EmbeddingStore for RedisEmbeddingStore {
+    async fn get(&self, keys: &[String]) -> Result<Vec<Option<Vec<f32>>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(store_error)?;
+        let redis_keys: Vec<String> = keys.iter().map(|k| Self::redis_key(k)).collect();
+        let values: Vec<Option<Vec<u8>>> = redis::cmd("MGET")
+            .arg(&redis_keys)
+            .query_async(&mut conn)
+            .await
+            .map_err(store_error)?;
+        Ok(values
+            .into_iter()
+            .map(|v| v.and_then(|b| decode(&b)))
+            .collect())
+    }
+
+    async fn put(&self, entries: &[(String, Vec<f32>)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(store_error)?;
+        let mut pipe = redis::pipe();
+        for (key, vector) in entries {
+            let cmd = pipe
+                .cmd("SET")
+                .arg(Self::redis_key(key))
+                .arg(encode(vector));
+            if let Some(ttl) = self.ttl_secs {
+                cmd.arg("EX").arg(ttl);
+            }
+            cmd.ignore();
+        }
+        pipe.query_async::<_, ()>(&mut conn)
+            .await
+            .map_err(store_error)
+    }
+}
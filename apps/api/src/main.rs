@@ -2,22 +2,23 @@ use axum::{
     debug_handler,
     extract::{Query, State},
     response::sse::{Event, Sse},
+    routing::delete,
     routing::get,
     routing::post,
     Json, Router,
 };
 
 mod auth_routes;
+mod embed_cache;
 use axum::http::{HeaderMap, Request, Response, StatusCode};
 use dotenv::dotenv;
 use futures::{Stream, StreamExt};
 use kb_auth::{jwt::JwtService, rbac::RbacService, session::SessionService};
 use kb_core::{QueryRequest, QueryResponse};
 use kb_error::KbError;
-use kb_llm::{make_providers, ChatProviderConfig, EmbedProviderConfig};
+use kb_llm::{ChatMessage, ChatModel, ChatProviderConfig, EmbedProviderConfig};
 use kb_rag::{DefaultGraphRagEngine, GraphRagEngine, RagEngine};
 use once_cell::sync::Lazy;
-use rig::client::CompletionClient;
 use rig::completion::Prompt;
 use serde::Deserialize;
 use serde_json::json;
@@ -44,6 +45,12 @@ static SESS_TTL_SECS: Lazy<u64> = Lazy::new(|| {
         .and_then(|s| s.parse().ok())
         .unwrap_or(3600)
 });
+/// 部署在会覆盖身份请求头的可信代理之后时设为 true，才采信 `x-tenant-id` / `x-user-id`
+static TRUST_IDENTITY_HEADERS: Lazy<bool> = Lazy::new(|| {
+    std::env::var("TRUST_IDENTITY_HEADERS")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+});
 static SETTINGS: Lazy<tokio::sync::RwLock<HashMap<String, String>>> =
     Lazy::new(|| tokio::sync::RwLock::new(HashMap::new()));
 
@@ -179,6 +186,17 @@ impl SledStore {
 
 static JOB_STORE: Lazy<SledStore> = Lazy::new(|| SledStore::new("data/jobs"));
 
+// 向量模型单条输入对应的分块字节上限，启动时按模型注册表设置
+static MAX_CHUNK_BYTES: once_cell::sync::OnceCell<usize> = once_cell::sync::OnceCell::new();
+
+/// 请求中的 chunk_size 不超过向量模型的单条输入上限
+fn clamp_chunk_size(chunk_size: usize) -> usize {
+    match MAX_CHUNK_BYTES.get() {
+        Some(max) => chunk_size.min(*max),
+        None => chunk_size,
+    }
+}
+
 // 记录每个 document_id 的已索引 chunks 数
 static INDEX_COUNTS: Lazy<tokio::sync::RwLock<HashMap<String, usize>>> =
     Lazy::new(|| tokio::sync::RwLock::new(HashMap::new()));
@@ -330,8 +348,10 @@ struct SessionState {
     query: String,
     top_k: usize,
     filters: Option<serde_json::Value>,
-    chat_history: Vec<rig::message::Message>,
-    pending_tool: Option<PendingTool>,
+    chat_history: Vec<kb_llm::ChatMessage>,
+    /// 等待客户端回传结果的工具调用，全部回传后才能继续对话
+    #[serde(default)]
+    pending_tools: Vec<PendingTool>,
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -344,8 +364,27 @@ struct PendingTool {
 #[derive(Clone)]
 struct AppState {
     rag: Arc<dyn RagEngine>,
+    chat: Arc<dyn ChatModel>,
     graph: Arc<dyn GraphRagEngine>,
     auth_services: auth_routes::AuthServices,
+    /// 按租户与用户累计的 token 用量与费用
+    usage: Arc<kb_llm::UsageLedger>,
+    /// 向量缓存，未启用时为空
+    embed_cache: Option<Arc<kb_llm::CachedEmbedModel>>,
+    /// 语义回答缓存，未启用时为空
+    answer_cache: Option<Arc<kb_rag::CachedRagEngine>>,
+    /// 按租户与模式选择的提示模板
+    prompts: Arc<kb_llm::PromptRegistry>,
+    /// 引用核验，未启用时为空；流式回答结束后据此推送 grounding 事件
+    grounding: Option<Arc<kb_rag::GroundingChecker>>,
+    /// 追问改写，流式查询与会话在检索前显式调用以回显改写结果
+    rewriter: Option<Arc<kb_rag::QueryRewriter>>,
+    /// mode = "auto" 时选择检索方式
+    router: Arc<kb_rag::QueryRouter>,
+    /// 可回答性门控，流式查询在生成前显式判断以推送 insufficient_evidence 事件
+    answerability: Arc<kb_rag::GatedRagEngine>,
+    /// 上下文组装配置（已按模型注册表调整），会话对话据此在预算内装入引用，流式查询据此确定装入的引用
+    context: kb_rag::ContextConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -355,6 +394,42 @@ struct AppConfig {
     embedding_provider: EmbedCfgYaml,
     vector_store: VectorStoreCfg,
     generation: Option<GenCfg>,
+    rerank: Option<kb_rag::RerankerConfig>,
+    llm_retry: Option<kb_llm::RetryConfig>,
+    /// 向量接口分批配置，未配置时按提供商默认限制分批
+    embed_batch: Option<kb_llm::EmbedBatchConfig>,
+    /// 按提供商名或类型配置的 RPM/TPM 限流
+    rate_limits: Option<HashMap<String, kb_llm::RateLimitConfig>>,
+    /// 按模型名配置的价格（每千 token）
+    pricing: Option<kb_llm::PriceTable>,
+    /// 租户用量配额
+    quotas: Option<kb_llm::QuotaConfig>,
+    /// 向量缓存（进程内 LRU + 可选 sled/Redis 持久化）
+    embedding_cache: Option<embed_cache::EmbedCacheCfg>,
+    /// 语义回答缓存，未配置时不启用
+    answer_cache: Option<kb_rag::AnswerCacheConfig>,
+    /// 覆盖或补充内置模型注册表（向量维度、单条输入上限、上下文窗口）
+    models: Option<HashMap<String, kb_llm::ModelInfo>>,
+    /// 检索上下文的 token 预算与低排名引用压缩
+    context: Option<kb_rag::ContextConfig>,
+    /// 父子分块：以小块检索，回答时扩展为所在父块的窗口，未配置时不启用
+    parent_chunks: Option<kb_rag::ParentChunkConfig>,
+    /// 分块的上下文标题（文档标题、章节路径、可选摘要），未配置时不启用
+    contextual_headers: Option<kb_rag::ContextualHeaderConfig>,
+    /// 提示模板及按租户、模式的选择规则
+    prompts: Option<kb_llm::PromptConfig>,
+    /// 回答的引用核验，未配置时不启用
+    grounding: Option<kb_rag::GroundingConfig>,
+    /// 结合对话历史改写追问，未配置时按默认值启用
+    query_rewrite: Option<kb_rag::RewriteConfig>,
+    /// 检索扩展（多查询 RRF / HyDE），请求可通过 expansion 字段指定
+    expansion: Option<kb_rag::ExpansionConfig>,
+    /// 多跳查询（mode = "multihop"）的子问题数量与各自的检索预算
+    multihop: Option<kb_rag::MultiHopConfig>,
+    /// mode = "auto" 时的查询路由
+    router: Option<kb_rag::RouterConfig>,
+    /// 可回答性判断：检索相关度不足时不生成回答，返回最接近的文档
+    answerability: Option<kb_rag::AnswerabilityConfig>,
     extractor: Option<ExtractorCfg>,
 }
 
@@ -370,7 +445,14 @@ struct ChatCfgYaml {
     base_url: Option<String>,
     api_key_env: Option<String>,
     api_url: Option<String>,
+    #[serde(default)]
     model: String,
+    /// kind=ollama 时模型未下载则自动拉取
+    #[serde(default)]
+    auto_pull: bool,
+    /// kind=fallback 时按优先级排列的提供商
+    #[serde(default)]
+    providers: Vec<ChatCfgYaml>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -378,8 +460,16 @@ struct EmbedCfgYaml {
     kind: String,
     base_url: Option<String>,
     api_key_env: Option<String>,
+    #[serde(default)]
     model: String,
     api_url: Option<String>,
+    #[serde(default)]
+    auto_pull: bool,
+    /// kind=fallback 时按优先级排列的提供商，向量维度必须一致
+    #[serde(default)]
+    providers: Vec<EmbedCfgYaml>,
+    /// kind=hashing 的向量维度；kind=fallback 时为声明的向量维度
+    dimension: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -401,6 +491,102 @@ struct ExtractorCfg {
     token_env: Option<String>,
 }
 
+/// llama.cpp server 提供 OpenAI 兼容接口，默认不校验密钥
+const LLAMA_CPP_DEFAULT_BASE_URL: &str = "http://localhost:8080";
+
+fn optional_env(key: Option<&str>) -> String {
+    key.and_then(|k| std::env::var(k).ok()).unwrap_or_default()
+}
+
+fn chat_provider_config(c: &ChatCfgYaml) -> anyhow::Result<ChatProviderConfig> {
+    Ok(match c.kind.as_str() {
+        "openai_compat" => ChatProviderConfig::OpenAiCompat {
+            base_url: c
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "https://api.openai.com".into()),
+            api_key: read_env(c.api_key_env.as_deref().unwrap_or("OPENAI_API_KEY"))?,
+            model: c.model.clone(),
+        },
+        "anthropic" => ChatProviderConfig::Anthropic {
+            api_url: c.api_url.clone(),
+            api_key: read_env(c.api_key_env.as_deref().unwrap_or("ANTHROPIC_API_KEY"))?,
+            model: c.model.clone(),
+        },
+        "ollama" => ChatProviderConfig::Ollama {
+            base_url: c.base_url.clone(),
+            model: c.model.clone(),
+            auto_pull: c.auto_pull,
+        },
+        "llama_cpp" => ChatProviderConfig::OpenAiCompat {
+            base_url: c
+                .base_url
+                .clone()
+                .unwrap_or_else(|| LLAMA_CPP_DEFAULT_BASE_URL.into()),
+            api_key: optional_env(c.api_key_env.as_deref()),
+            model: c.model.clone(),
+        },
+        "fallback" => ChatProviderConfig::Fallback {
+            providers: c
+                .providers
+                .iter()
+                .map(chat_provider_config)
+                .collect::<anyhow::Result<_>>()?,
+        },
+        other => anyhow::bail!("unsupported chat provider kind={}", other),
+    })
+}
+
+fn embed_provider_config(c: &EmbedCfgYaml) -> anyhow::Result<EmbedProviderConfig> {
+    Ok(match c.kind.as_str() {
+        "openai_compat" => EmbedProviderConfig::OpenAiCompat {
+            base_url: c
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "https://api.openai.com".into()),
+            api_key: read_env(c.api_key_env.as_deref().unwrap_or("OPENAI_API_KEY"))?,
+            model: c.model.clone(),
+        },
+        "qwen" => EmbedProviderConfig::QwenDashScope {
+            api_url: c.api_url.clone(),
+            api_key: read_env(c.api_key_env.as_deref().unwrap_or("DASHSCOPE_API_KEY"))?,
+            model: c.model.clone(),
+        },
+        "deepseek" => EmbedProviderConfig::DeepSeek {
+            base_url: c.base_url.clone(),
+            api_key: read_env(c.api_key_env.as_deref().unwrap_or("DEEPSEEK_API_KEY"))?,
+            model: c.model.clone(),
+        },
+        "ollama" => EmbedProviderConfig::Ollama {
+            base_url: c.base_url.clone(),
+            model: c.model.clone(),
+            auto_pull: c.auto_pull,
+        },
+        "llama_cpp" => EmbedProviderConfig::OpenAiCompat {
+            base_url: c
+                .base_url
+                .clone()
+                .unwrap_or_else(|| LLAMA_CPP_DEFAULT_BASE_URL.into()),
+            api_key: optional_env(c.api_key_env.as_deref()),
+            model: c.model.clone(),
+        },
+        "hashing" => EmbedProviderConfig::Hashing {
+            dimension: c
+                .dimension
+                .unwrap_or(kb_llm::hashing::HASHING_DEFAULT_DIMENSION),
+        },
+        "fallback" => EmbedProviderConfig::Fallback {
+            providers: c
+                .providers
+                .iter()
+                .map(embed_provider_config)
+                .collect::<anyhow::Result<_>>()?,
+            dimension: c.dimension,
+        },
+        other => anyhow::bail!("unsupported embedding provider kind={}", other),
+    })
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     init_tracing();
@@ -433,71 +619,78 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Build providers
-    let chat_cfg = match cfg.chat_provider.kind.as_str() {
-        "openai_compat" => ChatProviderConfig::OpenAiCompat {
-            base_url: cfg
-                .chat_provider
-                .base_url
-                .unwrap_or_else(|| "https://api.openai.com".into()),
-            api_key: read_env(
-                &cfg.chat_provider
-                    .api_key_env
-                    .unwrap_or_else(|| "OPENAI_API_KEY".into()),
-            )?,
-            model: cfg.chat_provider.model,
-        },
-        "anthropic" => ChatProviderConfig::Anthropic {
-            api_url: cfg.chat_provider.api_url,
-            api_key: read_env(
-                &cfg.chat_provider
-                    .api_key_env
-                    .unwrap_or_else(|| "ANTHROPIC_API_KEY".into()),
-            )?,
-            model: cfg.chat_provider.model,
-        },
-        other => anyhow::bail!("unsupported chat provider kind={}", other),
-    };
+    let chat_cfg = chat_provider_config(&cfg.chat_provider)?;
+    let embed_cfg = embed_provider_config(&cfg.embedding_provider)?;
+    // 向量缓存只保存主提供商生成的向量，故障转移期间的结果不入缓存
+    let embed_name = embed_cfg.primary_provider_name();
+
+    // 模型元数据：决定集合维度、分块上限与上下文预算
+    let registry =
+        kb_llm::ModelRegistry::builtin().with_overrides(cfg.models.clone().unwrap_or_default());
+    let chat_info = chat_cfg.model_info(&registry);
+    let embed_info = embed_cfg.model_info(&registry);
+    if chat_info.is_none() || embed_info.is_none() {
+        tracing::warn!(
+            chat = chat_info.is_some(),
+            embed = embed_info.is_some(),
+            "模型注册表中缺少模型元数据，可在 models 中补充"
+        );
+    }
+    if let Some(max_input) = embed_info.as_ref().and_then(|i| i.max_input_tokens) {
+        let _ = MAX_CHUNK_BYTES.set(kb_llm::tokens::bytes_for_tokens(max_input));
+    }
+    let engine_config = kb_rag::RagEngineConfig {
+        context: cfg.context.clone().unwrap_or_default(),
+        parent: cfg.parent_chunks.clone().unwrap_or_default(),
+        headers: cfg.contextual_headers.clone().unwrap_or_default(),
+        ..Default::default()
+    }
+    .with_models(chat_info.as_ref(), embed_info.as_ref());
 
-    let embed_cfg = match cfg.embedding_provider.kind.as_str() {
-        "openai_compat" => EmbedProviderConfig::OpenAiCompat {
-            base_url: cfg
-                .embedding_provider
-                .base_url
-                .clone()
-                .unwrap_or_else(|| "https://api.openai.com".into()),
-            api_key: read_env(
-                &cfg.embedding_provider
-                    .api_key_env
-                    .clone()
-                    .unwrap_or_else(|| "OPENAI_API_KEY".into()),
-            )?,
-            model: cfg.embedding_provider.model.clone(),
-        },
-        "qwen" => EmbedProviderConfig::QwenDashScope {
-            api_url: cfg.embedding_provider.api_url.clone(),
-            api_key: read_env(
-                &cfg.embedding_provider
-                    .api_key_env
-                    .clone()
-                    .unwrap_or_else(|| "DASHSCOPE_API_KEY".into()),
-            )?,
-            model: cfg.embedding_provider.model.clone(),
-        },
-        "deepseek" => EmbedProviderConfig::DeepSeek {
-            base_url: cfg.embedding_provider.base_url.clone(),
-            api_key: read_env(
-                &cfg.embedding_provider
-                    .api_key_env
-                    .clone()
-                    .unwrap_or_else(|| "DEEPSEEK_API_KEY".into()),
-            )?,
-            model: cfg.embedding_provider.model.clone(),
-        },
-        other => anyhow::bail!("unsupported embedding provider kind={}", other),
+    let provider_opts = kb_llm::ProviderOptions {
+        retry: cfg.llm_retry.clone(),
+        rate_limits: cfg.rate_limits.clone().unwrap_or_default(),
+        embed_batch: cfg.embed_batch.clone(),
     };
-
-    let providers =
-        make_providers(chat_cfg, embed_cfg).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    let providers = kb_llm::Providers {
+        chat: kb_llm::make_chat_model_with(chat_cfg, &provider_opts)
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?,
+        embed: kb_llm::make_embed_model_with(embed_cfg, &provider_opts)
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?,
+    };
+    // 启动校验（本地模型是否可用、故障转移链中各向量模型维度一致）
+    providers
+        .chat
+        .verify()
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    providers
+        .embed
+        .verify()
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    let mut embed_model: Arc<dyn kb_llm::EmbedModel> = Arc::from(providers.embed);
+    // 向量缓存在分批之外，仅未命中的文本进入分批与限流
+    let cache_cfg = cfg.embedding_cache.clone().unwrap_or_default();
+    let embed_cache = if cache_cfg.enabled {
+        let mut cache =
+            kb_llm::CachedEmbedModel::new(embed_model.clone(), &embed_name, cache_cfg.capacity);
+        if let Some(store) = embed_cache::build_store(&cache_cfg)? {
+            cache = cache.with_store(store);
+        }
+        let cache = Arc::new(cache);
+        embed_model = cache.clone();
+        info!(
+            model = %embed_name,
+            capacity = cache_cfg.capacity,
+            store = cache_cfg.store.as_deref().unwrap_or("memory"),
+            "向量缓存已启用"
+        );
+        Some(cache)
+    } else {
+        None
+    };
+    let chat_model: Arc<dyn ChatModel> = Arc::from(providers.chat);
 
     // 选择向量检索实现：qdrant -> Rig+Qdrant；memory/rig_mem -> Rig 内存实现；否则为简易多提供商内存实现
     let rag: Arc<dyn RagEngine> = match cfg.vector_store.kind.as_str() {
@@ -510,33 +703,160 @@ async fn main() -> anyhow::Result<()> {
                 .vector_store
                 .collection
                 .unwrap_or_else(|| "kb_chunks".into());
-            let oai_embed_model = std::env::var("OPENAI_EMBED_MODEL")
-                .unwrap_or_else(|_| "text-embedding-3-small".into());
-            let engine = kb_rag::RigQdrantRagEngine::new(
+            let engine = kb_rag::RigQdrantRagEngine::with_models(
                 url,
                 coll,
-                oai_embed_model,
-                Arc::from(providers.chat),
+                chat_model.clone(),
+                embed_model.clone(),
+                Some(engine_config.clone()),
             )
             .await?;
-            info!("RigQdrantRagEngine:qdrant_engine");
+            info!(
+                "RigQdrantRagEngine:qdrant_engine embed_model={}",
+                embed_name
+            );
             Arc::new(engine)
         }
         "memory" | "rig_mem" => {
-            let oai_embed_model = std::env::var("OPENAI_EMBED_MODEL")
-                .unwrap_or_else(|_| cfg.embedding_provider.model.clone());
-            info!("RigInMemoryRagEngine:oai_embed_model={}", oai_embed_model);
-            Arc::new(kb_rag::RigInMemoryRagEngine::new(
-                oai_embed_model,
-                Arc::from(providers.chat),
+            info!("RigInMemoryRagEngine:embed_model={}", embed_name);
+            Arc::new(kb_rag::RigInMemoryRagEngine::with_models(
+                chat_model.clone(),
+                embed_model.clone(),
+                Some(engine_config.clone()),
             ))
         }
-        _ => Arc::new(kb_rag::MultiProviderRagEngine::new(
-            Arc::from(providers.chat),
-            Arc::from(providers.embed),
+        _ => Arc::new(kb_rag::MultiProviderRagEngine::with_config(
+            chat_model.clone(),
+            embed_model.clone(),
+            Some(engine_config.clone()),
         )),
     };
 
+    // 按 mode 分派：lexical 走词汇检索，hybrid 融合向量与词汇检索；词汇索引在内存中，仅覆盖本进程索引的文档，
+    // 重启后为空时词汇检索退回向量检索
+    let lexical = kb_rag::LexicalRagEngine::new(
+        kb_rag::BaseRagEngine::new(
+            chat_model.clone(),
+            embed_model.clone(),
+            engine_config.clone(),
+        ),
+        kb_rag::LexicalConfig::default(),
+    );
+    let rag: Arc<dyn RagEngine> = Arc::new(kb_rag::RoutedRagEngine::new(rag, Arc::new(lexical)));
+
+    // 检索扩展位于重排之内：扩展召回的候选统一按原问题重排
+    let expansion_cfg = cfg.expansion.clone().unwrap_or_default();
+    info!(default = ?expansion_cfg.default, paraphrases = expansion_cfg.paraphrases, "ExpandedRagEngine");
+    let rag: Arc<dyn RagEngine> = Arc::new(kb_rag::ExpandedRagEngine::new(
+        rag,
+        chat_model.clone(),
+        expansion_cfg,
+    ));
+
+    // 按配置为检索引擎挂载重排链：超量召回候选后重排，再交由原引擎生成回答
+    let mut rerank_by_default = false;
+    let rag: Arc<dyn RagEngine> = match cfg.rerank.as_ref() {
+        Some(rerank_cfg) => {
+            match kb_rag::RerankerFactory::from_config(rerank_cfg, Some(embed_model.clone()))
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            {
+                Some(reranker) => {
+                    info!(
+                        enabled = rerank_cfg.enabled,
+                        candidate_multiplier = rerank_cfg.candidate_multiplier,
+                        "RerankingRagEngine:{}",
+                        reranker.name()
+                    );
+                    rerank_by_default = rerank_cfg.enabled;
+                    Arc::new(kb_rag::RerankingRagEngine::new(
+                        rag,
+                        Arc::from(reranker),
+                        rerank_cfg.clone(),
+                    ))
+                }
+                None => rag,
+            }
+        }
+        None => rag,
+    };
+
+    // 可回答性门控位于重排之外，按重排后的得分判断；相关度不足时跳过生成
+    let answerability = Arc::new(
+        kb_rag::GatedRagEngine::new(
+            rag,
+            kb_rag::AnswerabilityGate::new(
+                cfg.answerability.clone().unwrap_or_default(),
+                &engine_config,
+            ),
+        )
+        .with_rerank_default(rerank_by_default),
+    );
+    let rag: Arc<dyn RagEngine> = answerability.clone();
+
+    // 多跳查询位于引用核验之内，合成的回答同样附带核验报告；合并后的引用同样经过可回答性门控
+    let rag: Arc<dyn RagEngine> = Arc::new(
+        kb_rag::MultiHopRagEngine::new(
+            rag,
+            chat_model.clone(),
+            cfg.multihop.clone().unwrap_or_default(),
+        )
+        .with_gate(answerability.clone()),
+    );
+
+    // 引用核验：解析回答中的引用标记，可选由对话模型判断引用内容是否支持各句
+    let grounding = match cfg.grounding.clone() {
+        Some(grounding_cfg) if grounding_cfg.enabled => {
+            info!(entailment = grounding_cfg.entailment, "GroundedRagEngine");
+            Some(Arc::new(
+                kb_rag::GroundingChecker::new(grounding_cfg).with_chat_model(chat_model.clone()),
+            ))
+        }
+        _ => None,
+    };
+    let rag: Arc<dyn RagEngine> = match &grounding {
+        Some(checker) => Arc::new(kb_rag::GroundedRagEngine::new(rag, checker.clone())),
+        None => rag,
+    };
+
+    // 语义回答缓存位于最外层，命中时跳过检索、重排与生成
+    let (rag, answer_cache): (Arc<dyn RagEngine>, _) = match cfg.answer_cache.clone() {
+        Some(cache_cfg) if cache_cfg.enabled => {
+            info!(
+                threshold = cache_cfg.similarity_threshold,
+                ttl_secs = cache_cfg.ttl_secs,
+                "CachedRagEngine"
+            );
+            let cache = Arc::new(kb_rag::CachedRagEngine::new(
+                rag,
+                embed_model.clone(),
+                cache_cfg,
+            ));
+            (cache.clone(), Some(cache))
+        }
+        _ => (rag, None),
+    };
+
+    // 追问改写位于回答缓存外层：先把带历史的追问改写为独立问题，再查缓存与检索
+    let rewrite_cfg = cfg.query_rewrite.clone().unwrap_or_default();
+    let rewriter = rewrite_cfg.enabled.then(|| {
+        info!(max_turns = rewrite_cfg.max_turns, "ConversationalRagEngine");
+        Arc::new(kb_rag::QueryRewriter::new(
+            chat_model.clone(),
+            rewrite_cfg.clone(),
+        ))
+    });
+    let rag: Arc<dyn RagEngine> = match &rewriter {
+        Some(rewriter) => Arc::new(kb_rag::ConversationalRagEngine::new(rag, rewriter.clone())),
+        None => rag,
+    };
+
+    // 图检索目前为占位实现，自动路由不选择 graph
+    let router = Arc::new(
+        kb_rag::QueryRouter::new(cfg.router.clone().unwrap_or_default())
+            .with_chat_model(chat_model.clone())
+            .with_graph(false),
+    );
+
     // 初始化认证服务
     let jwt_secret =
         std::env::var("JWT_SECRET").unwrap_or_else(|_| "default_secret_key".to_string());
@@ -560,8 +880,23 @@ async fn main() -> anyhow::Result<()> {
 
     let state = AppState {
         rag,
+        chat: chat_model,
         graph: Arc::new(DefaultGraphRagEngine),
         auth_services,
+        usage: Arc::new(kb_llm::UsageLedger::new(
+            cfg.pricing.clone().unwrap_or_default(),
+            cfg.quotas.clone().unwrap_or_default(),
+        )),
+        embed_cache,
+        answer_cache,
+        prompts: Arc::new(kb_llm::PromptRegistry::new(
+            cfg.prompts.clone().unwrap_or_default(),
+        )),
+        grounding,
+        rewriter,
+        router,
+        answerability,
+        context: engine_config.context.clone(),
     };
 
     // Admin routes use AsyncRequireAuthorizationLayer with custom authorizer
@@ -578,6 +913,11 @@ async fn main() -> anyhow::Result<()> {
             "/api/v1/admin",
             Router::new()
                 .route("/settings", get(admin_get_settings).put(admin_put_settings))
+                .route(
+                    "/settings/prompts",
+                    get(admin_get_prompts).put(admin_put_prompts),
+                )
+                .route("/settings/prompts/:name", delete(admin_delete_prompt))
                 .route("/upload", post(admin_upload))
                 .route("/jobs", get(admin_jobs_list).post(admin_jobs_create))
                 .route("/jobs/:id", get(admin_jobs_get))
@@ -585,6 +925,13 @@ async fn main() -> anyhow::Result<()> {
                 .route("/index/status/:document_id", get(admin_index_status))
                 .route("/extract/health", get(admin_extract_health))
                 .route("/extract/test", post(admin_extract_test))
+                .route("/usage", get(admin_usage).delete(admin_usage_reset))
+                .route("/embedding-cache", get(admin_embedding_cache))
+                .route("/eval/retrieval", post(admin_eval_retrieval))
+                .route(
+                    "/answer-cache",
+                    get(admin_answer_cache).delete(admin_answer_cache_invalidate),
+                )
                 .layer(AsyncRequireAuthorizationLayer::new(
                     AdminAuthorizer::default(),
                 )),
@@ -612,7 +959,11 @@ async fn main() -> anyhow::Result<()> {
     // 后台启动 Job Runner（抽取 + 切分 + 索引）
     {
         let rag_for_jobs = state.rag.clone();
-        tokio::spawn(async move { job_runner(rag_for_jobs).await });
+        // 后台索引以低优先级调用模型，避免挤占交互式查询的限流额度
+        tokio::spawn(kb_llm::with_priority(
+            kb_llm::Priority::Background,
+            job_runner(rag_for_jobs),
+        ));
     }
 
     let addr: SocketAddr = format!("{}:{}", cfg.server.host, cfg.server.port)
@@ -636,9 +987,18 @@ fn init_tracing() {
 
 async fn query(
     State(state): State<AppState>,
-    Json(req): Json<QueryRequest>,
-) -> Json<QueryResponse> {
+    headers: HeaderMap,
+    Json(mut req): Json<QueryRequest>,
+) -> Result<Json<QueryResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let who = UsageIdentity::from_request(&state.auth_services.jwt_service, &headers);
+    if let Err(e) = state.usage.check_quota(&who.tenant) {
+        let status =
+            StatusCode::from_u16(e.to_http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return Err((status, Json(json!({"error": e.to_string()}))));
+    }
+    route_auto_mode(&state, &who, &mut req).await;
     let mode = req.mode.clone().unwrap_or_else(|| "rag".into());
+    let prompt = state.prompts.resolve(&who.tenant, &mode);
     let mut use_rig_agent = false;
     if mode == "rag" {
         if let Ok(cfg) = load_config() {
@@ -650,107 +1010,199 @@ async fn query(
         }
     }
 
-    let resp = if use_rig_agent {
-        // Rig Agent 非流式：动态上下文 + 可选工具
-        use qdrant_client::Qdrant;
-        use rig::{
-            client::{CompletionClient, EmbeddingsClient, ProviderClient},
-            providers,
-        };
-        use rig_qdrant::QdrantVectorStore;
-
-        let cfg = load_config().unwrap();
-        let url = cfg
-            .vector_store
-            .url
-            .unwrap_or_else(|| "http://localhost:6334".into());
-        let coll = cfg
-            .vector_store
-            .collection
-            .unwrap_or_else(|| "kb_chunks".into());
-        let chat_model = cfg
-            .generation
-            .as_ref()
-            .and_then(|g| g.model.clone())
-            .unwrap_or(cfg.chat_provider.model);
-        let embed_model = cfg.embedding_provider.model;
-        info!(
-            "RigQdrantRagEngine:chat_model={}, embed_model={}",
-            chat_model, embed_model
-        );
+    let start = std::time::Instant::now();
+    let metered = kb_llm::with_prompt(prompt.clone(), async {
+        if use_rig_agent {
+            // Rig Agent 非流式：动态上下文 + 可选工具
+            use qdrant_client::Qdrant;
+            use rig::{
+                client::{CompletionClient, EmbeddingsClient, ProviderClient},
+                providers,
+            };
+            use rig_qdrant::QdrantVectorStore;
 
-        let client = providers::openai::Client::from_env();
-        let embed = client.embedding_model(&embed_model);
-        let q = Qdrant::from_url(&url).build().unwrap();
-        let mut qp = qdrant_client::qdrant::QueryPointsBuilder::new(&coll).with_payload(true);
-        if let Some(flt) = build_qdrant_filter(&req.filters) {
-            qp = qp.filter(flt);
-        }
-        let index: QdrantVectorStore<_> = QdrantVectorStore::new(q, embed.clone(), qp.build());
-
-        // 工具示例：TimeNow（返回当前时间）
-        #[derive(serde::Deserialize, serde::Serialize)]
-        struct TimeNow;
-        #[derive(Debug)]
-        struct ToolError;
-        impl std::fmt::Display for ToolError {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                write!(f, "tool error")
+            let cfg = load_config().unwrap();
+            let url = cfg
+                .vector_store
+                .url
+                .unwrap_or_else(|| "http://localhost:6334".into());
+            let coll = cfg
+                .vector_store
+                .collection
+                .unwrap_or_else(|| "kb_chunks".into());
+            let chat_model = cfg
+                .generation
+                .as_ref()
+                .and_then(|g| g.model.clone())
+                .unwrap_or(cfg.chat_provider.model);
+            let embed_model = cfg.embedding_provider.model;
+            info!(
+                "RigQdrantRagEngine:chat_model={}, embed_model={}",
+                chat_model, embed_model
+            );
+
+            let client = providers::openai::Client::from_env();
+            let embed = client.embedding_model(&embed_model);
+            let q = Qdrant::from_url(&url).build().unwrap();
+            let mut qp = qdrant_client::qdrant::QueryPointsBuilder::new(&coll).with_payload(true);
+            if let Some(flt) = build_qdrant_filter(&req.filters) {
+                qp = qp.filter(flt);
             }
-        }
-        impl std::error::Error for ToolError {}
-        impl rig::tool::Tool for TimeNow {
-            const NAME: &'static str = "time_now";
-            type Error = ToolError;
-            type Args = serde_json::Value; // no args
-            type Output = String;
-            async fn definition(&self, _prompt: String) -> rig::completion::ToolDefinition {
-                rig::completion::ToolDefinition {
-                    name: "time_now".into(),
-                    description: "Return current server time in RFC3339".into(),
-                    parameters: json!({"type":"object","properties":{}}),
+            let index: QdrantVectorStore<_> = QdrantVectorStore::new(q, embed.clone(), qp.build());
+
+            // 工具示例：TimeNow（返回当前时间）
+            #[derive(serde::Deserialize, serde::Serialize)]
+            struct TimeNow;
+            #[derive(Debug)]
+            struct ToolError;
+            impl std::fmt::Display for ToolError {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    write!(f, "tool error")
                 }
             }
-            async fn call(&self, _args: Self::Args) -> Result<Self::Output, Self::Error> {
-                Ok(chrono::Utc::now().to_rfc3339())
+            impl std::error::Error for ToolError {}
+            impl rig::tool::Tool for TimeNow {
+                const NAME: &'static str = "time_now";
+                type Error = ToolError;
+                type Args = serde_json::Value; // no args
+                type Output = String;
+                async fn definition(&self, _prompt: String) -> rig::completion::ToolDefinition {
+                    rig::completion::ToolDefinition {
+                        name: "time_now".into(),
+                        description: "Return current server time in RFC3339".into(),
+                        parameters: json!({"type":"object","properties":{}}),
+                    }
+                }
+                async fn call(&self, _args: Self::Args) -> Result<Self::Output, Self::Error> {
+                    Ok(chrono::Utc::now().to_rfc3339())
+                }
             }
-        }
 
-        let k = req.top_k.unwrap_or(5) as usize;
-        let agent = client
-            .agent(&chat_model)
-            .preamble("You are a helpful assistant. Answer using only the provided context.")
-            .dynamic_context(k, index)
-            .tool(TimeNow)
-            .build();
-
-        match agent.prompt(&req.query).await {
-            Ok(answer) => Ok(QueryResponse {
-                answer,
-                citations: vec![],
-                contexts: vec![],
-                mode: mode.clone(),
-                latency_ms: 0,
-            }),
-            Err(e) => Err(KbError::Internal {
-                message: e.to_string(),
-                details: None,
-            }),
-        }
-    } else {
-        match mode.as_str() {
-            "graph" => state.graph.query(req).await,
-            "hybrid" => state.rag.query(req).await,
-            "lexical" | _ => state.rag.query(req).await,
+            let k = req.top_k.unwrap_or(5) as usize;
+            let agent = client
+                .agent(&chat_model)
+                .preamble(&prompt.render_system())
+                .dynamic_context(k, index)
+                .tool(TimeNow)
+                .build();
+
+            match agent.prompt(&req.query).await {
+                Ok(answer) => Ok(QueryResponse {
+                    answer,
+                    citations: vec![],
+                    contexts: vec![],
+                    mode: mode.clone(),
+                    latency_ms: 0,
+                    ..Default::default()
+                }),
+                Err(e) => Err(KbError::Internal {
+                    message: e.to_string(),
+                    details: None,
+                }),
+            }
+        } else {
+            // lexical / hybrid / multihop 由检索引擎按 mode 分派
+            match mode.as_str() {
+                "graph" => state.graph.query(req).await,
+                _ => state.rag.query(req).await,
+            }
         }
-    };
-    Json(resp.unwrap_or_else(|e| QueryResponse {
+    });
+    let (resp, usage) = with_metering(&state.usage, &who, metered).await;
+    // 查询日志：记录所用提示模板的版本，便于回溯回答质量变化
+    info!(
+        tenant = %who.tenant,
+        user = %who.user,
+        mode = %mode,
+        prompt = %prompt.name,
+        prompt_version = prompt.version,
+        latency_ms = start.elapsed().as_millis() as u64,
+        ok = resp.is_ok(),
+        "query"
+    );
+    let mut resp = resp.unwrap_or_else(|e| QueryResponse {
         answer: format!("error: {e}"),
         citations: vec![],
         contexts: vec![],
         mode: mode.to_string(),
         latency_ms: 0,
-    }))
+        ..Default::default()
+    });
+    resp.usage = Some(usage);
+    Ok(Json(resp))
+}
+
+/// `mode` 为 auto 时按问题特征选择检索方式并写回请求
+async fn route_auto_mode(state: &AppState, who: &UsageIdentity, req: &mut QueryRequest) {
+    if req.mode.as_deref() != Some(kb_rag::AUTO_MODE) {
+        return;
+    }
+    let decision = state.router.route(&req.query).await;
+    // 路由日志：记录问题与选择，便于离线评估路由质量
+    info!(
+        tenant = %who.tenant,
+        query = %req.query,
+        route = decision.route.as_str(),
+        confidence = decision.confidence,
+        classifier = %decision.classifier,
+        reason = %decision.reason,
+        "query routed"
+    );
+    req.mode = Some(decision.route.as_str().to_string());
+}
+
+/// 用量归属，同时决定配额、提示模板与回答缓存的租户作用域
+///
+/// 优先取自已验证的访问令牌（tenant_id / sub）；仅在 `TRUST_IDENTITY_HEADERS=true`
+/// 时采信 `x-tenant-id` / `x-user-id` 请求头，请求体中的过滤条件不参与身份判断。
+struct UsageIdentity {
+    tenant: String,
+    user: String,
+}
+
+impl UsageIdentity {
+    fn from_request(jwt: &JwtService, headers: &HeaderMap) -> Self {
+        let claims = headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| JwtService::extract_token_from_header(v).ok())
+            .and_then(|token| jwt.verify_access_token(token).ok());
+        if let Some(claims) = claims {
+            return Self {
+                tenant: claims.tenant_id.unwrap_or_else(|| "default".into()),
+                user: claims.sub,
+            };
+        }
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty() && *TRUST_IDENTITY_HEADERS)
+                .map(str::to_string)
+        };
+        Self {
+            tenant: header("x-tenant-id").unwrap_or_else(|| "default".into()),
+            user: header("x-user-id").unwrap_or_else(|| "anonymous".into()),
+        }
+    }
+}
+
+/// 在用量归集与租户作用域内执行请求，按租户与用户计入用量并返回本次请求的用量与费用
+async fn with_metering<F: std::future::Future>(
+    ledger: &kb_llm::UsageLedger,
+    who: &UsageIdentity,
+    f: F,
+) -> (F::Output, kb_core::Usage) {
+    let (output, report) = kb_llm::collect_usage(kb_rag::with_tenant(who.tenant.clone(), f)).await;
+    let cost = ledger.record(&who.tenant, &who.user, &report);
+    let total = report.total();
+    let usage = kb_core::Usage {
+        prompt_tokens: total.prompt_tokens,
+        completion_tokens: total.completion_tokens,
+        embedding_tokens: total.embedding_tokens,
+        cost,
+    };
+    (output, usage)
 }
 
 #[derive(serde::Serialize)]
@@ -764,7 +1216,8 @@ struct QueryTraceResp {
 }
 
 async fn query_trace(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<QueryRequest>,
 ) -> Json<serde_json::Value> {
     use qdrant_client::Qdrant;
@@ -799,9 +1252,12 @@ async fn query_trace(
         qp = qp.filter(flt);
     }
     let index: QdrantVectorStore<_> = QdrantVectorStore::new(q, embed.clone(), qp.build());
+    let who = UsageIdentity::from_request(&state.auth_services.jwt_service, &headers);
+    let mode = req.mode.clone().unwrap_or_else(|| "rag".into());
+    let prompt = state.prompts.resolve(&who.tenant, &mode);
     let agent = client
         .agent(&chat_model)
-        .preamble("You are a helpful assistant. Answer using only the provided context.")
+        .preamble(&prompt.render_system())
         .dynamic_context(req.top_k.unwrap_or(5) as usize, index)
         .build();
 
@@ -909,10 +1365,13 @@ async fn index_text(
     State(state): State<AppState>,
     Json(req): Json<IndexTextReq>,
 ) -> Json<serde_json::Value> {
-    let _ = state
-        .rag
-        .add_document_text(&req.document_id, &req.text, req.page)
-        .await;
+    let _ = kb_llm::with_priority(
+        kb_llm::Priority::Background,
+        state
+            .rag
+            .add_document_text(&req.document_id, &req.text, req.page),
+    )
+    .await;
     inc_index_count(&req.document_id, 1).await;
     Json(serde_json::json!({"status":"ok"}))
 }
@@ -940,10 +1399,13 @@ async fn index_text_with_meta(
         created_at: req.created_at,
         custom_fields: None,
     };
-    let _ = state
-        .rag
-        .add_document_text_with_meta(&req.document_id, &req.text, req.page, Some(meta))
-        .await;
+    let _ = kb_llm::with_priority(
+        kb_llm::Priority::Background,
+        state
+            .rag
+            .add_document_text_with_meta(&req.document_id, &req.text, req.page, Some(meta)),
+    )
+    .await;
     inc_index_count(&req.document_id, 1).await;
     Json(serde_json::json!({"status":"ok"}))
 }
@@ -1076,6 +1538,7 @@ async fn chunk_and_index(
     chunk_size: usize,
     overlap: usize,
 ) -> usize {
+    let chunk_size = clamp_chunk_size(chunk_size);
     let mut chunks = Vec::<String>::new();
     let mut current = String::new();
     for word in text.split_whitespace() {
@@ -1105,7 +1568,11 @@ async fn chunk_and_index(
     }
     let mut n = 0usize;
     for c in chunks.into_iter() {
-        let _ = engine.add_document_text(document_id, &c, None).await;
+        let _ = kb_llm::with_priority(
+            kb_llm::Priority::Background,
+            engine.add_document_text(document_id, &c, None),
+        )
+        .await;
         n += 1;
     }
     n
@@ -1122,160 +1589,173 @@ async fn index_url(
     State(state): State<AppState>,
     Json(req): Json<IndexUrlReq>,
 ) -> Json<serde_json::Value> {
-    let _ = kb_rag::index_web_url(state.rag.clone(), &req.url, &req.document_id).await;
+    let _ = kb_llm::with_priority(
+        kb_llm::Priority::Background,
+        kb_rag::index_web_url(state.rag.clone(), &req.url, &req.document_id),
+    )
+    .await;
     inc_index_count(&req.document_id, 1).await;
     Json(serde_json::json!({"status":"ok"}))
 }
 
 async fn query_stream(
-    State(_state): State<AppState>,
-    Json(req): Json<QueryRequest>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut req): Json<QueryRequest>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    use qdrant_client::Qdrant;
-    use rig::{
-        agent::{MultiTurnStreamItem, PromptHook},
-        client::{CompletionClient, EmbeddingsClient, ProviderClient},
-        completion::CompletionModel,
-        message::Message,
-        providers,
-        streaming::StreamingPrompt,
-    };
-    use rig_qdrant::QdrantVectorStore;
-
-    // Only support Qdrant backed streaming in this iteration
-    let k = req.top_k.unwrap_or(5) as u64;
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, Infallible>>(16);
+    let who = UsageIdentity::from_request(&state.auth_services.jwt_service, &headers);
+    route_auto_mode(&state, &who, &mut req).await;
+    let mode = req.mode.clone().unwrap_or_else(|| "rag".into());
+    let prompt = state.prompts.resolve(&who.tenant, &mode);
 
-    let chat_model = std::env::var("OPENAI_CHAT_MODEL").unwrap_or_else(|_| "gpt-4o".into());
-    let embed_model =
-        std::env::var("OPENAI_EMBED_MODEL").unwrap_or_else(|_| "text-embedding-3-small".into());
-
-    // Load app config again (cheap) to read vector store details
-    let cfg: AppConfig = load_config().expect("config");
-    let url = cfg
-        .vector_store
-        .url
-        .unwrap_or_else(|| "http://localhost:6334".into());
-    let coll = cfg
-        .vector_store
-        .collection
-        .unwrap_or_else(|| "kb_chunks".into());
+    // 先检索并推送引用，再通过检索引擎配置的 kb-llm 对话模型流式生成回答
+    let ledger = state.usage.clone();
+    let usage_tx = tx.clone();
+    tokio::spawn(async move {
+        if let Err(e) = ledger.check_quota(&who.tenant) {
+            let _ = usage_tx
+                .send(Ok(Event::default().event("error").data(e.to_string())))
+                .await;
+            return;
+        }
+        // 多跳查询需按子问题分组合成回答，在检索前拒绝以免白白拆分与检索
+        if mode == kb_rag::MULTIHOP_MODE {
+            let _ = usage_tx
+                .send(Ok(Event::default()
+                    .event("error")
+                    .data("多跳查询不支持流式输出，请使用 /api/v1/query")))
+                .await;
+            return;
+        }
+        let metered = kb_llm::with_prompt(prompt, async move {
+            let send_error = |e: String| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(Ok(Event::default().event("error").data(e))).await;
+                }
+            };
 
-    #[derive(Clone)]
-    struct SseHook {
-        tx: tokio::sync::mpsc::Sender<Result<Event, Infallible>>,
-    }
-    impl<M> PromptHook<M> for SseHook
-    where
-        M: rig::completion::CompletionModel,
-    {
-        fn on_tool_call(
-            &self,
-            tool_name: &str,
-            args: &str,
-        ) -> impl std::future::Future<Output = ()> + Send {
-            let tx = self.tx.clone();
-            let data = format!("{} {}", tool_name, args);
-            async move {
+            let (req, rewritten) = match &state.rewriter {
+                Some(rewriter) => rewriter.condense(&req).await,
+                None => (req, None),
+            };
+            if let Some(q) = rewritten {
                 let _ = tx
-                    .send(Ok(Event::default().event("tool_call").data(data)))
+                    .send(Ok(Event::default().event("rewritten_query").data(q)))
                     .await;
             }
-        }
-        fn on_tool_result(
-            &self,
-            tool_name: &str,
-            _args: &str,
-            result: &str,
-        ) -> impl std::future::Future<Output = ()> + Send {
-            let tx = self.tx.clone();
-            let data = format!("{} {}", tool_name, result);
-            async move {
+
+            let citations = match state.rag.retrieve(&req).await {
+                Ok(c) => c,
+                Err(e) => return send_error(e.to_string()).await,
+            };
+            // 相关度不足时不调用模型，推送最接近的文档后结束
+            if let Some(evidence) = state.answerability.assess(&req, &citations) {
+                let evidence_json =
+                    serde_json::to_string(&evidence).unwrap_or_else(|_| "{}".into());
+                let answer = kb_rag::AnswerabilityGate::answer(&evidence);
                 let _ = tx
-                    .send(Ok(Event::default().event("tool_result").data(data)))
+                    .send(Ok(Event::default()
+                        .event("insufficient_evidence")
+                        .data(evidence_json)))
                     .await;
+                let _ = tx
+                    .send(Ok(Event::default().event("final").data(answer)))
+                    .await;
+                return;
             }
-        }
-        fn on_stream_completion_response_finish(
-            &self,
-            _prompt: &Message,
-            _resp: &<M as CompletionModel>::StreamingResponse,
-        ) -> impl std::future::Future<Output = ()> + Send {
-            async {}
-        }
-    }
+            let citations_json = serde_json::to_string(&citations).unwrap_or_else(|_| "[]".into());
+            let _ = tx
+                .send(Ok(Event::default().event("citations").data(citations_json)))
+                .await;
 
-    tokio::spawn(async move {
-        let client = providers::openai::Client::from_env();
-        let embed = client.embedding_model(&embed_model);
-        let q = Qdrant::from_url(&url).build().unwrap();
-        let mut qp = qdrant_client::qdrant::QueryPointsBuilder::new(&coll).with_payload(true);
-        if let Some(flt) = build_qdrant_filter(&req.filters) {
-            qp = qp.filter(flt);
-        }
-        let index: QdrantVectorStore<_> = QdrantVectorStore::new(q, embed.clone(), qp.build());
-
-        let agent = client
-            .agent(&chat_model)
-            .preamble("You are a helpful assistant. Answer using only the provided context.")
-            .dynamic_context(k as usize, index)
-            .build();
-
-        let hook = SseHook { tx: tx.clone() };
-        let mut stream = agent
-            .stream_prompt(&req.query)
-            .multi_turn(10)
-            .with_hook(hook)
-            .await;
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(MultiTurnStreamItem::Text(t)) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("text").data(t.text)))
-                        .await;
-                }
-                Ok(MultiTurnStreamItem::FinalResponse(fr)) => {
-                    let _ = tx
-                        .send(Ok(Event::default()
-                            .event("final")
-                            .data(fr.response().to_string())))
-                        .await;
+            let mut stream = match state.rag.generate_stream(&req, citations.clone()).await {
+                Ok(s) => s,
+                Err(e) => return send_error(e.to_string()).await,
+            };
+            let mut answer = String::new();
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(text) => {
+                        answer.push_str(&text);
+                        if tx
+                            .send(Ok(Event::default().event("text").data(text)))
+                            .await
+                            .is_err()
+                        {
+                            // 客户端已断开
+                            return;
+                        }
+                    }
+                    Err(e) => return send_error(e.to_string()).await,
                 }
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(e.to_string())))
-                        .await;
-                    break;
+            }
+            // 按引擎相同的配置与预算重新组装，得到实际装入上下文的引用
+            let grounding = match (&state.grounding, citations.is_empty()) {
+                (Some(checker), false) => {
+                    let budget = state.context.query_budget(&req.query);
+                    let context_chunk_ids: Vec<String> =
+                        kb_rag::context::assemble(&state.context, &citations, budget)
+                            .parts
+                            .iter()
+                            .map(|p| citations[p.index].chunk_id.clone())
+                            .collect();
+                    Some(checker.check(&answer, &citations, &context_chunk_ids).await)
                 }
+                _ => None,
+            };
+            let _ = tx
+                .send(Ok(Event::default().event("final").data(answer)))
+                .await;
+            if let Some(report) = grounding {
+                let report_json = serde_json::to_string(&report).unwrap_or_else(|_| "{}".into());
+                let _ = tx
+                    .send(Ok(Event::default().event("grounding").data(report_json)))
+                    .await;
             }
-        }
+        });
+        let ((), usage) = with_metering(&ledger, &who, metered).await;
+        // 流式回答的用量按输出文本估算
+        let usage_json = serde_json::to_string(&usage).unwrap_or_else(|_| "{}".into());
+        let _ = usage_tx
+            .send(Ok(Event::default().event("usage").data(usage_json)))
+            .await;
     });
 
     let stream = ReceiverStream::new(rx);
     Sse::new(stream)
 }
 
-// 兼容 GET SSE：通过查询参数获取 query/top_k
+// 兼容 GET SSE：通过查询参数获取 query/mode/top_k/expansion
 #[derive(Deserialize)]
 struct StreamQuery {
     query: String,
+    /// 为空时为 rag
+    mode: Option<String>,
     top_k: Option<u64>,
+    expansion: Option<kb_core::QueryExpansion>,
+    parent_window: Option<bool>,
 }
 
 async fn query_stream_get(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(q): Query<StreamQuery>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let req = QueryRequest {
         query: q.query,
-        mode: Some("rag".into()),
+        mode: Some(q.mode.unwrap_or_else(|| "rag".into())),
         top_k: q.top_k.map(|v| v as u16),
         rerank: None,
         filters: None,
         stream: Some(true),
         include_raw_matches: None,
+        history: None,
+        expansion: q.expansion,
+        parent_window: q.parent_window,
     };
-    query_stream(State(state), Json(req)).await
+    query_stream(State(state), headers, Json(req)).await
 }
 
 #[derive(Deserialize)]
@@ -1292,7 +1772,7 @@ async fn session_start(Json(req): Json<SessionStartReq>) -> Json<serde_json::Val
         top_k: req.top_k.unwrap_or(5) as usize,
         filters: req.filters,
         chat_history: vec![],
-        pending_tool: None,
+        pending_tools: vec![],
     };
     save_session(sid, &st).await;
     Json(json!({"session_id": sid}))
@@ -1301,31 +1781,28 @@ async fn session_start(Json(req): Json<SessionStartReq>) -> Json<serde_json::Val
 #[derive(Deserialize)]
 struct SessionToolResultReq {
     session_id: Uuid,
+    /// 对应的工具调用 ID；为空时按顺序回传最早的待处理调用
+    call_id: Option<String>,
     result: String,
 }
 
 async fn session_tool_result(Json(req): Json<SessionToolResultReq>) -> Json<serde_json::Value> {
-    use rig::message::{Message, ToolResultContent, UserContent};
-    use rig::OneOrMany;
     if let Some(mut st) = load_session(req.session_id).await {
-        if let Some(p) = st.pending_tool.clone() {
+        let idx = match &req.call_id {
+            Some(call_id) => st
+                .pending_tools
+                .iter()
+                .position(|p| p.call_id.as_ref().unwrap_or(&p.id) == call_id),
+            None => (!st.pending_tools.is_empty()).then_some(0),
+        };
+        if let Some(idx) = idx {
             // 注入 tool_result
-            let content = OneOrMany::one(ToolResultContent::text(&req.result));
-            let msg = if let Some(call_id) = p.call_id.clone() {
-                Message::User {
-                    content: OneOrMany::one(UserContent::tool_result_with_call_id(
-                        &p.id, call_id, content,
-                    )),
-                }
-            } else {
-                Message::User {
-                    content: OneOrMany::one(UserContent::tool_result(&p.id, content)),
-                }
-            };
-            st.chat_history.push(msg);
-            st.pending_tool = None;
+            let p = st.pending_tools.remove(idx);
+            let call_id = p.call_id.unwrap_or(p.id);
+            st.chat_history.push(ChatMessage::tool(call_id, req.result));
             save_session(req.session_id, &st).await;
-            return Json(json!({"status":"ok"}));
+            let pending: Vec<&str> = st.pending_tools.iter().map(|p| p.id.as_str()).collect();
+            return Json(json!({"status":"ok", "pending": pending}));
         }
         return Json(json!({"status":"no_pending_tool"}));
     }
@@ -1335,177 +1812,200 @@ async fn session_tool_result(Json(req): Json<SessionToolResultReq>) -> Json<serd
 #[derive(Deserialize)]
 struct SessionStreamQuery {
     session_id: Uuid,
+    /// 追问内容；为空时继续当前对话（首轮使用 start 时的 query）
+    query: Option<String>,
 }
 
 async fn session_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Query(q): Query<SessionStreamQuery>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    use qdrant_client::Qdrant;
-    use rig::{
-        client::{EmbeddingsClient, ProviderClient},
-        providers,
-        streaming::StreamingCompletion,
-    };
-    use rig_qdrant::QdrantVectorStore;
-
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, Infallible>>(16);
+    let who = UsageIdentity::from_request(&state.auth_services.jwt_service, &headers);
+    let prompt = state.prompts.resolve(&who.tenant, "chat");
     let sid = q.session_id;
+    let ledger = state.usage.clone();
+    let usage_tx = tx.clone();
     tokio::spawn(async move {
-        // 读取会话
-        let st_opt = { load_session(sid).await };
-        if st_opt.is_none() {
-            let _ = tx
-                .send(Ok(Event::default().event("error").data("not_found")))
+        if let Err(e) = ledger.check_quota(&who.tenant) {
+            let _ = usage_tx
+                .send(Ok(Event::default().event("error").data(e.to_string())))
                 .await;
             return;
         }
-        let st = st_opt.unwrap();
-
-        // 构建 agent + index
-        let cfg: AppConfig = match load_config() {
-            Ok(c) => c,
-            Err(e) => {
-                let _ = tx
-                    .send(Ok(Event::default().event("error").data(e.to_string())))
-                    .await;
-                return;
-            }
-        };
-        let url = cfg
-            .vector_store
-            .url
-            .unwrap_or_else(|| "http://localhost:6334".into());
-        let coll = cfg
-            .vector_store
-            .collection
-            .unwrap_or_else(|| "kb_chunks".into());
-        let chat_model = cfg.chat_provider.model;
-        let embed_model = cfg.embedding_provider.model;
-        let client = providers::openai::Client::from_env();
-        let embed = client.embedding_model(&embed_model);
-        let qd = match Qdrant::from_url(&url).build() {
-            Ok(x) => x,
-            Err(e) => {
-                let _ = tx
-                    .send(Ok(Event::default().event("error").data(e.to_string())))
-                    .await;
-                return;
-            }
-        };
-        let mut qp = qdrant_client::qdrant::QueryPointsBuilder::new(&coll).with_payload(true);
-        if let Some(f) = build_qdrant_filter(&st.filters) {
-            qp = qp.filter(f);
-        }
-        let index: QdrantVectorStore<_> = QdrantVectorStore::new(qd, embed.clone(), qp.build());
-        // 定义一个示例工具供客户端驱动闭环演示（模型可决定是否调用）
-        #[derive(serde::Deserialize, serde::Serialize)]
-        struct TimeNow;
-        #[derive(Debug)]
-        struct ToolError;
-        impl std::fmt::Display for ToolError {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-                write!(f, "tool error")
-            }
-        }
-        impl std::error::Error for ToolError {}
-        impl rig::tool::Tool for TimeNow {
-            const NAME: &'static str = "time_now";
-            type Error = ToolError;
-            type Args = serde_json::Value; // 无参数
-            type Output = String;
-            async fn definition(&self, _prompt: String) -> rig::completion::ToolDefinition {
-                rig::completion::ToolDefinition {
-                    name: "time_now".into(),
-                    description: "Return current server time in RFC3339".into(),
-                    parameters: json!({"type":"object","properties":{}}),
+        let metered = kb_llm::with_prompt(prompt, async move {
+            let send_error = |e: String| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(Ok(Event::default().event("error").data(e))).await;
                 }
+            };
+
+            // 读取会话
+            let Some(mut st) = load_session(sid).await else {
+                return send_error("not_found".to_string()).await;
+            };
+            // 工具结果未全部回传时历史不完整，提供商会拒绝请求
+            if !st.pending_tools.is_empty() {
+                return send_error("pending_tool_result".to_string()).await;
             }
-            async fn call(&self, _args: Self::Args) -> Result<Self::Output, Self::Error> {
-                Ok(chrono::Utc::now().to_rfc3339())
+            if let Some(query) = q.query.filter(|s| !s.trim().is_empty()) {
+                st.chat_history.push(ChatMessage::user(query));
+            } else if st.chat_history.is_empty() {
+                st.chat_history.push(ChatMessage::user(st.query.clone()));
             }
-        }
-
-        let agent = client.agent(&chat_model)
-            .preamble("You are a helpful assistant. Answer using only the provided context. Tools may be available.")
-            .dynamic_context(st.top_k, index)
-            .tool(TimeNow)
-            .build();
 
-        // 低层流式：不自动执行工具；遇到 tool_call 挂起，等待客户端回传 /session/tool_result
-        let builder = match agent
-            .stream_completion(&st.query, st.chat_history.clone())
-            .await
-        {
-            Ok(b) => b,
-            Err(e) => {
+            // 以最近一条用户消息检索上下文，追问结合之前的轮次改写为独立问题
+            let last_user = st
+                .chat_history
+                .iter()
+                .rposition(|m| m.role == kb_llm::Role::User);
+            let question = last_user
+                .map(|i| st.chat_history[i].content.clone())
+                .unwrap_or_else(|| st.query.clone());
+            let history: Vec<kb_core::ConversationTurn> = st.chat_history[..last_user.unwrap_or(0)]
+                .iter()
+                .filter(|m| matches!(m.role, kb_llm::Role::User | kb_llm::Role::Assistant))
+                .filter(|m| !m.content.trim().is_empty())
+                .map(|m| kb_core::ConversationTurn {
+                    role: m.role.as_str().to_string(),
+                    content: m.content.clone(),
+                })
+                .collect();
+            let rewritten = match &state.rewriter {
+                Some(rewriter) => rewriter.rewrite(&question, &history).await.rewritten(),
+                None => None,
+            };
+            if let Some(q) = &rewritten {
                 let _ = tx
-                    .send(Ok(Event::default().event("error").data(e.to_string())))
+                    .send(Ok(Event::default()
+                        .event("rewritten_query")
+                        .data(q.clone())))
                     .await;
-                return;
             }
-        };
-        let mut stream = match builder.stream().await {
-            Ok(s) => s,
-            Err(e) => {
+            let search_req = QueryRequest {
+                query: rewritten.unwrap_or(question),
+                top_k: Some(st.top_k as u16),
+                filters: st.filters.clone(),
+                ..Default::default()
+            };
+            let citations = match state.rag.retrieve(&search_req).await {
+                Ok(c) => c,
+                Err(e) => return send_error(e.to_string()).await,
+            };
+            // 相关度不足时不调用模型，与流式查询一致推送最接近的文档后结束；该轮问答仍写回会话历史
+            if let Some(evidence) = state.answerability.assess(&search_req, &citations) {
+                let evidence_json =
+                    serde_json::to_string(&evidence).unwrap_or_else(|_| "{}".into());
+                let answer = kb_rag::AnswerabilityGate::answer(&evidence);
+                st.chat_history.push(ChatMessage::assistant(answer.clone()));
+                save_session(sid, &st).await;
+                let _ = tx
+                    .send(Ok(Event::default()
+                        .event("insufficient_evidence")
+                        .data(evidence_json)))
+                    .await;
                 let _ = tx
-                    .send(Ok(Event::default().event("error").data(e.to_string())))
+                    .send(Ok(Event::default().event("final").data(answer)))
                     .await;
                 return;
             }
-        };
-        while let Some(chunk) = stream.next().await {
-            use rig::streaming::StreamedAssistantContent;
-            match chunk {
-                Ok(StreamedAssistantContent::Text(t)) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("text").data(t.text)))
-                        .await;
-                }
-                Ok(StreamedAssistantContent::Reasoning(r)) => {
-                    let _ = tx
-                        .send(Ok(Event::default()
-                            .event("reasoning")
-                            .data(r.reasoning.into_iter().collect::<String>())))
-                        .await;
-                }
-                Ok(StreamedAssistantContent::ToolCall(tc)) => {
-                    // 写入会话：Assistant ToolCall，并挂起等待 tool_result
-                    use rig::message::{AssistantContent, Message};
-                    use rig::OneOrMany;
-                    if let Some(mut s) = load_session(sid).await {
-                        s.chat_history.push(Message::Assistant {
-                            id: None,
-                            content: OneOrMany::one(AssistantContent::ToolCall(tc.clone())),
-                        });
-                        s.pending_tool = Some(PendingTool {
-                            id: tc.id.clone(),
-                            call_id: tc.call_id.clone(),
-                            name: tc.function.name.clone(),
-                        });
-                        save_session(sid, &s).await;
+            // 对话历史随请求发送，与 system 提示一并从上下文预算中扣除
+            let system = kb_llm::current_prompt().render_system();
+            let conversation = st
+                .chat_history
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let budget = state.context.budget(&system, &conversation);
+            let context = kb_rag::context::assemble(&state.context, &citations, budget).text;
+            let citations_json = serde_json::to_string(&citations).unwrap_or_else(|_| "[]".into());
+            let _ = tx
+                .send(Ok(Event::default().event("citations").data(citations_json)))
+                .await;
+
+            let mut messages = vec![ChatMessage::system(format!(
+                "{}\n\nContext:\n{}",
+                system, context
+            ))];
+            messages.extend(st.chat_history.iter().cloned());
+            // 示例客户端工具 time_now：模型调用时挂起会话，等待客户端回传 /session/tool_result
+            let time_now = kb_llm::ToolDefinition::new(
+                "time_now",
+                "Return current server time in RFC3339",
+                json!({"type":"object","properties":{}}),
+            );
+            let chat_req = kb_llm::ChatRequest::new(messages)
+                .with_temperature(0.2)
+                .with_tools(vec![time_now]);
+
+            // 知识库检索与图谱查询作为服务端工具自动执行
+            let kb_search = kb_rag::KnowledgeBaseSearchTool::new(state.rag.clone())
+                .with_default_top_k(st.top_k as u16)
+                .with_filters(st.filters.clone());
+            let tools = kb_rag::ToolSet::new()
+                .with_tool(Arc::new(kb_search))
+                .with_tool(Arc::new(kb_rag::GraphEntityLookupTool::new(
+                    state.graph.clone(),
+                )));
+            let agent = kb_rag::ToolAgent::new(state.chat.clone(), tools);
+            let on_event = |event: kb_rag::AgentEvent| {
+                let events: Vec<Event> = match event {
+                    kb_rag::AgentEvent::Text(text) => {
+                        vec![Event::default().event("text").data(text)]
+                    }
+                    kb_rag::AgentEvent::Message(m) if m.role == kb_llm::Role::Tool => {
+                        vec![Event::default().event("tool_result").data(m.content)]
+                    }
+                    kb_rag::AgentEvent::Message(m) => m
+                        .tool_calls
+                        .iter()
+                        .map(|tc| {
+                            Event::default()
+                                .event("tool_call")
+                                .data(format!("{} {}", tc.name, tc.arguments))
+                        })
+                        .collect(),
+                };
+                let tx = tx.clone();
+                async move {
+                    for event in events {
+                        let _ = tx.send(Ok(event)).await;
                     }
-                    let _ = tx
-                        .send(Ok(Event::default().event("tool_call").data(format!(
-                            "{} {}",
-                            tc.function.name, tc.function.arguments
-                        ))))
-                        .await;
-                    break;
-                }
-                Ok(StreamedAssistantContent::Final(final_msg)) => {
-                    let json = serde_json::to_string(&final_msg).unwrap_or_default();
-                    let _ = tx
-                        .send(Ok(Event::default().event("final").data(json)))
-                        .await;
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(Ok(Event::default().event("error").data(e.to_string())))
-                        .await;
-                    break;
                 }
+            };
+            let run = match agent.run_stream(chat_req, on_event).await {
+                Ok(r) => r,
+                Err(e) => return send_error(e.to_string()).await,
+            };
+            st.chat_history.extend(run.messages);
+
+            // 写回会话历史，供下一轮追问或工具回传使用；客户端工具全部挂起等待回传
+            st.pending_tools = run
+                .pending_tool_calls
+                .into_iter()
+                .map(|tc| PendingTool {
+                    id: tc.id.clone(),
+                    call_id: Some(tc.id),
+                    name: tc.name,
+                })
+                .collect();
+            save_session(sid, &st).await;
+            if !st.pending_tools.is_empty() {
+                return;
             }
-        }
+            let _ = tx
+                .send(Ok(Event::default().event("final").data(run.answer)))
+                .await;
+        });
+        let ((), usage) = with_metering(&ledger, &who, metered).await;
+        // 流式回答的用量按输出文本估算
+        let usage_json = serde_json::to_string(&usage).unwrap_or_else(|_| "{}".into());
+        let _ = usage_tx
+            .send(Ok(Event::default().event("usage").data(usage_json)))
+            .await;
     });
 
     Sse::new(ReceiverStream::new(rx))
@@ -1770,6 +2270,69 @@ async fn admin_put_settings(
     admin_get_settings(headers).await
 }
 
+async fn admin_get_prompts(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    Json(json!({ "prompts": state.prompts.snapshot() }))
+}
+
+#[derive(Deserialize)]
+struct PromptsInput {
+    /// 新增或修改的模板，内容变化时版本号递增
+    #[serde(default)]
+    templates: HashMap<String, kb_llm::PromptTemplate>,
+    /// 为空时保留原有规则
+    modes: Option<HashMap<String, String>>,
+    tenants: Option<HashMap<String, HashMap<String, String>>>,
+}
+
+async fn admin_put_prompts(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(input): Json<PromptsInput>,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    for (name, template) in input.templates {
+        let version = state.prompts.upsert(&name, template);
+        info!(prompt = %name, version, "提示模板已更新");
+    }
+    if input.modes.is_some() || input.tenants.is_some() {
+        let current = state.prompts.snapshot();
+        state.prompts.set_rules(
+            input.modes.unwrap_or(current.modes),
+            input.tenants.unwrap_or(current.tenants),
+        );
+    }
+    // 缓存的回答由旧模板生成
+    if let Some(cache) = &state.answer_cache {
+        cache.clear();
+    }
+    admin_get_prompts(State(state), headers).await
+}
+
+async fn admin_delete_prompt(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    let removed = state.prompts.remove(&name);
+    if removed {
+        if let Some(cache) = &state.answer_cache {
+            cache.clear();
+        }
+    }
+    Json(json!({"status":"ok","removed":removed}))
+}
+
 // ===============
 // Admin: Upload
 // ===============
@@ -1800,6 +2363,7 @@ async fn admin_upload(
                 .unwrap_or_default()
                 .parse()
                 .unwrap_or(1800);
+            chunk_size = clamp_chunk_size(chunk_size);
         } else if name == "overlap" {
             overlap = field.text().await.unwrap_or_default().parse().unwrap_or(0);
         } else if name == "file" {
@@ -1870,7 +2434,11 @@ async fn admin_upload(
             }
             let mut n: usize = 0;
             for c in chunks.into_iter() {
-                let _ = state.rag.add_document_text(&document_id, &c, None).await;
+                let _ = kb_llm::with_priority(
+                    kb_llm::Priority::Background,
+                    state.rag.add_document_text(&document_id, &c, None),
+                )
+                .await;
                 n += 1;
             }
             inc_index_count(&document_id, n).await;
@@ -1934,7 +2502,11 @@ async fn admin_upload(
             }
             let mut n: usize = 0;
             for c in chunks.into_iter() {
-                let _ = state.rag.add_document_text(&document_id, &c, None).await;
+                let _ = kb_llm::with_priority(
+                    kb_llm::Priority::Background,
+                    state.rag.add_document_text(&document_id, &c, None),
+                )
+                .await;
                 n += 1;
             }
             inc_index_count(&document_id, n).await;
@@ -2110,6 +2682,120 @@ async fn admin_index_status_all(headers: HeaderMap) -> Json<serde_json::Value> {
     Json(json!({"status": "ok", "index_counts": &*m }))
 }
 
+async fn admin_usage(State(state): State<AppState>, headers: HeaderMap) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    Json(json!(state.usage.snapshot()))
+}
+
+async fn admin_embedding_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    match &state.embed_cache {
+        Some(cache) => {
+            let stats = cache.stats();
+            Json(json!({"enabled": true, "hit_rate": stats.hit_rate(), "stats": stats}))
+        }
+        None => Json(json!({"enabled": false})),
+    }
+}
+
+#[derive(Deserialize)]
+struct RetrievalEvalInput {
+    cases: Vec<kb_rag::EvalCase>,
+    #[serde(default = "default_eval_top_k")]
+    top_k: u16,
+    /// 为空时评测全部扩展方式
+    #[serde(default)]
+    expansions: Vec<kb_core::QueryExpansion>,
+}
+
+fn default_eval_top_k() -> u16 {
+    5
+}
+
+/// 在同一组用例上比较各检索扩展方式的 recall 与 MRR
+async fn admin_eval_retrieval(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(input): Json<RetrievalEvalInput>,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    let expansions = if input.expansions.is_empty() {
+        vec![
+            kb_core::QueryExpansion::None,
+            kb_core::QueryExpansion::MultiQuery,
+            kb_core::QueryExpansion::Hyde,
+        ]
+    } else {
+        input.expansions
+    };
+    let mut reports = Vec::new();
+    for expansion in expansions {
+        match kb_rag::evaluate(state.rag.as_ref(), &input.cases, expansion, input.top_k).await {
+            Ok(report) => reports.push(report),
+            Err(e) => return Json(json!({"error": e.to_string()})),
+        }
+    }
+    Json(json!({"top_k": input.top_k, "reports": reports}))
+}
+
+async fn admin_answer_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    match &state.answer_cache {
+        Some(cache) => Json(json!({"enabled": true, "stats": cache.stats()})),
+        None => Json(json!({"enabled": false})),
+    }
+}
+
+#[derive(Deserialize)]
+struct AnswerCacheInvalidate {
+    document_id: Option<String>,
+}
+
+/// 文档删除后使引用它的缓存回答失效；未指定 document_id 时清空缓存
+async fn admin_answer_cache_invalidate(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<AnswerCacheInvalidate>,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    let Some(cache) = &state.answer_cache else {
+        return Json(json!({"status":"ok","removed":0}));
+    };
+    let removed = match q.document_id.as_deref() {
+        Some(doc) => cache.invalidate_document(doc),
+        None => cache.clear(),
+    };
+    Json(json!({"status":"ok","removed":removed}))
+}
+
+/// 账期结算后清零累计用量
+async fn admin_usage_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Json<serde_json::Value> {
+    if !admin_auth_ok(&headers) {
+        return Json(json!({"error":"unauthorized"}));
+    }
+    state.usage.reset();
+    Json(json!({"status":"ok"}))
+}
+
 async fn admin_extract_health(headers: HeaderMap) -> Json<serde_json::Value> {
     if !admin_auth_ok(&headers) {
         return Json(json!({"error":"unauthorized"}));
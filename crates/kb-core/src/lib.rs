@@ -62,15 +62,45 @@ pub struct IndexJob {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct QueryRequest {
     pub query: String,
-    pub mode: Option<String>, // rag | graph | hybrid | lexical
+    pub mode: Option<String>, // rag | graph | hybrid | lexical | multihop | auto
     pub top_k: Option<u16>,
     pub rerank: Option<bool>,
     pub filters: Option<serde_json::Value>,
     pub stream: Option<bool>,
     pub include_raw_matches: Option<bool>,
+    /// 之前的对话轮次（由旧到新），用于把追问改写为独立问题后再检索
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<ConversationTurn>>,
+    /// 检索扩展方式，未指定时使用服务端默认配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expansion: Option<QueryExpansion>,
+    /// 是否把命中的子块扩展为所在父块的窗口，未指定时使用服务端默认配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_window: Option<bool>,
+}
+
+/// 检索扩展：针对简短、模糊的问题扩大召回
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryExpansion {
+    /// 直接以原问题检索
+    #[default]
+    None,
+    /// 生成多个改写问题分别检索，再以 RRF 融合
+    MultiQuery,
+    /// 生成假设性回答，以其向量检索（HyDE）
+    Hyde,
+}
+
+/// 对话中的一轮消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationTurn {
+    /// user 或 assistant
+    pub role: String,
+    pub content: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,15 +110,132 @@ pub struct Citation {
     pub page: Option<i32>,
     pub score: f32,
     pub snippet: String,
+    /// 分块的上下文标题（文档标题、章节路径等），只拼入发送给模型的上下文，不随片段展示
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    /// 完整的分块文本；`snippet` 为展示而截断时保留，供组装上下文使用，不随响应返回
+    #[serde(skip)]
+    pub text: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Citation {
+    /// 组装上下文使用的文本：优先完整分块文本，缺失时退回展示片段
+    pub fn content(&self) -> &str {
+        self.text.as_deref().unwrap_or(&self.snippet)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct QueryResponse {
     pub answer: String,
     pub citations: Vec<Citation>,
     pub contexts: Vec<String>,
+    /// 实际装入模型上下文的引用（chunk_id），预算不足时少于 `citations`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub context_chunk_ids: Vec<String>,
     pub mode: String,
     pub latency_ms: i64,
+    /// 重排耗时（仅在启用重排时返回）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_latency_ms: Option<i64>,
+    /// 本次请求的 token 用量与费用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// 回答是否来自语义回答缓存
+    #[serde(default)]
+    pub cache_hit: bool,
+    /// 结合对话历史改写后用于检索的独立问题（未改写时为空）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rewritten_query: Option<String>,
+    /// 引用核验报告（仅在启用核验时返回）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grounding: Option<GroundingReport>,
+    /// 多跳模式下拆分出的子问题及各自的引用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sub_questions: Vec<SubQuestion>,
+    /// 检索结果相关度不足、未调用模型生成回答时的说明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insufficient_evidence: Option<InsufficientEvidence>,
+}
+
+/// 证据不足的说明
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InsufficientEvidence {
+    /// no_results（没有检索结果）或 low_relevance（相关度低于阈值）
+    pub reason: String,
+    /// 检索结果中的最高得分
+    pub best_score: Option<f32>,
+    pub threshold: f32,
+    /// 最接近的文档，按得分从高到低
+    pub suggested_documents: Vec<SuggestedDocument>,
+}
+
+/// 证据不足时推荐查看的文档
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SuggestedDocument {
+    pub document_id: String,
+    pub page: Option<i32>,
+    pub score: f32,
+    pub snippet: String,
+}
+
+/// 多跳查询的子问题
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubQuestion {
+    pub question: String,
+    /// 该子问题检索到的引用在 `citations` 中的下标，回答中以 `[下标 + 1]` 标注
+    pub citations: Vec<usize>,
+}
+
+/// 单次请求的 token 用量与按价格表计算的费用
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub embedding_tokens: u64,
+    pub cost: f64,
+}
+
+/// 回答的引用核验报告
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GroundingReport {
+    /// 需要核验的句子：较长的陈述句或带引用标记的句子
+    pub sentences: Vec<SentenceGrounding>,
+    /// 陈述句中带有有效引用的比例
+    pub citation_coverage: f32,
+    /// 未标注引用的陈述句数
+    pub uncited: usize,
+    /// 越界或指向未发送给模型的引用标记数
+    pub invalid_citations: usize,
+    /// 经模型判定不被引用内容支持的句子数，未启用蕴含判断时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsupported: Option<usize>,
+}
+
+/// 单句核验结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SentenceGrounding {
+    pub text: String,
+    /// 句中的引用编号，从 1 开始，对应 `citations[n - 1]`
+    pub citations: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<GroundingIssue>,
+    /// 引用内容支持该句的程度（0~1），未启用蕴含判断时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entailment: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroundingIssue {
+    /// 陈述句没有引用
+    Uncited,
+    /// 引用编号超出引用列表
+    OutOfRange,
+    /// 引用未装入模型上下文
+    NotInContext,
+    /// 引用内容不支持该句
+    Unsupported,
 }
 
 pub use kb_error::{KbError as Error, Result};
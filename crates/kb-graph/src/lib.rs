@@ -150,6 +150,7 @@ impl GraphRagEngine {
                 contexts: vec![],
                 mode: "graph".to_string(),
                 latency_ms: start_time.elapsed().as_millis() as i64,
+                ..Default::default()
             });
         }
 
@@ -198,6 +199,8 @@ impl GraphRagEngine {
                     page: chunk.page,
                     score: triple.confidence.unwrap_or(0.0),
                     snippet: context_text,
+                    header: None,
+                    text: None,
                 });
             } else {
                 citations.push(Citation {
@@ -206,6 +209,8 @@ impl GraphRagEngine {
                     page: None,
                     score: triple.confidence.unwrap_or(0.0),
                     snippet: context_text,
+                    header: None,
+                    text: None,
                 });
             }
         }
@@ -221,6 +226,7 @@ impl GraphRagEngine {
             contexts,
             mode: "graph".to_string(),
             latency_ms: start_time.elapsed().as_millis() as i64,
+            ..Default::default()
         })
     }
 
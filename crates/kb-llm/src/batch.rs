@@ -0,0 +1,336 @@
+use async_trait::async_trait;
+use futures::{StreamExt, TryStreamExt};
+use kb_error::{KbError, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::tokens::{estimate_tokens, split_by_tokens, truncate_to_tokens};
+use crate::EmbedModel;
+
+/// 超长输入的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedInput {
+    /// 截断到单条输入上限
+    Truncate,
+    /// 切分为多段分别向量化，再按长度加权平均并归一化
+    #[default]
+    Split,
+}
+
+/// 向量接口的批处理配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedBatchConfig {
+    /// 单次请求的最大输入条数
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// 单次请求的最大估算 token 总数
+    #[serde(default = "default_max_batch_tokens")]
+    pub max_batch_tokens: usize,
+    /// 单条输入的最大估算 token 数
+    #[serde(default = "default_max_input_tokens")]
+    pub max_input_tokens: usize,
+    /// 同时进行的请求数
+    #[serde(default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+    #[serde(default)]
+    pub oversized: OversizedInput,
+}
+
+fn default_max_batch_size() -> usize {
+    64
+}
+
+fn default_max_batch_tokens() -> usize {
+    60_000
+}
+
+fn default_max_input_tokens() -> usize {
+    2048
+}
+
+fn default_max_concurrency() -> usize {
+    4
+}
+
+impl Default for EmbedBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: default_max_batch_size(),
+            max_batch_tokens: default_max_batch_tokens(),
+            max_input_tokens: default_max_input_tokens(),
+            max_concurrency: default_max_concurrency(),
+            oversized: OversizedInput::default(),
+        }
+    }
+}
+
+impl EmbedBatchConfig {
+    /// 取两份配置中更严格的限制，用于故障转移链
+    pub fn restrict(&self, other: &EmbedBatchConfig) -> EmbedBatchConfig {
+        EmbedBatchConfig {
+            max_batch_size: self.max_batch_size.min(other.max_batch_size),
+            max_batch_tokens: self.max_batch_tokens.min(other.max_batch_tokens),
+            max_input_tokens: self.max_input_tokens.min(other.max_input_tokens),
+            max_concurrency: self.max_concurrency.min(other.max_concurrency),
+            oversized: self.oversized,
+        }
+    }
+}
+
+/// 自动分批的向量模型包装：按条数与 token 数切分批次、限制并发、处理超长输入，并按原顺序返回
+pub struct BatchEmbedModel {
+    inner: Arc<dyn EmbedModel>,
+    config: EmbedBatchConfig,
+}
+
+impl BatchEmbedModel {
+    pub fn new(inner: Arc<dyn EmbedModel>, config: EmbedBatchConfig) -> Self {
+        Self { inner, config }
+    }
+
+    pub fn config(&self) -> &EmbedBatchConfig {
+        &self.config
+    }
+
+    /// 将输入展开为待向量化的片段：(原始序号, 片段文本, 估算 token 数)
+    fn pieces<'a>(&self, texts: &'a [String]) -> Vec<(usize, &'a str, usize)> {
+        let max_input = self.config.max_input_tokens.max(1);
+        let mut pieces = Vec::with_capacity(texts.len());
+        for (i, text) in texts.iter().enumerate() {
+            let tokens = estimate_tokens(text);
+            if tokens <= max_input {
+                pieces.push((i, text.as_str(), tokens));
+                continue;
+            }
+            match self.config.oversized {
+                OversizedInput::Truncate => {
+                    let piece = truncate_to_tokens(text, max_input);
+                    pieces.push((i, piece, estimate_tokens(piece)));
+                }
+                OversizedInput::Split => {
+                    for piece in split_by_tokens(text, max_input) {
+                        pieces.push((i, piece, estimate_tokens(piece)));
+                    }
+                }
+            }
+        }
+        pieces
+    }
+
+    /// 按条数与 token 总数切分批次，返回每批片段的下标范围
+    fn batches(&self, pieces: &[(usize, &str, usize)]) -> Vec<std::ops::Range<usize>> {
+        let max_size = self.config.max_batch_size.max(1);
+        let mut batches = Vec::new();
+        let mut start = 0;
+        let mut tokens = 0;
+        for (i, (_, _, t)) in pieces.iter().enumerate() {
+            if i > start && (i - start >= max_size || tokens + t > self.config.max_batch_tokens) {
+                batches.push(start..i);
+                start = i;
+                tokens = 0;
+            }
+            tokens += t;
+        }
+        if start < pieces.len() {
+            batches.push(start..pieces.len());
+        }
+        batches
+    }
+}
+
+#[async_trait]
+impl EmbedModel for BatchEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(self.embed_with_provider(texts).await?.0)
+    }
+
+    /// 各批次可能由故障转移链中不同的提供商完成，此时以 `+` 连接各提供商名，
+    /// 结果不对应任何单一模型
+    async fn embed_with_provider(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Option<String>)> {
+        if texts.is_empty() {
+            return Ok((Vec::new(), None));
+        }
+        let pieces = self.pieces(texts);
+        let batches = self.batches(&pieces);
+        debug!(
+            inputs = texts.len(),
+            pieces = pieces.len(),
+            batches = batches.len(),
+            "向量化分批"
+        );
+
+        let inner = &self.inner;
+        let pieces_ref = &pieces;
+        let results: Vec<(Vec<Vec<f32>>, Option<String>)> = futures::stream::iter(batches)
+            .map(|range| async move {
+                let input: Vec<String> = pieces_ref[range.clone()]
+                    .iter()
+                    .map(|(_, p, _)| p.to_string())
+                    .collect();
+                let (vectors, provider) = inner.embed_with_provider(&input).await?;
+                if vectors.len() != input.len() {
+                    return Err(KbError::EmbeddingService {
+                        provider: "batch".to_string(),
+                        message: format!(
+                            "expected {} embeddings, got {}",
+                            input.len(),
+                            vectors.len()
+                        ),
+                        retry_after: None,
+                    });
+                }
+                Ok((vectors, provider))
+            })
+            .buffered(self.config.max_concurrency.max(1))
+            .try_collect()
+            .await?;
+        let mut providers: Vec<String> = Vec::new();
+        for provider in results.iter().filter_map(|(_, p)| p.as_ref()) {
+            if !providers.contains(provider) {
+                providers.push(provider.clone());
+            }
+        }
+        let provider = (!providers.is_empty()).then(|| providers.join("+"));
+
+        // 按原始序号合并片段向量，切分的输入按片段长度加权平均
+        let mut merged: Vec<Option<(Vec<f32>, usize, usize)>> = vec![None; texts.len()];
+        for ((index, _, tokens), vector) in
+            pieces.iter().zip(results.into_iter().flat_map(|(v, _)| v))
+        {
+            let weight = (*tokens).max(1);
+            match &mut merged[*index] {
+                Some((sum, total, count)) => {
+                    for (s, v) in sum.iter_mut().zip(&vector) {
+                        *s += v * weight as f32;
+                    }
+                    *total += weight;
+                    *count += 1;
+                }
+                slot @ None => {
+                    *slot = Some((
+                        vector.iter().map(|v| v * weight as f32).collect(),
+                        weight,
+                        1,
+                    ))
+                }
+            }
+        }
+        let vectors = merged
+            .into_iter()
+            .map(|m| {
+                let (mut vector, total, count) = m.unwrap_or_default();
+                vector.iter_mut().for_each(|v| *v /= total.max(1) as f32);
+                if count > 1 {
+                    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+                    if norm > 0.0 {
+                        vector.iter_mut().for_each(|v| *v /= norm);
+                    }
+                }
+                vector
+            })
+            .collect();
+        Ok((vectors, provider))
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// 记录每次请求的输入，向量第一维为输入的字符数
+    #[derive(Default)]
+    struct RecordingEmbed {
+        calls: Mutex<Vec<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl EmbedModel for RecordingEmbed {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.calls.lock().unwrap().push(texts.to_vec());
+            Ok(texts
+                .iter()
+                .map(|t| vec![t.chars().count() as f32, 1.0])
+                .collect())
+        }
+    }
+
+    fn batch_model(config: EmbedBatchConfig) -> (Arc<RecordingEmbed>, BatchEmbedModel) {
+        let inner = Arc::new(RecordingEmbed::default());
+        (inner.clone(), BatchEmbedModel::new(inner, config))
+    }
+
+    #[tokio::test]
+    async fn test_splits_into_batches_and_keeps_order() {
+        let (inner, model) = batch_model(EmbedBatchConfig {
+            max_batch_size: 2,
+            max_concurrency: 2,
+            ..Default::default()
+        });
+        let texts: Vec<String> = (1..=5).map(|n| "a".repeat(n)).collect();
+        let vectors = model.embed(&texts).await.unwrap();
+        let lens: Vec<f32> = vectors.iter().map(|v| v[0]).collect();
+        assert_eq!(lens, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let sizes: Vec<usize> = inner
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.len())
+            .collect();
+        assert_eq!(sizes.iter().sum::<usize>(), 5);
+        assert!(sizes.iter().all(|s| *s <= 2));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_input_is_truncated_or_split() {
+        let long = "向".repeat(10);
+        let (inner, model) = batch_model(EmbedBatchConfig {
+            max_input_tokens: 4,
+            oversized: OversizedInput::Truncate,
+            ..Default::default()
+        });
+        let vectors = model.embed(std::slice::from_ref(&long)).await.unwrap();
+        assert_eq!(vectors[0][0], 4.0);
+        assert_eq!(inner.calls.lock().unwrap()[0], vec!["向".repeat(4)]);
+
+        let (inner, model) = batch_model(EmbedBatchConfig {
+            max_input_tokens: 4,
+            ..Default::default()
+        });
+        let vectors = model.embed(&[long, "short".to_string()]).await.unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(inner.calls.lock().unwrap()[0].len(), 4);
+        // 切分后的向量经过归一化
+        let norm: f32 = vectors[0].iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert_eq!(vectors[1], vec![5.0, 1.0]);
+    }
+
+    #[tokio::test]
+    async fn test_batches_respect_token_budget() {
+        let (inner, model) = batch_model(EmbedBatchConfig {
+            max_batch_tokens: 10,
+            ..Default::default()
+        });
+        let texts = vec!["向".repeat(6), "向".repeat(6), "向".repeat(3)];
+        model.embed(&texts).await.unwrap();
+        let sizes: Vec<usize> = inner
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.len())
+            .collect();
+        assert_eq!(sizes, vec![1, 2]);
+    }
+}
@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+use crate::EmbedModel;
+
+/// 向量缓存的持久化层（如 sled、Redis），读写失败时按未命中处理，不影响向量化
+#[async_trait]
+pub trait EmbeddingStore: Send + Sync {
+    /// 按键批量读取，返回与 `keys` 等长的结果
+    async fn get(&self, keys: &[String]) -> Result<Vec<Option<Vec<f32>>>>;
+    async fn put(&self, entries: &[(String, Vec<f32>)]) -> Result<()>;
+}
+
+/// 缓存命中统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmbedCacheStats {
+    pub memory_hits: u64,
+    pub store_hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl EmbedCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.memory_hits + self.store_hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            (self.memory_hits + self.store_hits) as f64 / total as f64
+        }
+    }
+}
+
+/// 进程内 LRU
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (Vec<f32>, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Vec<f32>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (vector, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = tick;
+        self.order.insert(tick, key.to_string());
+        Some(vector.clone())
+    }
+
+    fn insert(&mut self, key: String, vector: Vec<f32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (vector, self.tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// 按 `(模型名, sha256(文本))` 缓存向量的包装：先查进程内 LRU，再查持久化层，仅未命中的文本调用下游模型
+///
+/// 缓存键包含模型名，切换模型后旧向量自然失效。下游为故障转移链时模型名应为主提供商名，
+/// 由其他提供商完成的向量（见 [`EmbedModel::embed_with_provider`]）不写入缓存。
+pub struct CachedEmbedModel {
+    inner: Arc<dyn EmbedModel>,
+    model: String,
+    memory: Mutex<Lru>,
+    store: Option<Arc<dyn EmbeddingStore>>,
+    memory_hits: AtomicU64,
+    store_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedEmbedModel {
+    pub fn new(inner: Arc<dyn EmbedModel>, model: impl Into<String>, capacity: usize) -> Self {
+        Self {
+            inner,
+            model: model.into(),
+            memory: Mutex::new(Lru::new(capacity)),
+            store: None,
+            memory_hits: AtomicU64::new(0),
+            store_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// 设置持久化层
+    pub fn with_store(mut self, store: Arc<dyn EmbeddingStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// 文本的缓存键
+    pub fn cache_key(&self, text: &str) -> String {
+        format!("{}:{:x}", self.model, Sha256::digest(text.as_bytes()))
+    }
+
+    pub fn stats(&self) -> EmbedCacheStats {
+        let memory = self.memory.lock().unwrap();
+        EmbedCacheStats {
+            memory_hits: self.memory_hits.load(Ordering::Relaxed),
+            store_hits: self.store_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: memory.entries.len(),
+            capacity: memory.capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbedModel for CachedEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let keys: Vec<String> = texts.iter().map(|t| self.cache_key(t)).collect();
+        let mut out: Vec<Option<Vec<f32>>> = {
+            let mut memory = self.memory.lock().unwrap();
+            keys.iter().map(|k| memory.get(k)).collect()
+        };
+        let memory_hits = out.iter().filter(|v| v.is_some()).count();
+        self.memory_hits
+            .fetch_add(memory_hits as u64, Ordering::Relaxed);
+
+        // 同一请求内的重复文本只查询与向量化一次
+        let mut pending: Vec<usize> = Vec::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            if out[i].is_none() && !seen.contains_key(key.as_str()) {
+                seen.insert(key.as_str(), i);
+                pending.push(i);
+            }
+        }
+
+        let mut store_hits = 0;
+        if let (Some(store), false) = (&self.store, pending.is_empty()) {
+            let pending_keys: Vec<String> = pending.iter().map(|&i| keys[i].clone()).collect();
+            match store.get(&pending_keys).await {
+                Ok(found) => {
+                    let mut memory = self.memory.lock().unwrap();
+                    for (&i, vector) in pending.iter().zip(found) {
+                        if let Some(vector) = vector {
+                            memory.insert(keys[i].clone(), vector.clone());
+                            out[i] = Some(vector);
+                            store_hits += 1;
+                        }
+                    }
+                }
+                Err(e) => warn!(error = %e, "读取向量缓存失败"),
+            }
+            pending.retain(|&i| out[i].is_none());
+        }
+        self.store_hits.fetch_add(store_hits, Ordering::Relaxed);
+        self.misses
+            .fetch_add(pending.len() as u64, Ordering::Relaxed);
+        debug!(
+            inputs = texts.len(),
+            memory_hits,
+            store_hits,
+            misses = pending.len(),
+            "向量缓存"
+        );
+
+        if !pending.is_empty() {
+            let input: Vec<String> = pending.iter().map(|&i| texts[i].clone()).collect();
+            let (vectors, provider) = self.inner.embed_with_provider(&input).await?;
+            if vectors.len() != input.len() {
+                return Err(KbError::EmbeddingService {
+                    provider: self.model.clone(),
+                    message: format!("expected {} embeddings, got {}", input.len(), vectors.len()),
+                    retry_after: None,
+                });
+            }
+            let entries: Vec<(String, Vec<f32>)> = pending
+                .iter()
+                .zip(vectors)
+                .map(|(&i, v)| (keys[i].clone(), v))
+                .collect();
+            let cacheable = provider.as_ref().is_none_or(|p| *p == self.model);
+            if !cacheable {
+                debug!(provider = ?provider, model = %self.model, "向量由备用提供商生成，不写入缓存");
+            }
+            if cacheable {
+                let mut memory = self.memory.lock().unwrap();
+                for (key, vector) in &entries {
+                    memory.insert(key.clone(), vector.clone());
+                }
+            }
+            if let (Some(store), true) = (&self.store, cacheable) {
+                if let Err(e) = store.put(&entries).await {
+                    warn!(error = %e, "写入向量缓存失败");
+                }
+            }
+            for (&i, (_, vector)) in pending.iter().zip(entries) {
+                out[i] = Some(vector);
+            }
+        }
+
+        // 重复文本取首次出现的结果
+        Ok(keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| match &out[i] {
+                Some(v) => v.clone(),
+                None => out[seen[key.as_str()]].clone().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 统计下游收到的输入条数
+    #[derive(Default)]
+    struct CountingEmbed {
+        inputs: AtomicU64,
+    }
+
+    #[async_trait]
+    impl EmbedModel for CountingEmbed {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.inputs.fetch_add(texts.len() as u64, Ordering::Relaxed);
+            Ok(texts.iter().map(|t| vec![t.len() as f32]).collect())
+        }
+    }
+
+    #[derive(Default)]
+    struct MapStore(Mutex<HashMap<String, Vec<f32>>>);
+
+    #[async_trait]
+    impl EmbeddingStore for MapStore {
+        async fn get(&self, keys: &[String]) -> Result<Vec<Option<Vec<f32>>>> {
+            let map = self.0.lock().unwrap();
+            Ok(keys.iter().map(|k| map.get(k).cloned()).collect())
+        }
+
+        async fn put(&self, entries: &[(String, Vec<f32>)]) -> Result<()> {
+            self.0.lock().unwrap().extend(entries.iter().cloned());
+            Ok(())
+        }
+    }
+
+    fn texts(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_only_misses_reach_inner_model() {
+        let inner = Arc::new(CountingEmbed::default());
+        let cache = CachedEmbedModel::new(inner.clone(), "m", 16);
+        let first = cache.embed(&texts(&["a", "bb", "a"])).await.unwrap();
+        assert_eq!(first, vec![vec![1.0], vec![2.0], vec![1.0]]);
+        assert_eq!(inner.inputs.load(Ordering::Relaxed), 2);
+
+        let second = cache.embed(&texts(&["bb", "ccc"])).await.unwrap();
+        assert_eq!(second, vec![vec![2.0], vec![3.0]]);
+        assert_eq!(inner.inputs.load(Ordering::Relaxed), 3);
+        let stats = cache.stats();
+        assert_eq!((stats.memory_hits, stats.misses), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_store_survives_memory_eviction() {
+        let inner = Arc::new(CountingEmbed::default());
+        let store = Arc::new(MapStore::default());
+        let cache = CachedEmbedModel::new(inner.clone(), "m", 1).with_store(store.clone());
+        cache.embed(&texts(&["a", "bb"])).await.unwrap();
+        assert_eq!(cache.stats().entries, 1);
+
+        // 新实例（模拟重启）从持久化层读取
+        let restarted = CachedEmbedModel::new(inner.clone(), "m", 1).with_store(store);
+        restarted.embed(&texts(&["a", "bb"])).await.unwrap();
+        assert_eq!(inner.inputs.load(Ordering::Relaxed), 2);
+        assert_eq!(restarted.stats().store_hits, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_vectors_are_not_cached() {
+        let primary = Arc::new(CountingEmbed::default());
+        let chain = crate::FallbackEmbedModel::new(
+            vec![("backup".to_string(), primary.clone() as Arc<dyn EmbedModel>)],
+            None,
+        )
+        .unwrap();
+        let store = Arc::new(MapStore::default());
+        let cache = CachedEmbedModel::new(Arc::new(chain), "primary", 16).with_store(store.clone());
+        cache.embed(&texts(&["a"])).await.unwrap();
+        cache.embed(&texts(&["a"])).await.unwrap();
+        assert_eq!(primary.inputs.load(Ordering::Relaxed), 2);
+        assert_eq!(cache.stats().entries, 0);
+        assert!(store.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_key_includes_model_name() {
+        let inner: Arc<dyn EmbedModel> = Arc::new(CountingEmbed::default());
+        let a = CachedEmbedModel::new(inner.clone(), "model-a", 1);
+        let b = CachedEmbedModel::new(inner, "model-b", 1);
+        assert_ne!(a.cache_key("text"), b.cache_key("text"));
+        assert_eq!(a.cache_key("text"), a.cache_key("text"));
+    }
+}
@@ -0,0 +1,421 @@
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::{ChatDeltaStream, ChatModel, ChatRequest, ChatResponse, ChatStream, EmbedModel};
+
+/// 按优先级排列的提供商，记录每个提供商实际服务的调用次数
+struct Chain<M: ?Sized> {
+    providers: Vec<(String, Arc<M>)>,
+    served: Mutex<HashMap<String, u64>>,
+    /// 已停用的提供商，调用时跳过
+    disabled: Mutex<HashSet<String>>,
+}
+
+impl<M: ?Sized> Chain<M> {
+    fn new(providers: Vec<(String, Arc<M>)>) -> Result<Self> {
+        if providers.is_empty() {
+            return Err(KbError::Configuration {
+                key: "fallback.providers".to_string(),
+                reason: "at least one provider is required".to_string(),
+            });
+        }
+        Ok(Self {
+            providers,
+            served: Mutex::new(HashMap::new()),
+            disabled: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn disable(&self, name: &str) {
+        self.disabled.lock().unwrap().insert(name.to_string());
+    }
+
+    fn is_disabled(&self, name: &str) -> bool {
+        self.disabled.lock().unwrap().contains(name)
+    }
+
+    fn record(&self, name: &str) {
+        *self
+            .served
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += 1;
+    }
+
+    fn served_counts(&self) -> HashMap<String, u64> {
+        self.served.lock().unwrap().clone()
+    }
+
+    /// 依次尝试各提供商：可重试错误切换到下一个，不可重试错误直接返回
+    async fn run<'a, T, F, Fut>(&'a self, operation: &str, mut f: F) -> Result<(T, &'a str)>
+    where
+        F: FnMut(&'a Arc<M>) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut last_err = None;
+        for (i, (name, provider)) in self.providers.iter().enumerate() {
+            if self.is_disabled(name) {
+                continue;
+            }
+            match f(provider).await {
+                Ok(v) => {
+                    self.record(name);
+                    if i > 0 {
+                        info!(provider = %name, operation, "故障转移：由备用提供商完成调用");
+                    }
+                    return Ok((v, name.as_str()));
+                }
+                Err(e) if e.is_retryable() => {
+                    warn!(provider = %name, operation, error = %e, "提供商调用失败，尝试下一个");
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| KbError::ServiceUnavailable {
+            service: format!("fallback.{}", operation),
+            retry_after: None,
+        }))
+    }
+}
+
+/// 对话模型故障转移链
+pub struct FallbackChatModel {
+    chain: Chain<dyn ChatModel>,
+}
+
+impl FallbackChatModel {
+    pub fn new(providers: Vec<(String, Arc<dyn ChatModel>)>) -> Result<Self> {
+        Ok(Self {
+            chain: Chain::new(providers)?,
+        })
+    }
+
+    /// 各提供商实际服务的调用次数
+    pub fn served_counts(&self) -> HashMap<String, u64> {
+        self.chain.served_counts()
+    }
+}
+
+#[async_trait]
+impl ChatModel for FallbackChatModel {
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        let (mut resp, name) = self.chain.run("complete", |m| m.complete(req)).await?;
+        if resp.provider.is_none() {
+            resp.provider = Some(name.to_string());
+        }
+        Ok(resp)
+    }
+
+    /// 仅在建立流之前故障转移，流开始后的错误直接透传
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        let (stream, _) = self
+            .chain
+            .run("complete_stream", |m| m.complete_stream(req))
+            .await?;
+        Ok(stream)
+    }
+
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        let (stream, _) = self
+            .chain
+            .run("complete_stream_with_tools", |m| {
+                m.complete_stream_with_tools(req)
+            })
+            .await?;
+        Ok(stream)
+    }
+
+    /// 至少一个提供商可用即视为通过，其余仅记录告警
+    async fn verify(&self) -> Result<()> {
+        let mut last_err = None;
+        for (name, provider) in &self.chain.providers {
+            if let Err(e) = provider.verify().await {
+                warn!(provider = %name, error = %e, "提供商启动校验失败");
+                last_err = Some(e);
+            } else {
+                return Ok(());
+            }
+        }
+        Err(last_err.expect("fallback chain is never empty"))
+    }
+}
+
+/// 向量模型故障转移链，只在维度一致的模型之间切换
+pub struct FallbackEmbedModel {
+    chain: Chain<dyn EmbedModel>,
+    dimension: Mutex<Option<usize>>,
+}
+
+impl FallbackEmbedModel {
+    /// `dimension` 为配置声明的向量维度，为空时以启动校验得到的维度为准
+    pub fn new(
+        providers: Vec<(String, Arc<dyn EmbedModel>)>,
+        dimension: Option<usize>,
+    ) -> Result<Self> {
+        Ok(Self {
+            chain: Chain::new(providers)?,
+            dimension: Mutex::new(dimension),
+        })
+    }
+
+    pub fn served_counts(&self) -> HashMap<String, u64> {
+        self.chain.served_counts()
+    }
+
+    pub fn dimension(&self) -> Option<usize> {
+        *self.dimension.lock().unwrap()
+    }
+
+    /// 启动时校验：逐个探测各提供商的向量维度，不一致则拒绝启动
+    ///
+    /// 探测失败（如网络不可达）的提供商维度无法确认，在本进程内停用，恢复后需重启才会重新启用。
+    pub async fn verify_dimensions(&self) -> Result<usize> {
+        let probe = vec!["dimension probe".to_string()];
+        let mut expected = self.dimension();
+        let mut expected_from = "config".to_string();
+        for (name, provider) in &self.chain.providers {
+            if let Err(e) = provider.verify().await {
+                warn!(provider = %name, error = %e, "提供商启动校验失败，已停用");
+                self.chain.disable(name);
+                continue;
+            }
+            let dim = match provider.embed(&probe).await {
+                Ok(v) => v.first().map(|e| e.len()).unwrap_or(0),
+                Err(e) => {
+                    warn!(provider = %name, error = %e, "向量维度探测失败，已停用");
+                    self.chain.disable(name);
+                    continue;
+                }
+            };
+            match expected {
+                Some(d) if d != dim => {
+                    return Err(KbError::Configuration {
+                        key: "embedding_provider.providers".to_string(),
+                        reason: format!(
+                            "embedding dimension mismatch: {} returns {}, but {} has {}",
+                            name, dim, expected_from, d
+                        ),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    expected = Some(dim);
+                    expected_from = name.clone();
+                }
+            }
+        }
+        let dim = expected.ok_or_else(|| KbError::Configuration {
+            key: "embedding_provider.providers".to_string(),
+            reason: "unable to determine embedding dimension from any provider".to_string(),
+        })?;
+        *self.dimension.lock().unwrap() = Some(dim);
+        info!(dimension = dim, "向量模型维度校验通过");
+        Ok(dim)
+    }
+}
+
+#[async_trait]
+impl EmbedModel for FallbackEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(self.embed_with_provider(texts).await?.0)
+    }
+
+    async fn embed_with_provider(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Option<String>)> {
+        let expected = self.dimension();
+        let (vectors, name) = self
+            .chain
+            .run("embed", |m| async move {
+                let vectors = m.embed(texts).await?;
+                // 维度不符的结果不能混入同一向量空间，按可重试错误处理以切换到下一个提供商
+                let mismatch = expected
+                    .and_then(|d| vectors.iter().find(|v| v.len() != d).map(|v| (d, v.len())));
+                if let Some((want, got)) = mismatch {
+                    return Err(KbError::EmbeddingService {
+                        provider: "fallback".to_string(),
+                        message: format!("dimension mismatch: expected {}, got {}", want, got),
+                        retry_after: Some(std::time::Duration::ZERO),
+                    });
+                }
+                Ok(vectors)
+            })
+            .await?;
+        Ok((vectors, Some(name.to_string())))
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.verify_dimensions().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatMessage;
+    use std::time::Duration;
+
+    enum Behavior {
+        Ok(&'static str),
+        Transient,
+        Fatal,
+    }
+
+    struct MockChat(Behavior);
+
+    #[async_trait]
+    impl ChatModel for MockChat {
+        async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+            match self.0 {
+                Behavior::Ok(text) => Ok(ChatResponse {
+                    content: text.to_string(),
+                    ..Default::default()
+                }),
+                Behavior::Transient => Err(KbError::LlmService {
+                    provider: "mock".to_string(),
+                    message: "503".to_string(),
+                    retry_after: Some(Duration::ZERO),
+                }),
+                Behavior::Fatal => Err(KbError::LlmService {
+                    provider: "mock".to_string(),
+                    message: "401".to_string(),
+                    retry_after: None,
+                }),
+            }
+        }
+    }
+
+    fn chain(behaviors: Vec<(&str, Behavior)>) -> FallbackChatModel {
+        FallbackChatModel::new(
+            behaviors
+                .into_iter()
+                .map(|(n, b)| (n.to_string(), Arc::new(MockChat(b)) as Arc<dyn ChatModel>))
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn req() -> ChatRequest {
+        ChatRequest::new(vec![ChatMessage::user("hi")])
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_on_retryable_error() {
+        let model = chain(vec![
+            ("primary", Behavior::Transient),
+            ("secondary", Behavior::Ok("from secondary")),
+        ]);
+        let resp = model.complete(&req()).await.unwrap();
+        assert_eq!(resp.content, "from secondary");
+        assert_eq!(resp.provider.as_deref(), Some("secondary"));
+        assert_eq!(model.served_counts().get("secondary"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_stops_on_non_retryable_error() {
+        let model = chain(vec![
+            ("primary", Behavior::Fatal),
+            ("secondary", Behavior::Ok("unused")),
+        ]);
+        assert!(model.complete(&req()).await.is_err());
+        assert!(model.served_counts().is_empty());
+    }
+
+    struct MockEmbed(usize);
+
+    #[async_trait]
+    impl EmbedModel for MockEmbed {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            Ok(vec![vec![0.0; self.0]; texts.len()])
+        }
+    }
+
+    fn embed_chain(dims: &[usize]) -> FallbackEmbedModel {
+        FallbackEmbedModel::new(
+            dims.iter()
+                .enumerate()
+                .map(|(i, d)| {
+                    (
+                        format!("m{}", i),
+                        Arc::new(MockEmbed(*d)) as Arc<dyn EmbedModel>,
+                    )
+                })
+                .collect(),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_dimensions() {
+        assert_eq!(embed_chain(&[3, 3]).verify_dimensions().await.unwrap(), 3);
+        let err = embed_chain(&[3, 4]).verify_dimensions().await.unwrap_err();
+        assert!(matches!(err, KbError::Configuration { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_embed_skips_provider_with_wrong_dimension() {
+        let model = FallbackEmbedModel::new(
+            vec![
+                (
+                    "small".to_string(),
+                    Arc::new(MockEmbed(2)) as Arc<dyn EmbedModel>,
+                ),
+                (
+                    "large".to_string(),
+                    Arc::new(MockEmbed(4)) as Arc<dyn EmbedModel>,
+                ),
+            ],
+            Some(4),
+        )
+        .unwrap();
+        let (out, provider) = model.embed_with_provider(&["x".to_string()]).await.unwrap();
+        assert_eq!(out[0].len(), 4);
+        assert_eq!(provider.as_deref(), Some("large"));
+        assert_eq!(model.served_counts().get("large"), Some(&1));
+    }
+
+    /// 首次调用（启动探测）失败，之后正常返回
+    struct FlakyEmbed(std::sync::atomic::AtomicBool);
+
+    #[async_trait]
+    impl EmbedModel for FlakyEmbed {
+        async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            if !self.0.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                return Err(KbError::EmbeddingService {
+                    provider: "mock".to_string(),
+                    message: "503".to_string(),
+                    retry_after: Some(Duration::ZERO),
+                });
+            }
+            Ok(vec![vec![0.0; 3]; texts.len()])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_provider_is_disabled() {
+        let model = FallbackEmbedModel::new(
+            vec![
+                (
+                    "flaky".to_string(),
+                    Arc::new(FlakyEmbed(Default::default())) as Arc<dyn EmbedModel>,
+                ),
+                (
+                    "stable".to_string(),
+                    Arc::new(MockEmbed(3)) as Arc<dyn EmbedModel>,
+                ),
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(model.verify_dimensions().await.unwrap(), 3);
+        let (_, provider) = model.embed_with_provider(&["x".to_string()]).await.unwrap();
+        assert_eq!(provider.as_deref(), Some("stable"));
+    }
+}
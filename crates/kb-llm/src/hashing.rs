@@ -0,0 +1,139 @@
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+
+use crate::EmbedModel;
+
+/// 默认向量维度
+pub const HASHING_DEFAULT_DIMENSION: usize = 256;
+
+// ========== Feature Hashing（离线开发与测试） ==========
+
+/// 确定性的特征哈希向量模型：字符 n-gram 经哈希映射到固定维度后做 L2 归一化
+///
+/// 无需网络与密钥，相同文本在任意进程中得到相同向量，词面相近的文本相似度更高，
+/// 适合离线开发与 CI 中断言检索排序；不具备语义理解能力，不应用于生产检索。
+#[derive(Debug, Clone)]
+pub struct HashingEmbedModel {
+    dimension: usize,
+    min_n: usize,
+    max_n: usize,
+}
+
+impl Default for HashingEmbedModel {
+    fn default() -> Self {
+        Self {
+            dimension: HASHING_DEFAULT_DIMENSION,
+            min_n: 1,
+            max_n: 3,
+        }
+    }
+}
+
+impl HashingEmbedModel {
+    pub fn new(dimension: usize) -> Result<Self> {
+        if dimension == 0 {
+            return Err(KbError::Configuration {
+                key: "hashing.dimension".to_string(),
+                reason: "dimension must be greater than 0".to_string(),
+            });
+        }
+        Ok(Self {
+            dimension,
+            ..Default::default()
+        })
+    }
+
+    /// 设置字符 n-gram 的长度范围（含两端），默认 1..=3
+    pub fn with_ngram_range(mut self, min_n: usize, max_n: usize) -> Self {
+        self.min_n = min_n.max(1);
+        self.max_n = max_n.max(self.min_n);
+        self
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// 计算单条文本的向量
+    pub fn embed_one(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimension];
+        let normalized = text.to_lowercase();
+        // 按空白切词后在词内取 n-gram，词首尾补空格以区分词边界
+        for word in normalized.split_whitespace() {
+            let chars: Vec<char> = std::iter::once(' ')
+                .chain(word.chars())
+                .chain(std::iter::once(' '))
+                .collect();
+            for n in self.min_n..=self.max_n {
+                for gram in chars.windows(n) {
+                    if gram.iter().all(|c| *c == ' ') {
+                        continue;
+                    }
+                    let hash = fnv1a(gram);
+                    let index = (hash % self.dimension as u64) as usize;
+                    // 用高位决定符号，使哈希冲突在期望上相互抵消
+                    let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+                    vector[index] += sign;
+                }
+            }
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+        vector
+    }
+}
+
+/// FNV-1a 64 位哈希，结果不随 Rust 版本或进程变化
+fn fnv1a(chars: &[char]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut buf = [0u8; 4];
+    for c in chars {
+        for b in c.encode_utf8(&mut buf).bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[async_trait]
+impl EmbedModel for HashingEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|t| self.embed_one(t)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[test]
+    fn test_deterministic_and_normalized() {
+        let model = HashingEmbedModel::new(64).unwrap();
+        let a = model.embed_one("Rust 异步运行时");
+        assert_eq!(a, model.embed_one("Rust 异步运行时"));
+        assert_eq!(a.len(), 64);
+        assert!((cosine(&a, &a) - 1.0).abs() < 1e-5);
+        assert!(model.embed_one("   ").iter().all(|v| *v == 0.0));
+    }
+
+    #[test]
+    fn test_similar_texts_rank_higher() {
+        let model = HashingEmbedModel::default();
+        let query = model.embed_one("向量数据库索引");
+        let related = model.embed_one("向量数据库的索引结构");
+        let unrelated = model.embed_one("今天天气晴朗");
+        assert!(cosine(&query, &related) > cosine(&query, &unrelated));
+    }
+
+    #[test]
+    fn test_rejects_zero_dimension() {
+        assert!(HashingEmbedModel::new(0).is_err());
+    }
+}
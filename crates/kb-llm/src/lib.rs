@@ -1,18 +1,303 @@
+pub mod batch;
+pub mod cache;
+pub mod fallback;
+pub mod hashing;
+mod message;
+pub mod ollama;
+pub mod prompt;
+pub mod ratelimit;
+pub mod registry;
+pub mod retry;
+mod sse;
+pub mod structured;
+pub mod tokens;
+pub mod usage;
+
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
 use tracing::instrument;
 
+pub use batch::{BatchEmbedModel, EmbedBatchConfig, OversizedInput};
+pub use cache::{CachedEmbedModel, EmbedCacheStats, EmbeddingStore};
+pub use fallback::{FallbackChatModel, FallbackEmbedModel};
+pub use hashing::HashingEmbedModel;
 pub use kb_error::{KbError, Result};
+pub use message::{
+    ChatDelta, ChatMessage, ChatRequest, ChatResponse, ResponseFormat, Role, ToolCall, ToolChoice,
+    ToolDefinition,
+};
+pub use ollama::{OllamaClient, OllamaConfig};
+pub use prompt::{
+    current_prompt, with_prompt, PromptConfig, PromptRegistry, PromptTemplate, RenderedPrompt,
+};
+pub use ratelimit::{
+    with_priority, Priority, RateLimitConfig, RateLimitedChatModel, RateLimitedEmbedModel,
+    RateLimiter,
+};
+pub use registry::{ModelInfo, ModelRegistry};
+pub use retry::{CircuitBreakerConfig, RetryChatModel, RetryConfig, RetryEmbedModel};
+pub use structured::{chat_json, chat_json_with};
+pub use tokens::estimate_tokens;
+pub use usage::{
+    collect_usage, ModelPrice, PriceTable, QuotaConfig, QuotaLimit, TokenUsage, UsageLedger,
+    UsageReport, UsageSnapshot, UsageTotals,
+};
+
+/// 流式生成的增量文本流
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+/// 带工具调用的流式生成增量流
+pub type ChatDeltaStream = Pin<Box<dyn Stream<Item = Result<ChatDelta>> + Send>>;
 
 #[async_trait]
 pub trait ChatModel: Send + Sync {
-    async fn chat(&self, system: &str, context: &str, user: &str) -> Result<String>;
+    /// 多轮对话补全
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse>;
+
+    /// 多轮对话流式补全，逐段返回增量文本
+    ///
+    /// 默认实现等待完整回答后一次性返回，不支持流式的提供商无需覆盖。
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        let text = self.complete(req).await?.content;
+        Ok(Box::pin(futures::stream::once(async move { Ok(text) })))
+    }
+
+    /// 支持工具调用的流式补全：文本增量逐段返回，工具调用在参数完整后整体返回
+    ///
+    /// 默认实现在未声明工具时转发 `complete_stream`，否则等待完整回答后依次返回文本与工具调用。
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        if req.tools.is_empty() {
+            let stream = self.complete_stream(req).await?;
+            return Ok(Box::pin(stream.map(|r| r.map(ChatDelta::Text))));
+        }
+        Ok(response_deltas(self.complete(req).await?))
+    }
+
+    /// 单轮问答：system + 检索上下文 + 用户问题
+    async fn chat(&self, system: &str, context: &str, user: &str) -> Result<String> {
+        let req = ChatRequest::from_prompt(system, context, user);
+        Ok(self.complete(&req).await?.content)
+    }
+
+    /// 单轮问答的流式版本
+    async fn chat_stream(&self, system: &str, context: &str, user: &str) -> Result<ChatStream> {
+        let req = ChatRequest::from_prompt(system, context, user);
+        self.complete_stream(&req).await
+    }
+
+    /// 启动校验（如本地模型是否可用），默认无需校验
+    async fn verify(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
 pub trait EmbedModel: Send + Sync {
     async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    /// 向量化并返回实际完成调用的提供商（故障转移链中记录），单一提供商返回 `None`
+    ///
+    /// 不同提供商的向量不在同一空间，缓存等调用方据此区分；包装层应转发此方法。
+    async fn embed_with_provider(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Option<String>)> {
+        Ok((self.embed(texts).await?, None))
+    }
+
+    /// 启动校验（如故障转移链的维度一致性），默认无需校验
+    async fn verify(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// 将非 2xx 响应转换为 LLM 错误：429/5xx 视为可重试并携带 `Retry-After`
+async fn status_error(provider: &str, resp: reqwest::Response) -> KbError {
+    let (message, retry_after) = read_status_error(resp).await;
+    KbError::LlmService {
+        provider: provider.to_string(),
+        message,
+        retry_after,
+    }
+}
+
+/// 同 [`status_error`]，用于向量接口
+async fn embed_status_error(provider: &str, resp: reqwest::Response) -> KbError {
+    let (message, retry_after) = read_status_error(resp).await;
+    KbError::EmbeddingService {
+        provider: provider.to_string(),
+        message,
+        retry_after,
+    }
+}
+
+/// 补全缺失的用量（按文本估算）并计入当前请求
+fn finish_chat(model: &str, req: &ChatRequest, mut resp: ChatResponse) -> ChatResponse {
+    let usage = resp
+        .usage
+        .unwrap_or_else(|| TokenUsage::estimate_chat(req, &resp.content));
+    resp.usage = Some(usage);
+    usage::record(model, usage);
+    resp
+}
+
+/// 流结束时按已输出文本估算用量并计入当前请求（流式响应不含用量）
+fn metered_stream(model: &str, req: &ChatRequest, inner: ChatStream) -> ChatStream {
+    metered(model, req, inner, |text| estimate_tokens(text))
+}
+
+/// 同 [`metered_stream`]，用于带工具调用的增量流，工具调用按名称与参数估算
+fn metered_delta_stream(model: &str, req: &ChatRequest, inner: ChatDeltaStream) -> ChatDeltaStream {
+    metered(model, req, inner, |delta| match delta {
+        ChatDelta::Text(text) => estimate_tokens(text),
+        ChatDelta::ToolCall(call) => {
+            estimate_tokens(&call.name) + estimate_tokens(&call.arguments.to_string())
+        }
+    })
+}
+
+#[allow(clippy::type_complexity)]
+fn metered<T: Send + 'static>(
+    model: &str,
+    req: &ChatRequest,
+    mut inner: Pin<Box<dyn Stream<Item = Result<T>> + Send>>,
+    tokens: fn(&T) -> usize,
+) -> Pin<Box<dyn Stream<Item = Result<T>> + Send>> {
+    let model = model.to_string();
+    let mut usage = TokenUsage::estimate_chat(req, "");
+    let mut recorded = false;
+    Box::pin(futures::stream::poll_fn(move |cx| {
+        let item = inner.poll_next_unpin(cx);
+        match &item {
+            std::task::Poll::Ready(Some(Ok(value))) => {
+                usage.completion_tokens += tokens(value) as u64;
+            }
+            std::task::Poll::Ready(None) if !recorded => {
+                recorded = true;
+                usage::record(&model, usage);
+            }
+            _ => {}
+        }
+        item
+    }))
+}
+
+/// 将完整响应转换为增量流：先返回文本，再依次返回工具调用
+fn response_deltas(resp: ChatResponse) -> ChatDeltaStream {
+    let text = (!resp.content.is_empty()).then_some(ChatDelta::Text(resp.content));
+    let deltas: Vec<Result<ChatDelta>> = text
+        .into_iter()
+        .chain(resp.tool_calls.into_iter().map(ChatDelta::ToolCall))
+        .map(Ok)
+        .collect();
+    Box::pin(futures::stream::iter(deltas))
+}
+
+/// 按序号累积流式响应中的工具调用片段（ID、名称与分段到达的参数），流结束后组装为完整调用
+#[derive(Default)]
+pub(crate) struct ToolCallAccumulator {
+    calls: std::collections::BTreeMap<usize, (String, String, String)>,
+}
+
+impl ToolCallAccumulator {
+    pub(crate) fn push(
+        &mut self,
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: &str,
+    ) {
+        let (call_id, call_name, args) = self.calls.entry(index).or_default();
+        if let Some(id) = id.filter(|s| !s.is_empty()) {
+            *call_id = id;
+        }
+        if let Some(name) = name.filter(|s| !s.is_empty()) {
+            *call_name = name;
+        }
+        args.push_str(arguments);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// 未返回调用 ID 的提供商按序号生成，与非流式响应保持一致
+    pub(crate) fn finish(self) -> Vec<ToolCall> {
+        self.calls
+            .into_iter()
+            .map(|(index, (id, name, args))| ToolCall {
+                id: if id.is_empty() {
+                    format!("call_{}", index)
+                } else {
+                    id
+                },
+                name,
+                arguments: parse_tool_arguments(&args),
+            })
+            .collect()
+    }
+}
+
+/// 将提供商的原始负载流解析为增量流：`parse` 返回文本增量并把工具调用片段写入累积器，
+/// 原始流结束后依次返回累积的工具调用
+fn delta_stream<S, F>(payloads: S, parse: F) -> ChatDeltaStream
+where
+    S: Stream<Item = Result<String>> + Send + 'static,
+    F: FnMut(&mut ToolCallAccumulator, &str) -> Result<Option<String>> + Send + 'static,
+{
+    let state = (
+        Box::pin(payloads),
+        parse,
+        ToolCallAccumulator::default(),
+        None::<std::vec::IntoIter<ToolCall>>,
+    );
+    Box::pin(futures::stream::unfold(
+        state,
+        |(mut payloads, mut parse, mut acc, mut calls)| async move {
+            loop {
+                if let Some(pending) = calls.as_mut() {
+                    let call = pending.next()?;
+                    return Some((Ok(ChatDelta::ToolCall(call)), (payloads, parse, acc, calls)));
+                }
+                let item = match payloads.next().await {
+                    Some(Ok(data)) => match parse(&mut acc, &data) {
+                        Ok(Some(text)) => Ok(ChatDelta::Text(text)),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    },
+                    Some(Err(e)) => Err(e),
+                    None => {
+                        calls = Some(std::mem::take(&mut acc).finish().into_iter());
+                        continue;
+                    }
+                };
+                return Some((item, (payloads, parse, acc, calls)));
+            }
+        },
+    ))
+}
+
+/// 计入一次向量调用的用量，提供商未返回时按文本估算
+fn record_embedding(model: &str, texts: &[String], tokens: Option<u64>) {
+    let usage = tokens
+        .map(TokenUsage::embedding)
+        .unwrap_or_else(|| TokenUsage::estimate_embedding(texts));
+    usage::record(model, usage);
+}
+
+async fn read_status_error(resp: reqwest::Response) -> (String, Option<std::time::Duration>) {
+    let status = resp.status();
+    let transient = status.as_u16() == 429 || status.is_server_error();
+    // 可重试错误的 retry_after 必须为 Some，未给出 Retry-After 时由退避策略决定等待时间
+    let retry_after =
+        transient.then(|| retry::retry_after_from_headers(resp.headers()).unwrap_or_default());
+    let txt = resp.text().await.unwrap_or_default();
+    (format!("status={} body={}", status, txt), retry_after)
 }
 
 // ========== OpenAI-compatible (covers OpenAI, DeepSeek, some Qwen proxies) ==========
@@ -41,22 +326,181 @@ impl OpenAiCompatClient {
 }
 
 #[derive(Serialize)]
-struct OaiChatReqMsg {
-    role: String,
-    content: String,
+struct OaiFunctionCall<'a> {
+    name: &'a str,
+    arguments: String,
 }
 
 #[derive(Serialize)]
-struct OaiChatReq {
-    model: String,
-    messages: Vec<OaiChatReqMsg>,
+struct OaiToolCall<'a> {
+    id: &'a str,
+    r#type: &'static str,
+    function: OaiFunctionCall<'a>,
+}
+
+#[derive(Serialize)]
+struct OaiChatReqMsg<'a> {
+    role: &'static str,
+    content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OaiToolCall<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct OaiFunctionDef<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct OaiTool<'a> {
+    r#type: &'static str,
+    function: OaiFunctionDef<'a>,
+}
+
+#[derive(Serialize)]
+struct OaiChatReq<'a> {
+    model: &'a str,
+    messages: Vec<OaiChatReqMsg<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OaiTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+impl<'a> OaiChatReq<'a> {
+    fn new(model: &'a str, req: &'a ChatRequest, stream: bool) -> Self {
+        let messages = req
+            .messages
+            .iter()
+            .map(|m| OaiChatReqMsg {
+                role: m.role.as_str(),
+                // 仅含工具调用的助手消息 content 置空
+                content: (!m.content.is_empty() || m.tool_calls.is_empty()).then_some(&m.content),
+                tool_calls: m
+                    .tool_calls
+                    .iter()
+                    .map(|c| OaiToolCall {
+                        id: &c.id,
+                        r#type: "function",
+                        function: OaiFunctionCall {
+                            name: &c.name,
+                            arguments: c.arguments.to_string(),
+                        },
+                    })
+                    .collect(),
+                tool_call_id: m.tool_call_id.as_deref(),
+            })
+            .collect();
+        let tools = req
+            .tools
+            .iter()
+            .map(|t| OaiTool {
+                r#type: "function",
+                function: OaiFunctionDef {
+                    name: &t.name,
+                    description: &t.description,
+                    parameters: &t.parameters,
+                },
+            })
+            .collect();
+        let tool_choice = req.tool_choice.as_ref().map(|c| match c {
+            ToolChoice::Auto => serde_json::json!("auto"),
+            ToolChoice::None => serde_json::json!("none"),
+            ToolChoice::Required => serde_json::json!("required"),
+            ToolChoice::Tool { name } => {
+                serde_json::json!({"type": "function", "function": {"name": name}})
+            }
+        });
+        let response_format = req.response_format.as_ref().map(|f| match f {
+            ResponseFormat::JsonObject => serde_json::json!({"type": "json_object"}),
+            ResponseFormat::JsonSchema { name, schema } => serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": name, "schema": schema},
+            }),
+        });
+        Self {
+            model,
+            messages,
+            temperature: req.temperature,
+            max_tokens: req.max_tokens,
+            stop: &req.stop,
+            tools,
+            tool_choice,
+            response_format,
+            stream,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OaiRespFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct OaiRespToolCall {
+    id: String,
+    function: OaiRespFunctionCall,
 }
 
 #[derive(Deserialize)]
 struct OaiChatRespChoiceMsg {
-    content: String,
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OaiRespToolCall>,
+}
+
+/// 工具参数按 JSON 解析，模型偶尔输出非法 JSON 时保留原始字符串交由调用方处理
+fn parse_tool_arguments(arguments: &str) -> serde_json::Value {
+    if arguments.trim().is_empty() {
+        return serde_json::json!({});
+    }
+    serde_json::from_str(arguments)
+        .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()))
+}
+
+impl OaiChatResp {
+    fn into_response(self) -> ChatResponse {
+        let usage = self
+            .usage
+            .map(|u| TokenUsage::chat(u.prompt_tokens, u.completion_tokens));
+        let Some(choice) = self.choices.into_iter().next() else {
+            return ChatResponse {
+                usage,
+                ..Default::default()
+            };
+        };
+        ChatResponse {
+            content: choice.message.content.unwrap_or_default(),
+            tool_calls: choice
+                .message
+                .tool_calls
+                .into_iter()
+                .map(|c| ToolCall {
+                    id: c.id,
+                    name: c.function.name,
+                    arguments: parse_tool_arguments(&c.function.arguments),
+                })
+                .collect(),
+            usage,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -64,33 +508,57 @@ struct OaiChatRespChoice {
     message: OaiChatRespChoiceMsg,
 }
 
+#[derive(Deserialize)]
+struct OaiUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
 #[derive(Deserialize)]
 struct OaiChatResp {
     choices: Vec<OaiChatRespChoice>,
+    usage: Option<OaiUsage>,
 }
 
-#[async_trait]
-impl ChatModel for OpenAiCompatClient {
-    #[instrument(skip(self, system, context, user))]
-    async fn chat(&self, system: &str, context: &str, user: &str) -> Result<String> {
+#[derive(Deserialize)]
+struct OaiStreamFunctionCall {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OaiStreamToolCall {
+    index: usize,
+    id: Option<String>,
+    function: Option<OaiStreamFunctionCall>,
+}
+
+#[derive(Deserialize)]
+struct OaiStreamDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OaiStreamToolCall>,
+}
+
+#[derive(Deserialize)]
+struct OaiStreamChoice {
+    delta: OaiStreamDelta,
+}
+
+#[derive(Deserialize)]
+struct OaiStreamChunk {
+    choices: Vec<OaiStreamChoice>,
+}
+
+impl OpenAiCompatClient {
+    async fn send_chat(&self, req: &ChatRequest, stream: bool) -> Result<reqwest::Response> {
         let url = format!(
             "{}/v1/chat/completions",
             self.cfg.base_url.trim_end_matches('/')
         );
-        let body = OaiChatReq {
-            model: self.cfg.chat_model.clone(),
-            messages: vec![
-                OaiChatReqMsg {
-                    role: "system".into(),
-                    content: system.to_string(),
-                },
-                OaiChatReqMsg {
-                    role: "user".into(),
-                    content: format!("{}\n\nContext:\n{}", user, context),
-                },
-            ],
-            temperature: Some(0.2),
-        };
+        let body = OaiChatReq::new(&self.cfg.chat_model, req, stream);
 
         let resp = self
             .http
@@ -105,25 +573,68 @@ impl ChatModel for OpenAiCompatClient {
             })?;
 
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(KbError::LlmService {
-                provider: "openai_compat".to_string(),
-                message: format!("status={} body={}", status, txt),
-                retry_after: None,
-            });
+            return Err(status_error("openai_compat", resp).await);
+        }
+        Ok(resp)
+    }
+}
+
+/// 解析 OpenAI 兼容流式响应中的一条 `data:` 负载，返回其中的增量文本
+fn parse_oai_stream_data(data: &str) -> Result<Option<String>> {
+    parse_oai_stream_delta(&mut ToolCallAccumulator::default(), data)
+}
+
+/// 同 [`parse_oai_stream_data`]，并把工具调用片段（按 `index` 分段到达的参数）写入累积器
+fn parse_oai_stream_delta(acc: &mut ToolCallAccumulator, data: &str) -> Result<Option<String>> {
+    if data.trim() == "[DONE]" {
+        return Ok(None);
+    }
+    let chunk: OaiStreamChunk = serde_json::from_str(data)?;
+    let mut text = String::new();
+    for choice in chunk.choices {
+        text.push_str(choice.delta.content.as_deref().unwrap_or_default());
+        for call in choice.delta.tool_calls {
+            let (name, arguments) = call
+                .function
+                .map(|f| (f.name, f.arguments.unwrap_or_default()))
+                .unwrap_or_default();
+            acc.push(call.index, call.id, name, &arguments);
         }
+    }
+    Ok((!text.is_empty()).then_some(text))
+}
+
+#[async_trait]
+impl ChatModel for OpenAiCompatClient {
+    #[instrument(skip(self, req))]
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        let resp = self.send_chat(req, false).await?;
 
         let data: OaiChatResp = resp.json().await.map_err(|e| KbError::Network {
             operation: "http_request".to_string(),
             message: e.to_string(),
         })?;
-        let content = data
-            .choices
-            .get(0)
-            .map(|c| c.message.content.clone())
-            .unwrap_or_default();
-        Ok(content)
+        Ok(finish_chat(&self.cfg.chat_model, req, data.into_response()))
+    }
+
+    #[instrument(skip(self, req))]
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        let resp = self.send_chat(req, true).await?;
+        let stream = sse::sse_events(resp).filter_map(|ev| async move {
+            match ev {
+                Ok(ev) => parse_oai_stream_data(&ev.data).transpose(),
+                Err(e) => Some(Err(e)),
+            }
+        });
+        Ok(metered_stream(&self.cfg.chat_model, req, Box::pin(stream)))
+    }
+
+    #[instrument(skip(self, req))]
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        let resp = self.send_chat(req, true).await?;
+        let payloads = sse::sse_events(resp).map(|ev| ev.map(|ev| ev.data));
+        let stream = delta_stream(payloads, parse_oai_stream_delta);
+        Ok(metered_delta_stream(&self.cfg.chat_model, req, stream))
     }
 }
 
@@ -138,9 +649,16 @@ struct OaiEmbedData {
     embedding: Vec<f32>,
 }
 
+#[derive(Deserialize)]
+struct OaiEmbedUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+}
+
 #[derive(Deserialize)]
 struct OaiEmbedResp {
     data: Vec<OaiEmbedData>,
+    usage: Option<OaiEmbedUsage>,
 }
 
 #[async_trait]
@@ -157,7 +675,7 @@ impl EmbedModel for OpenAiCompatClient {
             })?;
         let url = format!("{}/v1/embeddings", self.cfg.base_url.trim_end_matches('/'));
         let body = OaiEmbedReq {
-            model,
+            model: model.clone(),
             input: texts.to_vec(),
         };
 
@@ -174,19 +692,14 @@ impl EmbedModel for OpenAiCompatClient {
             })?;
 
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(KbError::LlmService {
-                provider: "openai_compat".to_string(),
-                message: format!("status={} body={}", status, txt),
-                retry_after: None,
-            });
+            return Err(embed_status_error("openai_compat", resp).await);
         }
 
         let data: OaiEmbedResp = resp.json().await.map_err(|e| KbError::Network {
             operation: "http_request".to_string(),
             message: e.to_string(),
         })?;
+        record_embedding(&model, texts, data.usage.map(|u| u.prompt_tokens));
         Ok(data.data.into_iter().map(|d| d.embedding).collect())
     }
 }
@@ -216,54 +729,223 @@ impl AnthropicClient {
 }
 
 #[derive(Serialize)]
-struct AnthMessageContent {
-    r#type: &'static str,
-    text: String,
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthMessageContent<'a> {
+    Text {
+        text: &'a str,
+    },
+    ToolUse {
+        id: &'a str,
+        name: &'a str,
+        input: &'a serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: &'a str,
+        content: &'a str,
+    },
 }
 
 #[derive(Serialize)]
-struct AnthMessageReqMsg {
+struct AnthMessageReqMsg<'a> {
     role: &'static str,
-    content: Vec<AnthMessageContent>,
+    content: Vec<AnthMessageContent<'a>>,
 }
 
 #[derive(Serialize)]
-struct AnthMessageReq {
-    model: String,
-    messages: Vec<AnthMessageReqMsg>,
+struct AnthTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct AnthMessageReq<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthMessageReqMsg<'a>>,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+/// 未指定 max_tokens 时的默认输出上限（Anthropic 要求必填）
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 2048;
+
+/// 结构化输出对应的强制工具名，Anthropic 无 response_format，改为强制调用以 schema 为参数的工具
+fn anth_structured_tool(req: &ChatRequest) -> Option<(&str, &serde_json::Value)> {
+    match &req.response_format {
+        Some(ResponseFormat::JsonSchema { name, schema }) if req.tool_choice.is_none() => {
+            Some((name.as_str(), schema))
+        }
+        _ => None,
+    }
+}
+
+impl<'a> AnthMessageReq<'a> {
+    /// system 消息放入顶层 `system` 字段，工具结果作为 user 消息中的 `tool_result` 块，
+    /// 相邻同角色消息合并以满足 user/assistant 交替的要求
+    fn new(model: &'a str, req: &'a ChatRequest, stream: bool) -> Self {
+        let mut messages: Vec<AnthMessageReqMsg<'a>> = Vec::new();
+        for m in &req.messages {
+            let (role, mut blocks) = match m.role {
+                Role::System => continue,
+                Role::User => ("user", vec![AnthMessageContent::Text { text: &m.content }]),
+                Role::Assistant => {
+                    let text = (!m.content.is_empty() || m.tool_calls.is_empty())
+                        .then_some(AnthMessageContent::Text { text: &m.content });
+                    let tool_uses = m.tool_calls.iter().map(|c| AnthMessageContent::ToolUse {
+                        id: &c.id,
+                        name: &c.name,
+                        input: &c.arguments,
+                    });
+                    ("assistant", text.into_iter().chain(tool_uses).collect())
+                }
+                Role::Tool => (
+                    "user",
+                    vec![AnthMessageContent::ToolResult {
+                        tool_use_id: m.tool_call_id.as_deref().unwrap_or_default(),
+                        content: &m.content,
+                    }],
+                ),
+            };
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.append(&mut blocks),
+                _ => messages.push(AnthMessageReqMsg {
+                    role,
+                    content: blocks,
+                }),
+            }
+        }
+        let mut tools: Vec<AnthTool<'a>> = req
+            .tools
+            .iter()
+            .map(|t| AnthTool {
+                name: &t.name,
+                description: &t.description,
+                input_schema: &t.parameters,
+            })
+            .collect();
+        let mut tool_choice = req.tool_choice.as_ref().map(|c| match c {
+            ToolChoice::Auto => serde_json::json!({"type": "auto"}),
+            ToolChoice::None => serde_json::json!({"type": "none"}),
+            ToolChoice::Required => serde_json::json!({"type": "any"}),
+            ToolChoice::Tool { name } => serde_json::json!({"type": "tool", "name": name}),
+        });
+        if let Some((name, schema)) = anth_structured_tool(req) {
+            tools.push(AnthTool {
+                name,
+                description: "Return the final answer as structured output.",
+                input_schema: schema,
+            });
+            tool_choice = Some(serde_json::json!({"type": "tool", "name": name}));
+        }
+        Self {
+            model,
+            system: req.system_prompt(),
+            messages,
+            max_tokens: req.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+            temperature: req.temperature,
+            stop_sequences: &req.stop,
+            tools,
+            tool_choice,
+            stream,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct AnthMessageRespContent {
-    #[allow(dead_code)]
     r#type: String,
     text: Option<String>,
+    id: Option<String>,
+    name: Option<String>,
+    input: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct AnthUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
 }
 
 #[derive(Deserialize)]
 struct AnthMessageResp {
     content: Vec<AnthMessageRespContent>,
+    usage: Option<AnthUsage>,
 }
 
-#[async_trait]
-impl ChatModel for AnthropicClient {
-    #[instrument(skip(self, system, context, user))]
-    async fn chat(&self, system: &str, context: &str, user: &str) -> Result<String> {
+#[derive(Deserialize)]
+struct AnthStreamDelta {
+    text: Option<String>,
+    partial_json: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnthStreamError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct AnthStreamEvent {
+    r#type: String,
+    index: Option<usize>,
+    content_block: Option<AnthMessageRespContent>,
+    delta: Option<AnthStreamDelta>,
+    error: Option<AnthStreamError>,
+}
+
+/// 解析 Anthropic 流式事件，只关心文本增量与错误事件
+fn parse_anth_stream_data(data: &str) -> Result<Option<String>> {
+    parse_anth_stream_delta(&mut ToolCallAccumulator::default(), data)
+}
+
+/// 同 [`parse_anth_stream_data`]，并把 `tool_use` 块及其分段到达的参数写入累积器
+fn parse_anth_stream_delta(acc: &mut ToolCallAccumulator, data: &str) -> Result<Option<String>> {
+    let ev: AnthStreamEvent = serde_json::from_str(data)?;
+    let index = ev.index.unwrap_or_default();
+    match ev.r#type.as_str() {
+        "content_block_start" => {
+            if let Some(block) = ev.content_block.filter(|b| b.r#type == "tool_use") {
+                acc.push(index, block.id, block.name, "");
+            }
+            Ok(None)
+        }
+        "content_block_delta" => {
+            let Some(delta) = ev.delta else {
+                return Ok(None);
+            };
+            if let Some(partial) = delta.partial_json {
+                acc.push(index, None, None, &partial);
+            }
+            Ok(delta.text)
+        }
+        "error" => Err(KbError::LlmService {
+            provider: "anthropic".to_string(),
+            message: ev
+                .error
+                .map(|e| e.message)
+                .unwrap_or_else(|| "stream error".to_string()),
+            retry_after: None,
+        }),
+        _ => Ok(None),
+    }
+}
+
+impl AnthropicClient {
+    async fn send_messages(&self, req: &ChatRequest, stream: bool) -> Result<reqwest::Response> {
         let url = format!("{}/v1/messages", self.cfg.api_url.trim_end_matches('/'));
-        let body = AnthMessageReq {
-            model: self.cfg.model.clone(),
-            messages: vec![AnthMessageReqMsg {
-                role: "user",
-                content: vec![AnthMessageContent {
-                    r#type: "text",
-                    text: format!("{}\n\nSystem:\n{}\n\nContext:\n{}", user, system, context),
-                }],
-            }],
-            max_tokens: 2048,
-            temperature: Some(0.2),
-        };
+        let body = AnthMessageReq::new(&self.cfg.model, req, stream);
 
         let resp = self
             .http
@@ -279,26 +961,71 @@ impl ChatModel for AnthropicClient {
             })?;
 
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(KbError::LlmService {
-                provider: "openai_compat".to_string(),
-                message: format!("status={} body={}", status, txt),
-                retry_after: None,
-            });
+            return Err(status_error("anthropic", resp).await);
         }
+        Ok(resp)
+    }
+}
 
+#[async_trait]
+impl ChatModel for AnthropicClient {
+    #[instrument(skip(self, req))]
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        let resp = self.send_messages(req, false).await?;
         let data: AnthMessageResp = resp.json().await.map_err(|e| KbError::Network {
             operation: "http_request".to_string(),
             message: e.to_string(),
         })?;
-        let mut out = String::new();
+        let mut out = ChatResponse {
+            usage: data
+                .usage
+                .map(|u| TokenUsage::chat(u.input_tokens, u.output_tokens)),
+            ..Default::default()
+        };
+        let structured = anth_structured_tool(req).map(|(name, _)| name);
         for c in data.content.into_iter() {
-            if let Some(t) = c.text {
-                out.push_str(&t);
+            match c.r#type.as_str() {
+                // 强制工具调用的参数即结构化输出，放回 content
+                "tool_use" if structured.is_some() && c.name.as_deref() == structured => {
+                    out.content = c.input.unwrap_or_default().to_string();
+                }
+                "tool_use" => out.tool_calls.push(ToolCall {
+                    id: c.id.unwrap_or_default(),
+                    name: c.name.unwrap_or_default(),
+                    arguments: c.input.unwrap_or_else(|| serde_json::json!({})),
+                }),
+                _ => {
+                    if let Some(t) = c.text {
+                        out.content.push_str(&t);
+                    }
+                }
             }
         }
-        Ok(out)
+        Ok(finish_chat(&self.cfg.model, req, out))
+    }
+
+    #[instrument(skip(self, req))]
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        let resp = self.send_messages(req, true).await?;
+        let stream = sse::sse_events(resp).filter_map(|ev| async move {
+            match ev {
+                Ok(ev) => parse_anth_stream_data(&ev.data).transpose(),
+                Err(e) => Some(Err(e)),
+            }
+        });
+        Ok(metered_stream(&self.cfg.model, req, Box::pin(stream)))
+    }
+
+    /// 结构化输出依赖强制工具调用，此时退回非流式补全以便把参数放回文本
+    #[instrument(skip(self, req))]
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        if anth_structured_tool(req).is_some() {
+            return Ok(response_deltas(self.complete(req).await?));
+        }
+        let resp = self.send_messages(req, true).await?;
+        let payloads = sse::sse_events(resp).map(|ev| ev.map(|ev| ev.data));
+        let stream = delta_stream(payloads, parse_anth_stream_delta);
+        Ok(metered_delta_stream(&self.cfg.model, req, stream))
     }
 }
 
@@ -348,9 +1075,16 @@ struct DashScopeEmbedVec {
     embedding: Vec<f32>,
 }
 
+#[derive(Deserialize)]
+struct DashScopeEmbedUsage {
+    #[serde(default)]
+    total_tokens: u64,
+}
+
 #[derive(Deserialize)]
 struct DashScopeEmbedResp {
     data: Vec<DashScopeEmbedVec>,
+    usage: Option<DashScopeEmbedUsage>,
 }
 
 #[async_trait]
@@ -374,18 +1108,13 @@ impl EmbedModel for QwenDashScopeClient {
                 message: e.to_string(),
             })?;
         if !resp.status().is_success() {
-            let status = resp.status();
-            let txt = resp.text().await.unwrap_or_default();
-            return Err(KbError::LlmService {
-                provider: "openai_compat".to_string(),
-                message: format!("status={} body={}", status, txt),
-                retry_after: None,
-            });
+            return Err(embed_status_error("qwen", resp).await);
         }
         let data: DashScopeEmbedResp = resp.json().await.map_err(|e| KbError::Network {
             operation: "http_request".to_string(),
             message: e.to_string(),
         })?;
+        record_embedding(&self.cfg.model, texts, data.usage.map(|u| u.total_tokens));
         Ok(data.data.into_iter().map(|d| d.embedding).collect())
     }
 }
@@ -437,6 +1166,18 @@ pub enum ChatProviderConfig {
         api_key: String,
         model: String,
     },
+    /// 本地/离线部署的 Ollama 服务
+    #[serde(rename = "ollama")]
+    Ollama {
+        #[serde(default)]
+        base_url: Option<String>,
+        model: String,
+        #[serde(default)]
+        auto_pull: bool,
+    },
+    /// 按优先级排列的故障转移链
+    #[serde(rename = "fallback")]
+    Fallback { providers: Vec<ChatProviderConfig> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -460,6 +1201,31 @@ pub enum EmbedProviderConfig {
         api_key: String,
         model: String,
     },
+    #[serde(rename = "ollama")]
+    Ollama {
+        #[serde(default)]
+        base_url: Option<String>,
+        model: String,
+        #[serde(default)]
+        auto_pull: bool,
+    },
+    /// 确定性的特征哈希向量，无需网络，用于离线开发与测试
+    #[serde(rename = "hashing")]
+    Hashing {
+        #[serde(default = "default_hashing_dimension")]
+        dimension: usize,
+    },
+    /// 按优先级排列的故障转移链，所有模型的向量维度必须一致
+    #[serde(rename = "fallback")]
+    Fallback {
+        providers: Vec<EmbedProviderConfig>,
+        #[serde(default)]
+        dimension: Option<usize>,
+    },
+}
+
+fn default_hashing_dimension() -> usize {
+    hashing::HASHING_DEFAULT_DIMENSION
 }
 
 pub struct Providers {
@@ -467,8 +1233,193 @@ pub struct Providers {
     pub embed: Box<dyn EmbedModel>,
 }
 
+impl ChatProviderConfig {
+    /// 用于日志与熔断器标识的提供商名称，如 `openai_compat/gpt-4o`
+    pub fn provider_name(&self) -> String {
+        match self {
+            ChatProviderConfig::OpenAiCompat { model, .. } => format!("openai_compat/{}", model),
+            ChatProviderConfig::Anthropic { model, .. } => format!("anthropic/{}", model),
+            ChatProviderConfig::Ollama { model, .. } => format!("ollama/{}", model),
+            ChatProviderConfig::Fallback { providers } => format!(
+                "fallback[{}]",
+                providers
+                    .iter()
+                    .map(|p| p.provider_name())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    /// 从注册表查找模型元数据；故障转移链取各提供商中最小的上下文窗口与输出上限
+    pub fn model_info(&self, registry: &ModelRegistry) -> Option<ModelInfo> {
+        match self {
+            ChatProviderConfig::Fallback { providers } => {
+                min_model_info(providers.iter().map(|p| p.model_info(registry)))
+            }
+            _ => registry.get(&self.provider_name()).cloned(),
+        }
+    }
+}
+
+/// 逐字段取最小值，用于故障转移链中任一提供商都可能被调用的场景
+fn min_model_info(infos: impl Iterator<Item = Option<ModelInfo>>) -> Option<ModelInfo> {
+    fn min(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+    infos.flatten().reduce(|a, b| ModelInfo {
+        embedding_dimension: a.embedding_dimension.or(b.embedding_dimension),
+        max_input_tokens: min(a.max_input_tokens, b.max_input_tokens),
+        context_window: min(a.context_window, b.context_window),
+        max_output_tokens: min(a.max_output_tokens, b.max_output_tokens),
+    })
+}
+
+impl EmbedProviderConfig {
+    /// 从注册表查找模型元数据；特征哈希模型的维度取自配置
+    pub fn model_info(&self, registry: &ModelRegistry) -> Option<ModelInfo> {
+        match self {
+            EmbedProviderConfig::Hashing { dimension } => Some(ModelInfo {
+                embedding_dimension: Some(*dimension),
+                ..Default::default()
+            }),
+            EmbedProviderConfig::Fallback { providers, .. } => {
+                min_model_info(providers.iter().map(|p| p.model_info(registry)))
+            }
+            _ => registry.get(&self.provider_name()).cloned(),
+        }
+    }
+}
+
+impl EmbedProviderConfig {
+    /// 用于日志与熔断器标识的提供商名称
+    pub fn provider_name(&self) -> String {
+        match self {
+            EmbedProviderConfig::OpenAiCompat { model, .. } => format!("openai_compat/{}", model),
+            EmbedProviderConfig::QwenDashScope { model, .. } => format!("qwen/{}", model),
+            EmbedProviderConfig::DeepSeek { model, .. } => format!("deepseek/{}", model),
+            EmbedProviderConfig::Ollama { model, .. } => format!("ollama/{}", model),
+            EmbedProviderConfig::Hashing { dimension } => format!("hashing/{}", dimension),
+            EmbedProviderConfig::Fallback { providers, .. } => format!(
+                "fallback[{}]",
+                providers
+                    .iter()
+                    .map(|p| p.provider_name())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    /// 正常情况下完成调用的提供商名；故障转移链取链首，与链上报的服务提供商名一致
+    pub fn primary_provider_name(&self) -> String {
+        match self {
+            EmbedProviderConfig::Fallback { providers, .. } => providers
+                .first()
+                .map(|p| p.provider_name())
+                .unwrap_or_else(|| self.provider_name()),
+            _ => self.provider_name(),
+        }
+    }
+}
+
+impl EmbedProviderConfig {
+    /// 提供商的默认分批限制，本地计算的模型返回 `None`
+    pub fn batch_defaults(&self) -> Option<EmbedBatchConfig> {
+        let base = EmbedBatchConfig::default();
+        match self {
+            EmbedProviderConfig::OpenAiCompat { .. } => Some(EmbedBatchConfig {
+                max_batch_size: 2048,
+                max_batch_tokens: 250_000,
+                max_input_tokens: 8000,
+                ..base
+            }),
+            // DashScope text-embedding-v3 单次最多 10 条
+            EmbedProviderConfig::QwenDashScope { .. } => Some(EmbedBatchConfig {
+                max_batch_size: 10,
+                ..base
+            }),
+            EmbedProviderConfig::DeepSeek { .. } => Some(base),
+            // 本地模型串行执行，避免抢占推理资源
+            EmbedProviderConfig::Ollama { .. } => Some(EmbedBatchConfig {
+                max_batch_size: 32,
+                max_concurrency: 1,
+                ..base
+            }),
+            EmbedProviderConfig::Hashing { .. } => None,
+            EmbedProviderConfig::Fallback { providers, .. } => providers
+                .iter()
+                .filter_map(|p| p.batch_defaults())
+                .reduce(|a, b| a.restrict(&b)),
+        }
+    }
+}
+
 pub fn make_providers(chat: ChatProviderConfig, embed: EmbedProviderConfig) -> Result<Providers> {
-    let chat_box: Box<dyn ChatModel> = match chat {
+    Ok(Providers {
+        chat: make_chat_model(chat, None)?,
+        embed: make_embed_model(embed, None)?,
+    })
+}
+
+/// 同 [`make_providers`]，并为每个提供商挂载重试、超时与熔断
+pub fn make_providers_with_retry(
+    chat: ChatProviderConfig,
+    embed: EmbedProviderConfig,
+    retry: &RetryConfig,
+) -> Result<Providers> {
+    Ok(Providers {
+        chat: make_chat_model(chat, Some(retry))?,
+        embed: make_embed_model(embed, Some(retry))?,
+    })
+}
+
+/// 提供商构建选项
+#[derive(Debug, Clone, Default)]
+pub struct ProviderOptions {
+    /// 重试、超时与熔断，为空时不重试
+    pub retry: Option<RetryConfig>,
+    /// 限流配置，键为提供商名（如 `openai_compat/gpt-4o`）或类型（如 `openai_compat`），前者优先
+    pub rate_limits: HashMap<String, RateLimitConfig>,
+    /// 向量接口分批配置，为空时使用提供商默认限制
+    pub embed_batch: Option<EmbedBatchConfig>,
+}
+
+impl ProviderOptions {
+    fn rate_limiter(&self, name: &str) -> Option<Arc<RateLimiter>> {
+        let kind = name.split('/').next().unwrap_or(name);
+        self.rate_limits
+            .get(name)
+            .or_else(|| self.rate_limits.get(kind))
+            .map(|cfg| Arc::new(RateLimiter::new(name, cfg.clone())))
+    }
+
+    fn with_retry(retry: Option<&RetryConfig>) -> Self {
+        Self {
+            retry: retry.cloned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// 按配置构建对话模型，`retry` 非空时包装重试与熔断
+pub fn make_chat_model(
+    chat: ChatProviderConfig,
+    retry: Option<&RetryConfig>,
+) -> Result<Box<dyn ChatModel>> {
+    make_chat_model_with(chat, &ProviderOptions::with_retry(retry))
+}
+
+/// 按配置与构建选项构建对话模型：重试在内、限流在外，本地排队超时不计入熔断也不触发重试
+pub fn make_chat_model_with(
+    chat: ChatProviderConfig,
+    opts: &ProviderOptions,
+) -> Result<Box<dyn ChatModel>> {
+    let name = chat.provider_name();
+    let mut chat_box: Box<dyn ChatModel> = match chat {
         ChatProviderConfig::OpenAiCompat {
             base_url,
             api_key,
@@ -488,9 +1439,83 @@ pub fn make_providers(chat: ChatProviderConfig, embed: EmbedProviderConfig) -> R
             api_key,
             model,
         })),
+        ChatProviderConfig::Ollama {
+            base_url,
+            model,
+            auto_pull,
+        } => Box::new(OllamaClient::new(OllamaConfig {
+            base_url: base_url.unwrap_or_else(|| ollama::OLLAMA_DEFAULT_BASE_URL.into()),
+            model,
+            auto_pull,
+        })),
+        // 链中每个提供商各自限流、重试与熔断，链本身不再包装
+        ChatProviderConfig::Fallback { providers } => {
+            let chain = providers
+                .into_iter()
+                .map(|p| Ok((p.provider_name(), Arc::from(make_chat_model_with(p, opts)?))))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Box::new(FallbackChatModel::new(chain)?));
+        }
     };
 
-    let embed_box: Box<dyn EmbedModel> = match embed {
+    if let Some(cfg) = &opts.retry {
+        chat_box = Box::new(RetryChatModel::new(
+            Arc::from(chat_box),
+            name.clone(),
+            cfg.clone(),
+        ));
+    }
+    if let Some(limiter) = opts.rate_limiter(&name) {
+        chat_box = Box::new(RateLimitedChatModel::new(Arc::from(chat_box), limiter));
+    }
+    Ok(chat_box)
+}
+
+/// 按配置构建向量模型，`retry` 非空时包装重试与熔断，并按提供商默认限制自动分批
+pub fn make_embed_model(
+    embed: EmbedProviderConfig,
+    retry: Option<&RetryConfig>,
+) -> Result<Box<dyn EmbedModel>> {
+    make_embed_model_with(embed, &ProviderOptions::with_retry(retry))
+}
+
+/// 同 [`make_embed_model`]，使用指定的分批配置，`batch` 为空时不分批
+pub fn make_embed_model_with_batch(
+    embed: EmbedProviderConfig,
+    retry: Option<&RetryConfig>,
+    batch: Option<&EmbedBatchConfig>,
+) -> Result<Box<dyn EmbedModel>> {
+    let model = build_embed_model(embed, &ProviderOptions::with_retry(retry))?;
+    Ok(with_batching(model, batch))
+}
+
+/// 按配置与构建选项构建向量模型
+pub fn make_embed_model_with(
+    embed: EmbedProviderConfig,
+    opts: &ProviderOptions,
+) -> Result<Box<dyn EmbedModel>> {
+    let batch = opts.embed_batch.clone().or_else(|| embed.batch_defaults());
+    let model = build_embed_model(embed, opts)?;
+    Ok(with_batching(model, batch.as_ref()))
+}
+
+fn with_batching(
+    model: Box<dyn EmbedModel>,
+    batch: Option<&EmbedBatchConfig>,
+) -> Box<dyn EmbedModel> {
+    match batch {
+        // 分批在重试之外，每个批次独立重试
+        Some(cfg) => Box::new(BatchEmbedModel::new(Arc::from(model), cfg.clone())),
+        None => model,
+    }
+}
+
+fn build_embed_model(
+    embed: EmbedProviderConfig,
+    opts: &ProviderOptions,
+) -> Result<Box<dyn EmbedModel>> {
+    let name = embed.provider_name();
+    let mut embed_box: Box<dyn EmbedModel> = match embed {
         EmbedProviderConfig::OpenAiCompat {
             base_url,
             api_key,
@@ -520,10 +1545,254 @@ pub fn make_providers(chat: ChatProviderConfig, embed: EmbedProviderConfig) -> R
             model,
             base_url: base_url.unwrap_or_else(|| "https://api.deepseek.com".into()),
         })),
+        EmbedProviderConfig::Ollama {
+            base_url,
+            model,
+            auto_pull,
+        } => Box::new(OllamaClient::new(OllamaConfig {
+            base_url: base_url.unwrap_or_else(|| ollama::OLLAMA_DEFAULT_BASE_URL.into()),
+            model,
+            auto_pull,
+        })),
+        // 本地计算不会失败，无需重试包装
+        EmbedProviderConfig::Hashing { dimension } => {
+            return Ok(Box::new(HashingEmbedModel::new(dimension)?));
+        }
+        EmbedProviderConfig::Fallback {
+            providers,
+            dimension,
+        } => {
+            let chain = providers
+                .into_iter()
+                .map(|p| Ok((p.provider_name(), Arc::from(build_embed_model(p, opts)?))))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Box::new(FallbackEmbedModel::new(chain, dimension)?));
+        }
     };
 
-    Ok(Providers {
-        chat: chat_box,
-        embed: embed_box,
-    })
+    if let Some(cfg) = &opts.retry {
+        embed_box = Box::new(RetryEmbedModel::new(
+            Arc::from(embed_box),
+            name.clone(),
+            cfg.clone(),
+        ));
+    }
+    // 限流在重试之外，原因同 `make_chat_model_with`
+    if let Some(limiter) = opts.rate_limiter(&name) {
+        embed_box = Box::new(RateLimitedEmbedModel::new(Arc::from(embed_box), limiter));
+    }
+    Ok(embed_box)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_oai_stream_data() {
+        let data = r#"{"choices":[{"delta":{"content":"你好"}}]}"#;
+        assert_eq!(
+            parse_oai_stream_data(data).unwrap(),
+            Some("你好".to_string())
+        );
+        assert_eq!(
+            parse_oai_stream_data(r#"{"choices":[{"delta":{}}]}"#).unwrap(),
+            None
+        );
+        assert_eq!(parse_oai_stream_data("[DONE]").unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_anth_stream_data() {
+        let delta =
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#;
+        assert_eq!(
+            parse_anth_stream_data(delta).unwrap(),
+            Some("Hi".to_string())
+        );
+        assert_eq!(
+            parse_anth_stream_data(r#"{"type":"message_stop"}"#).unwrap(),
+            None
+        );
+        let err = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        assert!(matches!(
+            parse_anth_stream_data(err),
+            Err(KbError::LlmService { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_deltas_assemble_tool_calls() {
+        let oai = [
+            r#"{"choices":[{"delta":{"content":"查一下"}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","function":{"name":"kb_search","arguments":"{\"query\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_b","function":{"name":"time_now","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"rust\"}"}}]}}]}"#,
+            "[DONE]",
+        ];
+        let payloads = futures::stream::iter(oai.map(|d| Ok(d.to_string())));
+        let deltas: Vec<ChatDelta> = delta_stream(payloads, parse_oai_stream_delta)
+            .map(|d| d.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas[0], ChatDelta::Text("查一下".to_string()));
+        assert_eq!(
+            deltas[1],
+            ChatDelta::ToolCall(ToolCall {
+                id: "call_a".to_string(),
+                name: "kb_search".to_string(),
+                arguments: serde_json::json!({"query": "rust"}),
+            })
+        );
+        assert!(
+            matches!(&deltas[2], ChatDelta::ToolCall(c) if c.id == "call_b" && c.arguments == serde_json::json!({}))
+        );
+
+        let anth = [
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_1","name":"kb_search","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"{\"query\": \"rust\"}"}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let payloads = futures::stream::iter(anth.map(|d| Ok(d.to_string())));
+        let deltas: Vec<ChatDelta> = delta_stream(payloads, parse_anth_stream_delta)
+            .map(|d| d.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            deltas,
+            vec![ChatDelta::ToolCall(ToolCall {
+                id: "toolu_1".to_string(),
+                name: "kb_search".to_string(),
+                arguments: serde_json::json!({"query": "rust"}),
+            })]
+        );
+    }
+
+    fn sample_request() -> ChatRequest {
+        ChatRequest::new(vec![
+            ChatMessage::system("be brief"),
+            ChatMessage::user("现在几点？"),
+            ChatMessage::assistant("调用工具"),
+            ChatMessage::tool("call_1", "2024-01-01T00:00:00Z"),
+            ChatMessage::user("谢谢"),
+        ])
+        .with_max_tokens(256)
+        .with_stop(vec!["END".to_string()])
+    }
+
+    #[test]
+    fn test_oai_request_keeps_roles_and_options() {
+        let req = sample_request();
+        let body = serde_json::to_value(OaiChatReq::new("gpt-4o", &req, false)).unwrap();
+        let roles: Vec<&str> = body["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool", "user"]);
+        assert_eq!(body["messages"][3]["tool_call_id"], "call_1");
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop"][0], "END");
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("stream").is_none());
+    }
+
+    #[test]
+    fn test_anthropic_request_uses_system_field() {
+        let req = sample_request();
+        let body = serde_json::to_value(AnthMessageReq::new("claude", &req, true)).unwrap();
+        assert_eq!(body["system"], "be brief");
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop_sequences"][0], "END");
+        assert_eq!(body["stream"], true);
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(messages[1]["role"], "assistant");
+        // 工具结果与随后的用户消息合并为同一条 user 消息
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][0]["type"], "tool_result");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "call_1");
+        assert_eq!(messages[2]["content"][1]["text"], "谢谢");
+    }
+
+    fn tool_request() -> ChatRequest {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "kb_search".to_string(),
+            arguments: serde_json::json!({"query": "退款"}),
+        };
+        ChatRequest::new(vec![
+            ChatMessage::user("退款政策是什么？"),
+            ChatMessage::assistant_tool_calls("", vec![call]),
+            ChatMessage::tool("call_1", "[1] 七天无理由退款"),
+        ])
+        .with_tools(vec![ToolDefinition::new(
+            "kb_search",
+            "搜索知识库",
+            serde_json::json!({"type": "object", "properties": {"query": {"type": "string"}}}),
+        )])
+        .with_tool_choice(ToolChoice::Tool {
+            name: "kb_search".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_oai_tool_request_and_response() {
+        let req = tool_request();
+        let body = serde_json::to_value(OaiChatReq::new("gpt-4o", &req, false)).unwrap();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "kb_search");
+        assert_eq!(body["tool_choice"]["function"]["name"], "kb_search");
+        let assistant = &body["messages"][1];
+        assert!(assistant["content"].is_null());
+        assert_eq!(
+            assistant["tool_calls"][0]["function"]["arguments"],
+            r#"{"query":"退款"}"#
+        );
+
+        let resp: OaiChatResp = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":null,"tool_calls":[
+                {"id":"call_2","type":"function","function":{"name":"kb_search","arguments":"{\"query\":\"发票\"}"}}
+            ]}}]}"#,
+        )
+        .unwrap();
+        let resp = resp.into_response();
+        assert_eq!(resp.content, "");
+        assert_eq!(resp.tool_calls[0].name, "kb_search");
+        assert_eq!(resp.tool_calls[0].arguments["query"], "发票");
+    }
+
+    #[test]
+    fn test_anthropic_tool_request() {
+        let req = tool_request();
+        let body = serde_json::to_value(AnthMessageReq::new("claude", &req, false)).unwrap();
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(body["tool_choice"]["type"], "tool");
+        let assistant = &body["messages"][1];
+        assert_eq!(assistant["content"].as_array().unwrap().len(), 1);
+        assert_eq!(assistant["content"][0]["type"], "tool_use");
+        assert_eq!(assistant["content"][0]["input"]["query"], "退款");
+        assert_eq!(body["messages"][2]["content"][0]["type"], "tool_result");
+    }
+
+    #[test]
+    fn test_structured_output_request() {
+        let req = ChatRequest::new(vec![ChatMessage::user("分类")]).with_response_format(
+            ResponseFormat::JsonSchema {
+                name: "QueryClass".to_string(),
+                schema: serde_json::json!({"type": "object"}),
+            },
+        );
+        let body = serde_json::to_value(OaiChatReq::new("gpt", &req, false)).unwrap();
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["name"], "QueryClass");
+
+        // Anthropic 通过强制调用同名工具获得结构化输出
+        let body = serde_json::to_value(AnthMessageReq::new("claude", &req, false)).unwrap();
+        assert_eq!(body["tools"][0]["name"], "QueryClass");
+        assert_eq!(body["tool_choice"]["name"], "QueryClass");
+    }
 }
@@ -0,0 +1,562 @@
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use kb_error::{KbError, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, instrument};
+
+use crate::{
+    delta_stream, embed_status_error, finish_chat, metered_delta_stream, metered_stream,
+    record_embedding, status_error, ChatDeltaStream, ChatModel, ChatRequest, ChatResponse,
+    ChatStream, EmbedModel, OaiFunctionDef, OaiTool, ResponseFormat, TokenUsage, ToolCall,
+    ToolCallAccumulator, ToolChoice,
+};
+
+/// Ollama 默认监听地址
+pub const OLLAMA_DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// 建立连接的超时：服务不可达但不拒绝连接时避免启动无限挂起
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 单次请求的超时，本地模型生成较慢，留出足够余量
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+/// 健康检查只列出模型，应很快返回
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// 拉取模型需下载数 GB 文件，单独放宽
+const PULL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// ========== Ollama（本地/离线部署） ==========
+
+#[derive(Clone)]
+pub struct OllamaConfig {
+    pub base_url: String, // e.g. http://localhost:11434
+    pub model: String,    // e.g. qwen2.5:7b / nomic-embed-text
+    /// 启动校验时模型未下载则自动拉取（离线环境应预先导入模型并关闭）
+    pub auto_pull: bool,
+}
+
+#[derive(Clone)]
+pub struct OllamaClient {
+    http: Client,
+    cfg: OllamaConfig,
+}
+
+impl OllamaClient {
+    pub fn new(cfg: OllamaConfig) -> Self {
+        let http = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            // 与 `Client::new` 相同，仅在 TLS 后端无法初始化时失败
+            .expect("failed to build HTTP client");
+        Self { http, cfg }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.cfg.base_url.trim_end_matches('/'), path)
+    }
+
+    /// 健康检查：返回服务端已下载的模型列表
+    pub async fn health_check(&self) -> Result<Vec<String>> {
+        let resp = self
+            .http
+            .get(self.url("/api/tags"))
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| KbError::Network {
+                operation: "ollama_health_check".to_string(),
+                message: format!("{}: {}", self.cfg.base_url, e),
+            })?;
+        if !resp.status().is_success() {
+            return Err(status_error("ollama", resp).await);
+        }
+        let tags: OllamaTags = resp.json().await.map_err(|e| KbError::Network {
+            operation: "http_request".to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(tags.models.into_iter().map(|m| m.name).collect())
+    }
+
+    /// 确认模型可用：未下载时按配置拉取，否则报配置错误
+    pub async fn ensure_model(&self) -> Result<()> {
+        let installed = self.health_check().await?;
+        if installed.iter().any(|m| model_matches(m, &self.cfg.model)) {
+            return Ok(());
+        }
+        if !self.cfg.auto_pull {
+            return Err(KbError::Configuration {
+                key: "ollama.model".to_string(),
+                reason: format!(
+                    "model {} not found on {}, run `ollama pull {}` or enable auto_pull",
+                    self.cfg.model, self.cfg.base_url, self.cfg.model
+                ),
+            });
+        }
+        self.pull().await
+    }
+
+    async fn pull(&self) -> Result<()> {
+        info!(model = %self.cfg.model, "拉取 Ollama 模型");
+        let body = serde_json::json!({"model": self.cfg.model, "stream": false});
+        let resp = self
+            .http
+            .post(self.url("/api/pull"))
+            .json(&body)
+            .timeout(PULL_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| KbError::Network {
+                operation: "ollama_pull".to_string(),
+                message: e.to_string(),
+            })?;
+        if !resp.status().is_success() {
+            return Err(status_error("ollama", resp).await);
+        }
+        let status: OllamaPullStatus = resp.json().await.map_err(|e| KbError::Network {
+            operation: "ollama_pull".to_string(),
+            message: e.to_string(),
+        })?;
+        match status.error {
+            Some(message) => Err(KbError::LlmService {
+                provider: "ollama".to_string(),
+                message,
+                retry_after: None,
+            }),
+            None => {
+                info!(model = %self.cfg.model, status = %status.status, "Ollama 模型拉取完成");
+                Ok(())
+            }
+        }
+    }
+
+    async fn send_chat(&self, req: &ChatRequest, stream: bool) -> Result<reqwest::Response> {
+        let body = OllamaChatReq::new(&self.cfg.model, req, stream);
+        let resp = self
+            .http
+            .post(self.url("/api/chat"))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| KbError::Network {
+                operation: "http_request".to_string(),
+                message: e.to_string(),
+            })?;
+        if !resp.status().is_success() {
+            return Err(status_error("ollama", resp).await);
+        }
+        Ok(resp)
+    }
+}
+
+/// 未指定标签的模型名等同于 `:latest`
+fn model_matches(installed: &str, wanted: &str) -> bool {
+    installed == wanted
+        || (!wanted.contains(':') && installed.strip_suffix(":latest") == Some(wanted))
+}
+
+#[derive(Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaTags {
+    #[serde(default)]
+    models: Vec<OllamaTag>,
+}
+
+#[derive(Deserialize)]
+struct OllamaPullStatus {
+    #[serde(default)]
+    status: String,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaFunctionCall<'a> {
+    name: &'a str,
+    arguments: &'a serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct OllamaToolCall<'a> {
+    function: OllamaFunctionCall<'a>,
+}
+
+#[derive(Serialize)]
+struct OllamaChatReqMsg<'a> {
+    role: &'static str,
+    content: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall<'a>>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    stop: &'a [String],
+}
+
+#[derive(Serialize)]
+struct OllamaChatReq<'a> {
+    model: &'a str,
+    messages: Vec<OllamaChatReqMsg<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OaiTool<'a>>,
+    options: OllamaOptions<'a>,
+    /// 结构化输出："json" 或 JSON Schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    stream: bool,
+}
+
+impl<'a> OllamaChatReq<'a> {
+    fn new(model: &'a str, req: &'a ChatRequest, stream: bool) -> Self {
+        let messages = req
+            .messages
+            .iter()
+            .map(|m| OllamaChatReqMsg {
+                role: m.role.as_str(),
+                content: &m.content,
+                tool_calls: m
+                    .tool_calls
+                    .iter()
+                    .map(|c| OllamaToolCall {
+                        function: OllamaFunctionCall {
+                            name: &c.name,
+                            arguments: &c.arguments,
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+        // Ollama 不支持 tool_choice：None 时不下发工具，指定工具时只下发该工具
+        let tools = req
+            .tools
+            .iter()
+            .filter(|t| match &req.tool_choice {
+                Some(ToolChoice::None) => false,
+                Some(ToolChoice::Tool { name }) => &t.name == name,
+                _ => true,
+            })
+            .map(|t| OaiTool {
+                r#type: "function",
+                function: OaiFunctionDef {
+                    name: &t.name,
+                    description: &t.description,
+                    parameters: &t.parameters,
+                },
+            })
+            .collect();
+        Self {
+            model,
+            messages,
+            tools,
+            options: OllamaOptions {
+                temperature: req.temperature,
+                num_predict: req.max_tokens,
+                stop: &req.stop,
+            },
+            format: req.response_format.as_ref().map(|f| match f {
+                ResponseFormat::JsonObject => serde_json::json!("json"),
+                ResponseFormat::JsonSchema { schema, .. } => schema.clone(),
+            }),
+            stream,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OllamaRespFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct OllamaRespToolCall {
+    function: OllamaRespFunctionCall,
+}
+
+#[derive(Deserialize)]
+struct OllamaRespMsg {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaRespToolCall>,
+}
+
+/// `/api/chat` 的完整响应与流式响应的每一行结构相同
+#[derive(Deserialize)]
+struct OllamaChatChunk {
+    message: Option<OllamaRespMsg>,
+    error: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl OllamaChatChunk {
+    fn into_response(self) -> Result<ChatResponse> {
+        if let Some(message) = self.error {
+            return Err(ollama_error(message));
+        }
+        let usage = match (self.prompt_eval_count, self.eval_count) {
+            (None, None) => None,
+            (p, c) => Some(TokenUsage::chat(p.unwrap_or(0), c.unwrap_or(0))),
+        };
+        let Some(msg) = self.message else {
+            return Ok(ChatResponse {
+                usage,
+                ..Default::default()
+            });
+        };
+        Ok(ChatResponse {
+            content: msg.content,
+            usage,
+            // Ollama 不返回调用 ID，按序号生成以便工具结果回传时对应
+            tool_calls: msg
+                .tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, c)| ToolCall {
+                    id: format!("call_{}", i),
+                    name: c.function.name,
+                    arguments: match c.function.arguments {
+                        serde_json::Value::Null => serde_json::json!({}),
+                        args => args,
+                    },
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+}
+
+fn ollama_error(message: String) -> KbError {
+    KbError::LlmService {
+        provider: "ollama".to_string(),
+        message,
+        retry_after: None,
+    }
+}
+
+/// 解析 Ollama 流式响应（NDJSON）中的一行，返回其中的增量文本
+fn parse_ollama_stream_line(line: &str) -> Result<Option<String>> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let chunk: OllamaChatChunk = serde_json::from_str(line)?;
+    let text = chunk.into_response()?.content;
+    Ok((!text.is_empty()).then_some(text))
+}
+
+/// 同 [`parse_ollama_stream_line`]，并把其中完整的工具调用写入累积器（序号跨行递增）
+fn parse_ollama_stream_delta(acc: &mut ToolCallAccumulator, line: &str) -> Result<Option<String>> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let chunk: OllamaChatChunk = serde_json::from_str(line)?;
+    let resp = chunk.into_response()?;
+    for call in resp.tool_calls {
+        acc.push(
+            acc.len(),
+            None,
+            Some(call.name),
+            &call.arguments.to_string(),
+        );
+    }
+    Ok((!resp.content.is_empty()).then_some(resp.content))
+}
+
+/// 将 HTTP 响应体按行切分（NDJSON），按字节缓冲避免在 UTF-8 字符中间截断
+fn json_lines(resp: reqwest::Response) -> impl Stream<Item = Result<String>> + Send + 'static {
+    futures::stream::unfold(
+        (resp.bytes_stream(), Vec::new(), false),
+        |(mut bytes, mut buf, mut done)| async move {
+            loop {
+                if let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line).trim().to_string();
+                    return Some((Ok(line), (bytes, buf, done)));
+                }
+                if done {
+                    if buf.is_empty() {
+                        return None;
+                    }
+                    let line = String::from_utf8_lossy(&std::mem::take(&mut buf))
+                        .trim()
+                        .to_string();
+                    return Some((Ok(line), (bytes, buf, done)));
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(e)) => {
+                        let err = KbError::Network {
+                            operation: "ndjson_stream".to_string(),
+                            message: e.to_string(),
+                        };
+                        return Some((Err(err), (bytes, Vec::new(), true)));
+                    }
+                    None => done = true,
+                }
+            }
+        },
+    )
+}
+
+#[async_trait]
+impl ChatModel for OllamaClient {
+    #[instrument(skip(self, req))]
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        let resp = self.send_chat(req, false).await?;
+        let data: OllamaChatChunk = resp.json().await.map_err(|e| KbError::Network {
+            operation: "http_request".to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(finish_chat(&self.cfg.model, req, data.into_response()?))
+    }
+
+    #[instrument(skip(self, req))]
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        let resp = self.send_chat(req, true).await?;
+        let stream = json_lines(resp).filter_map(|line| async move {
+            match line {
+                Ok(line) => parse_ollama_stream_line(&line).transpose(),
+                Err(e) => Some(Err(e)),
+            }
+        });
+        Ok(metered_stream(&self.cfg.model, req, Box::pin(stream)))
+    }
+
+    #[instrument(skip(self, req))]
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        let resp = self.send_chat(req, true).await?;
+        let stream = delta_stream(json_lines(resp), parse_ollama_stream_delta);
+        Ok(metered_delta_stream(&self.cfg.model, req, stream))
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.ensure_model().await
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaEmbedReq<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResp {
+    embeddings: Vec<Vec<f32>>,
+    prompt_eval_count: Option<u64>,
+}
+
+#[async_trait]
+impl EmbedModel for OllamaClient {
+    #[instrument(skip(self, texts))]
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let body = OllamaEmbedReq {
+            model: &self.cfg.model,
+            input: texts,
+        };
+        let resp = self
+            .http
+            .post(self.url("/api/embed"))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| KbError::Network {
+                operation: "http_request".to_string(),
+                message: e.to_string(),
+            })?;
+        if !resp.status().is_success() {
+            return Err(embed_status_error("ollama", resp).await);
+        }
+        let data: OllamaEmbedResp = resp.json().await.map_err(|e| KbError::Network {
+            operation: "http_request".to_string(),
+            message: e.to_string(),
+        })?;
+        record_embedding(&self.cfg.model, texts, data.prompt_eval_count);
+        Ok(data.embeddings)
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.ensure_model().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatMessage, ToolDefinition};
+    use serde_json::json;
+
+    #[test]
+    fn test_model_matches_latest_tag() {
+        assert!(model_matches("nomic-embed-text:latest", "nomic-embed-text"));
+        assert!(model_matches("qwen2.5:7b", "qwen2.5:7b"));
+        assert!(!model_matches("qwen2.5:14b", "qwen2.5:7b"));
+        assert!(!model_matches("qwen2.5:7b", "qwen2.5"));
+    }
+
+    #[test]
+    fn test_chat_request_serialization() {
+        let call = ToolCall {
+            id: "call_0".to_string(),
+            name: "kb_search".to_string(),
+            arguments: json!({"query": "rust"}),
+        };
+        let req = ChatRequest::new(vec![
+            ChatMessage::system("sys"),
+            ChatMessage::assistant_tool_calls("", vec![call]),
+            ChatMessage::tool("call_0", "result"),
+        ])
+        .with_temperature(0.1)
+        .with_max_tokens(64)
+        .with_tools(vec![ToolDefinition::new("kb_search", "search", json!({}))]);
+        let body = serde_json::to_value(OllamaChatReq::new("qwen2.5:7b", &req, true)).unwrap();
+        assert_eq!(
+            body["options"],
+            json!({"temperature": 0.1f32, "num_predict": 64})
+        );
+        assert_eq!(
+            body["messages"][1]["tool_calls"][0]["function"]["arguments"],
+            json!({"query": "rust"})
+        );
+        assert_eq!(body["messages"][2]["role"], "tool");
+        assert_eq!(body["tools"][0]["function"]["name"], "kb_search");
+        assert_eq!(body["stream"], true);
+
+        let req = req.with_tool_choice(ToolChoice::None);
+        let body = serde_json::to_value(OllamaChatReq::new("m", &req, false)).unwrap();
+        assert!(body.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_parse_chat_response_and_stream() {
+        let data = r#"{"message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"kb_search","arguments":{"query":"rust"}}}]},"done":true}"#;
+        let resp = serde_json::from_str::<OllamaChatChunk>(data)
+            .unwrap()
+            .into_response()
+            .unwrap();
+        assert_eq!(resp.tool_calls[0].id, "call_0");
+        assert_eq!(resp.tool_calls[0].arguments, json!({"query": "rust"}));
+
+        let line = r#"{"message":{"role":"assistant","content":"你好"},"done":false}"#;
+        assert_eq!(
+            parse_ollama_stream_line(line).unwrap(),
+            Some("你好".to_string())
+        );
+        assert_eq!(parse_ollama_stream_line(r#"{"done":true}"#).unwrap(), None);
+        assert!(matches!(
+            parse_ollama_stream_line(r#"{"error":"model not found"}"#),
+            Err(KbError::LlmService { .. })
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+/// 内置模板名，未配置规则时使用
+pub const DEFAULT_TEMPLATE: &str = "default";
+
+/// 租户规则中适用于所有模式的键
+const ANY_MODE: &str = "*";
+
+tokio::task_local! {
+    static PROMPT: Arc<PromptTemplate>;
+}
+
+/// 以指定模板执行 `f`，其间生成回答使用该模板
+pub async fn with_prompt<F: Future>(template: Arc<PromptTemplate>, f: F) -> F::Output {
+    PROMPT.scope(template, f).await
+}
+
+/// 当前任务的提示模板，未设置时为内置默认模板
+pub fn current_prompt() -> Arc<PromptTemplate> {
+    PROMPT
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::new(PromptTemplate::default()))
+}
+
+/// 提示模板：`system` 与 `user` 中可使用变量 `{context}`、`{question}`、`{language}`、
+/// `{citation_style}`、`{refusal}`，后三者取模板自身的字段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    /// 模板名，注册时以键名为准
+    #[serde(default)]
+    pub name: String,
+    /// 版本号，每次修改递增，记录在查询日志中
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default = "default_system")]
+    pub system: String,
+    #[serde(default = "default_user")]
+    pub user: String,
+    /// 回答语言
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// 引用格式要求
+    #[serde(default = "default_citation_style")]
+    pub citation_style: String,
+    /// 上下文不足时的拒答策略
+    #[serde(default = "default_refusal")]
+    pub refusal: String,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+fn default_system() -> String {
+    "You are a helpful assistant. Answer the user's question based on the provided context. {refusal} {citation_style} Answer in {language}.".to_string()
+}
+
+fn default_user() -> String {
+    "{question}\n\nContext:\n{context}".to_string()
+}
+
+fn default_language() -> String {
+    "the same language as the question".to_string()
+}
+
+fn default_citation_style() -> String {
+    "Always cite your sources using [1], [2], etc. when referencing information from the context."
+        .to_string()
+}
+
+fn default_refusal() -> String {
+    "If the context doesn't contain enough information to answer the question, say so clearly."
+        .to_string()
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_TEMPLATE.to_string(),
+            version: default_version(),
+            system: default_system(),
+            user: default_user(),
+            language: default_language(),
+            citation_style: default_citation_style(),
+            refusal: default_refusal(),
+        }
+    }
+}
+
+/// 渲染后的提示
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedPrompt {
+    pub system: String,
+    pub user: String,
+}
+
+impl PromptTemplate {
+    /// 代入上下文与问题
+    pub fn render(&self, context: &str, question: &str) -> RenderedPrompt {
+        let vars = [
+            ("context", context),
+            ("question", question),
+            ("language", self.language.as_str()),
+            ("citation_style", self.citation_style.as_str()),
+            ("refusal", self.refusal.as_str()),
+        ];
+        RenderedPrompt {
+            system: substitute(&self.system, &vars),
+            user: substitute(&self.user, &vars),
+        }
+    }
+
+    /// 仅含 system 提示的渲染结果（如 Rig Agent 的 preamble），上下文由调用方另行提供
+    pub fn render_system(&self) -> String {
+        self.render("", "").system.trim().to_string()
+    }
+}
+
+/// 单遍替换 `{name}`，代入的值中即使含有 `{...}` 也不会再次展开；未知变量原样保留
+fn substitute(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| (*v, end))
+        });
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 提示模板配置（YAML 中的 `prompts` 段）
+///
+/// 选择顺序：`tenants.<租户>.<模式>` → `tenants.<租户>."*"` → `modes.<模式>` → `default` 模板。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptConfig {
+    /// 模板名 → 模板
+    #[serde(default)]
+    pub templates: HashMap<String, PromptTemplate>,
+    /// 模式 → 模板名
+    #[serde(default)]
+    pub modes: HashMap<String, String>,
+    /// 租户 → (模式或 `*` → 模板名)
+    #[serde(default)]
+    pub tenants: HashMap<String, HashMap<String, String>>,
+}
+
+/// 提示模板注册表，可在运行时修改
+#[derive(Debug, Default)]
+pub struct PromptRegistry {
+    inner: RwLock<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    templates: HashMap<String, Arc<PromptTemplate>>,
+    modes: HashMap<String, String>,
+    tenants: HashMap<String, HashMap<String, String>>,
+}
+
+impl PromptRegistry {
+    pub fn new(config: PromptConfig) -> Self {
+        let registry = Self::default();
+        for (name, template) in config.templates {
+            registry.upsert(&name, template);
+        }
+        registry.set_rules(config.modes, config.tenants);
+        registry
+    }
+
+    /// 新增或修改模板，返回生效的版本号：修改时版本至少比原版本大 1
+    pub fn upsert(&self, name: &str, mut template: PromptTemplate) -> u32 {
+        let mut inner = self.inner.write().unwrap();
+        template.name = name.to_string();
+        if let Some(existing) = inner.templates.get(name) {
+            // 内容未变时保持原版本
+            let requested = template.version;
+            template.version = existing.version;
+            if **existing == template {
+                return existing.version;
+            }
+            template.version = requested.max(existing.version + 1);
+        }
+        let version = template.version;
+        inner.templates.insert(name.to_string(), Arc::new(template));
+        version
+    }
+
+    /// 删除模板，引用该模板的规则回退到默认模板
+    pub fn remove(&self, name: &str) -> bool {
+        self.inner.write().unwrap().templates.remove(name).is_some()
+    }
+
+    /// 替换模式与租户规则
+    pub fn set_rules(
+        &self,
+        modes: HashMap<String, String>,
+        tenants: HashMap<String, HashMap<String, String>>,
+    ) {
+        let mut inner = self.inner.write().unwrap();
+        inner.modes = modes;
+        inner.tenants = tenants;
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<PromptTemplate>> {
+        self.inner.read().unwrap().templates.get(name).cloned()
+    }
+
+    /// 按租户与模式选择模板
+    pub fn resolve(&self, tenant: &str, mode: &str) -> Arc<PromptTemplate> {
+        let inner = self.inner.read().unwrap();
+        let tenant_rules = inner.tenants.get(tenant);
+        let template = [
+            tenant_rules.and_then(|r| r.get(mode)),
+            tenant_rules.and_then(|r| r.get(ANY_MODE)),
+            inner.modes.get(mode),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|name| inner.templates.get(name))
+        .or_else(|| inner.templates.get(DEFAULT_TEMPLATE))
+        .cloned();
+        template.unwrap_or_else(|| Arc::new(PromptTemplate::default()))
+    }
+
+    /// 当前配置的快照
+    pub fn snapshot(&self) -> PromptConfig {
+        let inner = self.inner.read().unwrap();
+        PromptConfig {
+            templates: inner
+                .templates
+                .iter()
+                .map(|(k, v)| (k.clone(), (**v).clone()))
+                .collect(),
+            modes: inner.modes.clone(),
+            tenants: inner.tenants.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(language: &str) -> PromptTemplate {
+        PromptTemplate {
+            language: language.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_substitutes_once() {
+        let rendered = PromptTemplate::default().render("[1] doc {question}", "如何退款？");
+        assert!(rendered.user.starts_with("如何退款？"));
+        // 上下文中的占位符不再展开
+        assert!(rendered.user.ends_with("[1] doc {question}"));
+        assert!(rendered
+            .system
+            .contains("the same language as the question"));
+        assert!(!rendered.system.contains('{'));
+        assert_eq!(substitute("{unknown} {", &[]), "{unknown} {");
+    }
+
+    #[test]
+    fn test_resolve_by_tenant_and_mode() {
+        let config = PromptConfig {
+            templates: HashMap::from([
+                ("zh".to_string(), template("Chinese")),
+                ("graph".to_string(), template("English")),
+                ("acme_graph".to_string(), template("Japanese")),
+            ]),
+            modes: HashMap::from([("graph".to_string(), "graph".to_string())]),
+            tenants: HashMap::from([(
+                "acme".to_string(),
+                HashMap::from([
+                    ("*".to_string(), "zh".to_string()),
+                    ("graph".to_string(), "acme_graph".to_string()),
+                ]),
+            )]),
+        };
+        let registry = PromptRegistry::new(config);
+        assert_eq!(registry.resolve("acme", "rag").name, "zh");
+        assert_eq!(registry.resolve("acme", "graph").name, "acme_graph");
+        assert_eq!(registry.resolve("other", "graph").name, "graph");
+        assert_eq!(registry.resolve("other", "rag").name, DEFAULT_TEMPLATE);
+    }
+
+    #[test]
+    fn test_upsert_bumps_version() {
+        let registry = PromptRegistry::default();
+        assert_eq!(registry.upsert("zh", template("Chinese")), 1);
+        assert_eq!(registry.upsert("zh", template("Chinese")), 1);
+        assert_eq!(registry.upsert("zh", template("Simplified Chinese")), 2);
+        assert_eq!(registry.resolve("t", "rag").name, DEFAULT_TEMPLATE);
+        registry.set_rules(
+            HashMap::from([("rag".to_string(), "zh".to_string())]),
+            HashMap::new(),
+        );
+        let active = registry.resolve("t", "rag");
+        assert_eq!((active.name.as_str(), active.version), ("zh", 2));
+    }
+}
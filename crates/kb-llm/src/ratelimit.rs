@@ -0,0 +1,378 @@
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::tokens::estimate_tokens;
+use crate::{ChatDeltaStream, ChatModel, ChatRequest, ChatResponse, ChatStream, EmbedModel};
+
+/// 调用优先级：交互式查询优先，后台索引只使用预留额度之外的配额
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Background,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+/// 以指定优先级执行 `f`，其间的模型调用按该优先级排队
+pub async fn with_priority<F: Future>(priority: Priority, f: F) -> F::Output {
+    PRIORITY.scope(priority, f).await
+}
+
+/// 当前任务的调用优先级，未设置时为交互式
+pub fn current_priority() -> Priority {
+    PRIORITY.try_with(|p| *p).unwrap_or_default()
+}
+
+/// 令牌桶限流配置，RPM/TPM 为空表示不限
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    #[serde(default)]
+    pub tokens_per_minute: Option<u32>,
+    /// 为交互式查询预留的额度比例，后台调用不能使用这部分额度
+    #[serde(default = "default_interactive_reserve")]
+    pub interactive_reserve: f64,
+    /// 单次调用最长排队时间，超时返回可重试错误
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+fn default_interactive_reserve() -> f64 {
+    0.2
+}
+
+fn default_max_wait_ms() -> u64 {
+    30_000
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            interactive_reserve: default_interactive_reserve(),
+            max_wait_ms: default_max_wait_ms(),
+        }
+    }
+}
+
+/// 按分钟匀速补充的令牌桶
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.available =
+            (self.available + self.capacity * elapsed.as_secs_f64() / 60.0).min(self.capacity);
+    }
+
+    /// 扣除 `cost` 后需保留 `reserve` 比例额度时，还需等待的时间；为零表示可立即扣除
+    fn wait_for(&self, cost: f64, reserve: f64) -> Duration {
+        // 单次消耗超过容量时按容量计，避免永远无法满足
+        let needed = cost.min(self.capacity) + self.capacity * reserve;
+        let missing = needed.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing * 60.0 / self.capacity)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    last_refill: Instant,
+    interactive_waiting: usize,
+}
+
+/// 单个提供商/模型的 RPM/TPM 令牌桶限流器
+pub struct RateLimiter {
+    name: String,
+    config: RateLimitConfig,
+    state: Mutex<LimiterState>,
+}
+
+/// 等待期间登记交互式请求，使后台请求让行
+struct WaitingGuard<'a>(&'a RateLimiter);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().interactive_waiting -= 1;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(name: impl Into<String>, config: RateLimitConfig) -> Self {
+        let state = LimiterState {
+            requests: config.requests_per_minute.map(Bucket::new),
+            tokens: config.tokens_per_minute.map(Bucket::new),
+            last_refill: Instant::now(),
+            interactive_waiting: 0,
+        };
+        Self {
+            name: name.into(),
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// 按当前任务优先级获取一次调用的额度，`tokens` 为预估 token 数
+    pub async fn acquire(&self, tokens: u64) -> Result<()> {
+        self.acquire_with(tokens, current_priority()).await
+    }
+
+    pub async fn acquire_with(&self, tokens: u64, priority: Priority) -> Result<()> {
+        let deadline = Instant::now() + Duration::from_millis(self.config.max_wait_ms);
+        let interactive = priority == Priority::Interactive;
+        let mut guard: Option<WaitingGuard> = None;
+        loop {
+            let wait = {
+                let mut locked = self.state.lock().unwrap();
+                let state = &mut *locked;
+                let now = Instant::now();
+                let elapsed = now.duration_since(state.last_refill);
+                state.last_refill = now;
+                for bucket in [&mut state.requests, &mut state.tokens]
+                    .into_iter()
+                    .flatten()
+                {
+                    bucket.refill(elapsed);
+                }
+                let reserve = if interactive {
+                    0.0
+                } else {
+                    self.config.interactive_reserve.clamp(0.0, 0.9)
+                };
+                let mut wait = Duration::ZERO;
+                if let Some(b) = &state.requests {
+                    wait = wait.max(b.wait_for(1.0, reserve));
+                }
+                if let Some(b) = &state.tokens {
+                    wait = wait.max(b.wait_for(tokens as f64, reserve));
+                }
+                // 有交互式请求排队时后台请求让行
+                if !interactive && state.interactive_waiting > 0 {
+                    wait = wait.max(Duration::from_millis(50));
+                }
+                if wait.is_zero() {
+                    if let Some(b) = &mut state.requests {
+                        b.available -= 1.0;
+                    }
+                    if let Some(b) = &mut state.tokens {
+                        b.available -= (tokens as f64).min(b.capacity);
+                    }
+                    return Ok(());
+                }
+                if interactive && guard.is_none() {
+                    state.interactive_waiting += 1;
+                    guard = Some(WaitingGuard(self));
+                }
+                wait
+            };
+            let now = Instant::now();
+            if now + wait > deadline {
+                return Err(KbError::ServiceUnavailable {
+                    service: format!("rate_limit:{}", self.name),
+                    retry_after: Some(wait),
+                });
+            }
+            debug!(limiter = %self.name, ?priority, wait_ms = wait.as_millis() as u64, "限流等待");
+            // 分段等待，以便及时响应交互式请求的让行
+            tokio::time::sleep(wait.min(Duration::from_millis(250))).await;
+        }
+    }
+
+    /// 调用完成后按实际用量修正 TPM 额度（预估偏低时补扣，偏高时返还）
+    ///
+    /// 补扣的欠额最多一分钟额度，避免单次严重低估的调用长时间阻塞后续请求。
+    pub fn reconcile(&self, estimated: u64, actual: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(b) = &mut state.tokens {
+            b.available =
+                (b.available + estimated as f64 - actual as f64).clamp(-b.capacity, b.capacity);
+        }
+    }
+}
+
+fn estimate_request_tokens(req: &ChatRequest) -> u64 {
+    let prompt: usize = req
+        .messages
+        .iter()
+        .map(|m| estimate_tokens(&m.content))
+        .sum();
+    // 未指定 max_tokens 时按提示长度的一半预估输出
+    let completion = req.max_tokens.map(|t| t as usize).unwrap_or(prompt / 2);
+    (prompt + completion) as u64
+}
+
+/// 带限流的对话模型包装
+pub struct RateLimitedChatModel {
+    inner: Arc<dyn ChatModel>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedChatModel {
+    pub fn new(inner: Arc<dyn ChatModel>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl ChatModel for RateLimitedChatModel {
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        let estimated = estimate_request_tokens(req);
+        self.limiter.acquire(estimated).await?;
+        let resp = self.inner.complete(req).await?;
+        if let Some(usage) = resp.usage {
+            self.limiter
+                .reconcile(estimated, usage.prompt_tokens + usage.completion_tokens);
+        }
+        Ok(resp)
+    }
+
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        self.limiter.acquire(estimate_request_tokens(req)).await?;
+        self.inner.complete_stream(req).await
+    }
+
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        self.limiter.acquire(estimate_request_tokens(req)).await?;
+        self.inner.complete_stream_with_tools(req).await
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+/// 带限流的向量模型包装
+pub struct RateLimitedEmbedModel {
+    inner: Arc<dyn EmbedModel>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedEmbedModel {
+    pub fn new(inner: Arc<dyn EmbedModel>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl EmbedModel for RateLimitedEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let tokens = texts.iter().map(|t| estimate_tokens(t) as u64).sum();
+        self.limiter.acquire(tokens).await?;
+        self.inner.embed(texts).await
+    }
+
+    async fn embed_with_provider(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Option<String>)> {
+        let tokens = texts.iter().map(|t| estimate_tokens(t) as u64).sum();
+        self.limiter.acquire(tokens).await?;
+        self.inner.embed_with_provider(texts).await
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rpm: u32, reserve: f64, max_wait_ms: u64) -> RateLimiter {
+        RateLimiter::new(
+            "test",
+            RateLimitConfig {
+                requests_per_minute: Some(rpm),
+                tokens_per_minute: None,
+                interactive_reserve: reserve,
+                max_wait_ms,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_requests_per_minute_exhausts_bucket() {
+        let limiter = limiter(2, 0.0, 10);
+        limiter.acquire(0).await.unwrap();
+        limiter.acquire(0).await.unwrap();
+        let err = limiter.acquire(0).await.unwrap_err();
+        assert!(matches!(err, KbError::ServiceUnavailable { .. }));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_background_cannot_use_interactive_reserve() {
+        let limiter = limiter(10, 0.5, 10);
+        for _ in 0..5 {
+            limiter.acquire_with(0, Priority::Background).await.unwrap();
+        }
+        assert!(limiter.acquire_with(0, Priority::Background).await.is_err());
+        assert!(limiter.acquire_with(0, Priority::Interactive).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_priority_scope() {
+        assert_eq!(current_priority(), Priority::Interactive);
+        let p = with_priority(Priority::Background, async { current_priority() }).await;
+        assert_eq!(p, Priority::Background);
+    }
+
+    #[test]
+    fn test_token_bucket_wait_time() {
+        let mut bucket = Bucket::new(60);
+        bucket.available = 0.0;
+        assert_eq!(bucket.wait_for(1.0, 0.0), Duration::from_secs(1));
+        bucket.refill(Duration::from_secs(2));
+        assert_eq!(bucket.wait_for(1.0, 0.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_reconcile_debt_is_capped() {
+        let limiter = RateLimiter::new(
+            "test",
+            RateLimitConfig {
+                requests_per_minute: None,
+                tokens_per_minute: Some(600),
+                interactive_reserve: 0.0,
+                max_wait_ms: 10,
+            },
+        );
+        limiter.reconcile(10, 1_000_000);
+        let available = limiter
+            .state
+            .lock()
+            .unwrap()
+            .tokens
+            .as_ref()
+            .unwrap()
+            .available;
+        assert_eq!(available, -600.0);
+    }
+}
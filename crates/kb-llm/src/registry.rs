@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 模型元数据，未知的字段为空
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// 向量维度（仅向量模型）
+    #[serde(default)]
+    pub embedding_dimension: Option<usize>,
+    /// 单条输入的最大 token 数（仅向量模型）
+    #[serde(default)]
+    pub max_input_tokens: Option<usize>,
+    /// 上下文窗口 token 数（仅对话模型）
+    #[serde(default)]
+    pub context_window: Option<usize>,
+    /// 单次输出的最大 token 数（仅对话模型）
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+}
+
+/// 估算上下文预算时为 system 提示与用户问题预留的 token 数
+const PROMPT_OVERHEAD_TOKENS: usize = 512;
+
+/// 未知最大输出时为回答预留的 token 数
+const DEFAULT_OUTPUT_RESERVE: usize = 1024;
+
+impl ModelInfo {
+    pub fn embedding(dimension: usize, max_input_tokens: usize) -> Self {
+        Self {
+            embedding_dimension: Some(dimension),
+            max_input_tokens: Some(max_input_tokens),
+            ..Default::default()
+        }
+    }
+
+    pub fn chat(context_window: usize, max_output_tokens: usize) -> Self {
+        Self {
+            context_window: Some(context_window),
+            max_output_tokens: Some(max_output_tokens),
+            ..Default::default()
+        }
+    }
+
+    /// 以 `other` 中非空的字段覆盖当前值
+    pub fn merge(&self, other: &ModelInfo) -> ModelInfo {
+        ModelInfo {
+            embedding_dimension: other.embedding_dimension.or(self.embedding_dimension),
+            max_input_tokens: other.max_input_tokens.or(self.max_input_tokens),
+            context_window: other.context_window.or(self.context_window),
+            max_output_tokens: other.max_output_tokens.or(self.max_output_tokens),
+        }
+    }
+
+    /// 检索上下文可用的 token 数：上下文窗口扣除回答与提示的预留
+    pub fn context_budget(&self) -> Option<usize> {
+        let window = self.context_window?;
+        let reserve = self
+            .max_output_tokens
+            .unwrap_or(DEFAULT_OUTPUT_RESERVE)
+            .min(window / 2);
+        Some(window.saturating_sub(reserve + PROMPT_OVERHEAD_TOKENS))
+    }
+}
+
+/// 内置向量模型：(模型名, 向量维度, 单条最大输入)
+const EMBEDDING_MODELS: &[(&str, usize, usize)] = &[
+    // OpenAI
+    ("text-embedding-3-small", 1536, 8191),
+    ("text-embedding-3-large", 3072, 8191),
+    ("text-embedding-ada-002", 1536, 8191),
+    // 通义千问
+    ("text-embedding-v1", 1536, 2048),
+    ("text-embedding-v2", 1536, 2048),
+    ("text-embedding-v3", 1024, 8192),
+    // Ollama
+    ("nomic-embed-text", 768, 8192),
+    ("mxbai-embed-large", 1024, 512),
+    ("bge-m3", 1024, 8192),
+];
+
+/// 内置对话模型：(模型名, 上下文窗口, 最大输出)
+const CHAT_MODELS: &[(&str, usize, usize)] = &[
+    // OpenAI
+    ("gpt-4o", 128_000, 16_384),
+    ("gpt-4o-mini", 128_000, 16_384),
+    ("gpt-4-turbo", 128_000, 4096),
+    ("gpt-3.5-turbo", 16_385, 4096),
+    // Anthropic
+    ("claude-3-5-sonnet-latest", 200_000, 8192),
+    ("claude-3-5-haiku-latest", 200_000, 8192),
+    ("claude-3-opus-latest", 200_000, 4096),
+    // DeepSeek
+    ("deepseek-chat", 64_000, 8192),
+    ("deepseek-reasoner", 64_000, 8192),
+    // 通义千问
+    ("qwen-turbo", 131_072, 8192),
+    ("qwen-plus", 131_072, 8192),
+    ("qwen-max", 32_768, 8192),
+    // Ollama
+    ("llama3.1", 131_072, 4096),
+    ("qwen2.5", 32_768, 8192),
+];
+
+/// 模型注册表：内置常用模型的维度与上下文限制，可由配置覆盖或补充
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    models: HashMap<String, ModelInfo>,
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl ModelRegistry {
+    /// 空注册表
+    pub fn empty() -> Self {
+        Self {
+            models: HashMap::new(),
+        }
+    }
+
+    /// 含内置模型的注册表
+    pub fn builtin() -> Self {
+        let embedding = EMBEDDING_MODELS
+            .iter()
+            .map(|(name, dim, input)| (name.to_string(), ModelInfo::embedding(*dim, *input)));
+        let chat = CHAT_MODELS
+            .iter()
+            .map(|(name, window, output)| (name.to_string(), ModelInfo::chat(*window, *output)));
+        let models = embedding.chain(chat).collect();
+        Self { models }
+    }
+
+    /// 注册或覆盖模型元数据，已存在的模型按字段合并
+    pub fn register(&mut self, name: impl Into<String>, info: ModelInfo) {
+        let name = name.into();
+        let merged = match self.models.get(&name) {
+            Some(existing) => existing.merge(&info),
+            None => info,
+        };
+        self.models.insert(name, merged);
+    }
+
+    /// 按配置覆盖（如 YAML 中的 `models` 段）
+    pub fn with_overrides(mut self, overrides: HashMap<String, ModelInfo>) -> Self {
+        for (name, info) in overrides {
+            self.register(name, info);
+        }
+        self
+    }
+
+    /// 查找模型元数据：依次尝试完整名称、去掉提供商前缀（如 `openai_compat/`）、去掉 Ollama 标签（如 `:latest`）
+    pub fn get(&self, model: &str) -> Option<&ModelInfo> {
+        let bare = model.rsplit_once('/').map(|(_, m)| m).unwrap_or(model);
+        let untagged = bare.split_once(':').map(|(m, _)| m).unwrap_or(bare);
+        self.models
+            .get(model)
+            .or_else(|| self.models.get(bare))
+            .or_else(|| self.models.get(untagged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_strips_provider_prefix_and_tag() {
+        let registry = ModelRegistry::builtin();
+        let info = registry
+            .get("openai_compat/text-embedding-3-small")
+            .unwrap();
+        assert_eq!(info.embedding_dimension, Some(1536));
+        assert_eq!(
+            registry
+                .get("ollama/nomic-embed-text:latest")
+                .unwrap()
+                .embedding_dimension,
+            Some(768)
+        );
+        assert!(registry.get("unknown-model").is_none());
+    }
+
+    #[test]
+    fn test_overrides_merge_fields() {
+        let registry = ModelRegistry::builtin().with_overrides(HashMap::from([
+            (
+                "gpt-4o".to_string(),
+                ModelInfo {
+                    context_window: Some(32_000),
+                    ..Default::default()
+                },
+            ),
+            ("my-embed".to_string(), ModelInfo::embedding(384, 512)),
+        ]));
+        let gpt = registry.get("gpt-4o").unwrap();
+        assert_eq!(gpt.context_window, Some(32_000));
+        assert_eq!(gpt.max_output_tokens, Some(16_384));
+        assert_eq!(
+            registry.get("my-embed").unwrap().embedding_dimension,
+            Some(384)
+        );
+    }
+
+    #[test]
+    fn test_context_budget_reserves_output() {
+        assert_eq!(ModelInfo::chat(8192, 1024).context_budget(), Some(6656));
+        // 最大输出超过窗口一半时只预留一半
+        assert_eq!(ModelInfo::chat(4096, 4096).context_budget(), Some(1536));
+        assert_eq!(ModelInfo::default().context_budget(), None);
+    }
+}
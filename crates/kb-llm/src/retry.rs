@@ -0,0 +1,605 @@
+use async_trait::async_trait;
+use kb_error::{KbError, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::{ChatDeltaStream, ChatModel, ChatRequest, ChatResponse, ChatStream, EmbedModel};
+
+/// 重试、超时与熔断配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// 首次请求之外的最大重试次数
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// 指数退避的基础延迟
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// 单次等待的上限；服务端要求的 Retry-After 超过该值时放弃重试
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    /// 是否对退避延迟加随机抖动
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+    /// 单次请求超时，为空表示不限制
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+fn default_timeout_ms() -> Option<u64> {
+    Some(60_000)
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+            jitter: default_jitter(),
+            timeout_ms: default_timeout_ms(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+        }
+    }
+}
+
+/// 熔断配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// 连续可重试失败达到该次数后熔断，0 表示关闭熔断
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// 熔断持续时间，到期后放行一次探测请求
+    #[serde(default = "default_open_ms")]
+    pub open_ms: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_ms() -> u64 {
+    30_000
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            open_ms: default_open_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// 探测请求进行中；探测被取消而未回报结果时，到期后重新放行探测
+    HalfOpen {
+        probe_until: Instant,
+    },
+}
+
+/// 熔断器：连续失败后短路请求，冷却期结束后半开放行一次探测，探测期间其余请求仍被短路
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// 检查是否放行请求；熔断中或探测进行中返回建议的等待时间
+    fn acquire(&self) -> std::result::Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Open { until } | BreakerState::HalfOpen { probe_until: until }
+                if now < until =>
+            {
+                Err(until - now)
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen {
+                    probe_until: now + Duration::from_millis(self.config.open_ms),
+                };
+                Ok(())
+            }
+            BreakerState::Closed { .. } => Ok(()),
+        }
+    }
+
+    fn on_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    fn on_failure(&self) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            // 半开探测失败立即重新熔断
+            BreakerState::HalfOpen { .. } => self.config.failure_threshold,
+            BreakerState::Open { .. } => return,
+        };
+        *state = if failures >= self.config.failure_threshold {
+            BreakerState::Open {
+                until: Instant::now() + Duration::from_millis(self.config.open_ms),
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+
+    /// 当前是否处于熔断状态
+    pub fn is_open(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), BreakerState::Open { until } if Instant::now() < until)
+    }
+}
+
+/// 单个提供商的重试策略，持有该提供商的熔断器
+#[derive(Debug)]
+pub struct RetryPolicy {
+    provider: String,
+    config: RetryConfig,
+    breaker: CircuitBreaker,
+}
+
+impl RetryPolicy {
+    pub fn new(provider: impl Into<String>, config: RetryConfig) -> Self {
+        Self {
+            provider: provider.into(),
+            breaker: CircuitBreaker::new(config.circuit_breaker.clone()),
+            config,
+        }
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// 第 `attempt` 次重试前的退避延迟（不含服务端 Retry-After）
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .config
+            .base_delay_ms
+            .saturating_mul(1u64 << attempt.min(20))
+            .min(self.config.max_delay_ms);
+        let ms = if self.config.jitter && exp > 1 {
+            // 等比抖动：在 [exp/2, exp] 内随机，避免多个客户端同时重试
+            exp / 2 + rand::thread_rng().gen_range(0..=exp / 2)
+        } else {
+            exp
+        };
+        Duration::from_millis(ms)
+    }
+
+    /// 按策略执行操作：单次超时、可重试错误退避重试、熔断短路
+    pub async fn execute<T, F, Fut>(&self, operation: &str, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let mut attempt = 0u32;
+        loop {
+            if let Err(remaining) = self.breaker.acquire() {
+                return Err(KbError::ServiceUnavailable {
+                    service: self.provider.clone(),
+                    retry_after: Some(remaining),
+                });
+            }
+
+            let result = match self.config.timeout_ms {
+                Some(ms) => tokio::time::timeout(Duration::from_millis(ms), f())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(KbError::Timeout {
+                            operation: format!("{}.{}", self.provider, operation),
+                            timeout_ms: ms,
+                        })
+                    }),
+                None => f().await,
+            };
+
+            let err = match result {
+                Ok(v) => {
+                    self.breaker.on_success();
+                    return Ok(v);
+                }
+                Err(e) => e,
+            };
+            if !err.is_retryable() {
+                // 非瞬时错误说明服务可达，不计入熔断
+                self.breaker.on_success();
+                return Err(err);
+            }
+            self.breaker.on_failure();
+
+            if attempt >= self.config.max_retries {
+                warn!(provider = %self.provider, operation, attempts = attempt + 1, error = %err, "重试次数耗尽");
+                return Err(err);
+            }
+            let server_delay = err.retry_after().unwrap_or_default();
+            if server_delay > Duration::from_millis(self.config.max_delay_ms) {
+                warn!(provider = %self.provider, operation, retry_after_ms = server_delay.as_millis() as u64, "Retry-After 超过上限，放弃重试");
+                return Err(err);
+            }
+            let delay = self.backoff(attempt).max(server_delay);
+            debug!(
+                provider = %self.provider,
+                operation,
+                attempt = attempt + 1,
+                delay_ms = delay.as_millis() as u64,
+                error = %err,
+                "请求失败，退避后重试"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// 从响应头解析 `Retry-After`（秒）或 `retry-after-ms`（毫秒）
+pub(crate) fn retry_after_from_headers(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let get = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(ms) = get("retry-after-ms").and_then(|v| v.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    get("retry-after")
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .map(|secs| Duration::from_millis((secs.max(0.0) * 1000.0) as u64))
+}
+
+/// 带重试与熔断的对话模型包装
+pub struct RetryChatModel {
+    inner: Arc<dyn ChatModel>,
+    policy: RetryPolicy,
+}
+
+impl RetryChatModel {
+    pub fn new(
+        inner: Arc<dyn ChatModel>,
+        provider: impl Into<String>,
+        config: RetryConfig,
+    ) -> Self {
+        Self {
+            inner,
+            policy: RetryPolicy::new(provider, config),
+        }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+#[async_trait]
+impl ChatModel for RetryChatModel {
+    async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+        self.policy
+            .execute("complete", || self.inner.complete(req))
+            .await
+    }
+
+    /// 仅对建立流的请求重试，流开始后的错误直接透传
+    async fn complete_stream(&self, req: &ChatRequest) -> Result<ChatStream> {
+        self.policy
+            .execute("complete_stream", || self.inner.complete_stream(req))
+            .await
+    }
+
+    async fn complete_stream_with_tools(&self, req: &ChatRequest) -> Result<ChatDeltaStream> {
+        self.policy
+            .execute("complete_stream_with_tools", || {
+                self.inner.complete_stream_with_tools(req)
+            })
+            .await
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+/// 带重试与熔断的向量模型包装
+pub struct RetryEmbedModel {
+    inner: Arc<dyn EmbedModel>,
+    policy: RetryPolicy,
+}
+
+impl RetryEmbedModel {
+    pub fn new(
+        inner: Arc<dyn EmbedModel>,
+        provider: impl Into<String>,
+        config: RetryConfig,
+    ) -> Self {
+        Self {
+            inner,
+            policy: RetryPolicy::new(provider, config),
+        }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+#[async_trait]
+impl EmbedModel for RetryEmbedModel {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.policy
+            .execute("embed", || self.inner.embed(texts))
+            .await
+    }
+
+    async fn embed_with_provider(
+        &self,
+        texts: &[String],
+    ) -> Result<(Vec<Vec<f32>>, Option<String>)> {
+        self.policy
+            .execute("embed", || self.inner.embed_with_provider(texts))
+            .await
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.inner.verify().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatMessage, OpenAiCompatClient, OpenAiCompatConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// 预设响应：状态码、额外响应头、响应体
+    type MockResponse = (u16, Vec<(&'static str, &'static str)>, &'static str);
+
+    /// 极简 HTTP 模拟服务：按顺序返回预设响应
+    async fn mock_server(responses: Vec<MockResponse>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            for (status, headers, body) in responses {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                read_request(&mut socket).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut resp = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n",
+                    status,
+                    body.len()
+                );
+                for (k, v) in headers {
+                    resp.push_str(&format!("{}: {}\r\n", k, v));
+                }
+                resp.push_str("\r\n");
+                resp.push_str(body);
+                let _ = socket.write_all(resp.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (format!("http://{}", addr), hits)
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap_or(0);
+            if n == 0 {
+                return;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let content_length = text[..end]
+                    .lines()
+                    .find_map(|l| {
+                        let (k, v) = l.split_once(':')?;
+                        k.eq_ignore_ascii_case("content-length")
+                            .then(|| v.trim().parse::<usize>().ok())
+                            .flatten()
+                    })
+                    .unwrap_or(0);
+                if buf.len() >= end + 4 + content_length {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn client(base_url: String) -> Arc<dyn ChatModel> {
+        Arc::new(OpenAiCompatClient::new(OpenAiCompatConfig {
+            base_url,
+            api_key: "test".to_string(),
+            chat_model: "gpt-test".to_string(),
+            embedding_model: None,
+        }))
+    }
+
+    fn fast_config() -> RetryConfig {
+        RetryConfig {
+            max_retries: 2,
+            base_delay_ms: 1,
+            max_delay_ms: 2_000,
+            jitter: false,
+            timeout_ms: Some(2_000),
+            circuit_breaker: CircuitBreakerConfig::default(),
+        }
+    }
+
+    const OK_BODY: &str = r#"{"choices":[{"message":{"content":"pong"}}]}"#;
+
+    #[tokio::test]
+    async fn test_retries_rate_limited_request() {
+        let (url, hits) = mock_server(vec![
+            (
+                429,
+                vec![("retry-after", "0")],
+                r#"{"error":"rate limited"}"#,
+            ),
+            (503, vec![], "{}"),
+            (200, vec![], OK_BODY),
+        ])
+        .await;
+        let model = RetryChatModel::new(client(url), "openai", fast_config());
+
+        let resp = model
+            .complete(&ChatRequest::new(vec![ChatMessage::user("ping")]))
+            .await
+            .unwrap();
+        assert_eq!(resp.content, "pong");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let (url, hits) = mock_server(vec![(400, vec![], "{}"), (200, vec![], OK_BODY)]).await;
+        let model = RetryChatModel::new(client(url), "openai", fast_config());
+
+        let err = model.chat("s", "c", "u").await.unwrap_err();
+        assert!(matches!(
+            err,
+            KbError::LlmService {
+                retry_after: None,
+                ..
+            }
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_retry_after_exceeds_limit() {
+        let (url, hits) = mock_server(vec![
+            (429, vec![("retry-after", "120")], "{}"),
+            (200, vec![], OK_BODY),
+        ])
+        .await;
+        let model = RetryChatModel::new(client(url), "openai", fast_config());
+
+        let err = model.chat("s", "c", "u").await.unwrap_err();
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(120)));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// 始终返回可重试错误的模型
+    struct FailingChat {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ChatModel for FailingChat {
+        async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(KbError::LlmService {
+                provider: "mock".to_string(),
+                message: "overloaded".to_string(),
+                retry_after: Some(Duration::ZERO),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_short_circuits() {
+        let inner = Arc::new(FailingChat {
+            calls: AtomicUsize::new(0),
+        });
+        let config = RetryConfig {
+            max_retries: 0,
+            circuit_breaker: CircuitBreakerConfig {
+                failure_threshold: 2,
+                open_ms: 60_000,
+            },
+            ..fast_config()
+        };
+        let model = RetryChatModel::new(inner.clone(), "mock", config);
+
+        for _ in 0..2 {
+            assert!(model.chat("s", "c", "u").await.is_err());
+        }
+        assert!(model.policy().breaker().is_open());
+        let err = model.chat("s", "c", "u").await.unwrap_err();
+        assert!(matches!(err, KbError::ServiceUnavailable { .. }));
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_half_open_admits_single_probe() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_ms: 200,
+        });
+        breaker.on_failure();
+        assert!(breaker.acquire().is_err());
+        std::thread::sleep(Duration::from_millis(250));
+        // 冷却期已过，仅第一个请求作为探测放行
+        assert!(breaker.acquire().is_ok());
+        assert!(breaker.acquire().is_err());
+        breaker.on_success();
+        assert!(breaker.acquire().is_ok());
+        assert!(breaker.acquire().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_is_retryable() {
+        struct SlowChat;
+
+        #[async_trait]
+        impl ChatModel for SlowChat {
+            async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(ChatResponse::default())
+            }
+        }
+
+        let config = RetryConfig {
+            max_retries: 0,
+            timeout_ms: Some(10),
+            ..fast_config()
+        };
+        let model = RetryChatModel::new(Arc::new(SlowChat), "slow", config);
+        let err = model.chat("s", "c", "u").await.unwrap_err();
+        assert!(matches!(err, KbError::Timeout { timeout_ms: 10, .. }));
+    }
+}
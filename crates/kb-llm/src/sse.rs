@@ -0,0 +1,157 @@
+use futures::{Stream, StreamExt};
+use kb_error::{KbError, Result};
+
+/// 一条 Server-Sent Events 事件
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// 增量 SSE 解码器：按字节缓冲，遇到空行切分事件，避免在 UTF-8 字符中间截断
+#[derive(Default)]
+pub(crate) struct SseDecoder {
+    buf: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// 追加一段字节并返回其中已完整的事件
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some((end, sep_len)) = find_event_boundary(&self.buf) {
+            let block: Vec<u8> = self.buf.drain(..end + sep_len).take(end).collect();
+            if let Some(ev) = parse_block(&String::from_utf8_lossy(&block)) {
+                events.push(ev);
+            }
+        }
+        events
+    }
+
+    /// 连接结束时处理缓冲区中剩余的最后一个事件
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let block = std::mem::take(&mut self.buf);
+        parse_block(&String::from_utf8_lossy(&block))
+    }
+}
+
+fn find_event_boundary(buf: &[u8]) -> Option<(usize, usize)> {
+    let lf = buf.windows(2).position(|w| w == b"\n\n").map(|p| (p, 2));
+    let crlf = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|p| (p, 4));
+    match (lf, crlf) {
+        (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+fn parse_block(block: &str) -> Option<SseEvent> {
+    let mut event = None;
+    let mut data_lines = Vec::new();
+    for line in block.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((f, v)) => (f, v.strip_prefix(' ').unwrap_or(v)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => event = Some(value.to_string()),
+            "data" => data_lines.push(value.to_string()),
+            _ => {}
+        }
+    }
+    if event.is_none() && data_lines.is_empty() {
+        return None;
+    }
+    Some(SseEvent {
+        event,
+        data: data_lines.join("\n"),
+    })
+}
+
+/// 将 HTTP 响应体转换为 SSE 事件流
+pub(crate) fn sse_events(
+    resp: reqwest::Response,
+) -> impl Stream<Item = Result<SseEvent>> + Send + 'static {
+    let bytes = resp.bytes_stream();
+    futures::stream::unfold(
+        (
+            bytes,
+            SseDecoder::default(),
+            std::collections::VecDeque::new(),
+            false,
+        ),
+        |(mut bytes, mut decoder, mut pending, mut done)| async move {
+            loop {
+                if let Some(ev) = pending.pop_front() {
+                    return Some((Ok(ev), (bytes, decoder, pending, done)));
+                }
+                if done {
+                    return None;
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => pending.extend(decoder.push(&chunk)),
+                    Some(Err(e)) => {
+                        let err = KbError::Network {
+                            operation: "sse_stream".to_string(),
+                            message: e.to_string(),
+                        };
+                        return Some((Err(err), (bytes, decoder, pending, true)));
+                    }
+                    None => {
+                        done = true;
+                        pending.extend(decoder.finish());
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_splits_events_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: {\"a\":").is_empty());
+        let events = decoder.push(b"1}\n\nevent: ping\ndata: x\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: None,
+                    data: "{\"a\":1}".to_string(),
+                },
+                SseEvent {
+                    event: Some("ping".to_string()),
+                    data: "x".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decoder_keeps_multibyte_chars_intact() {
+        let mut decoder = SseDecoder::default();
+        let bytes = "data: 你好\r\n\r\n".as_bytes();
+        // 在“你”的 UTF-8 字节中间切开
+        assert!(decoder.push(&bytes[..7]).is_empty());
+        let events = decoder.push(&bytes[7..]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "你好");
+    }
+
+    #[test]
+    fn test_decoder_flushes_trailing_event() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b": comment\n\ndata: [DONE]").is_empty());
+        assert_eq!(decoder.finish().unwrap().data, "[DONE]");
+    }
+}
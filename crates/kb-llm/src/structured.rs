@@ -0,0 +1,190 @@
+use kb_error::{KbError, Result};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use tracing::debug;
+
+use crate::{ChatMessage, ChatModel, ChatRequest, ResponseFormat, Role};
+
+/// 解析失败后要求模型修正输出的默认次数
+pub const DEFAULT_MAX_REPAIRS: usize = 2;
+
+/// 生成类型 `T` 的 JSON Schema，用作结构化输出约束
+///
+/// Anthropic 以强制工具调用实现结构化输出，工具参数必须是对象，因此 `T` 应为结构体。
+pub fn json_schema_for<T: JsonSchema>() -> ResponseFormat {
+    let mut schema = serde_json::to_value(schemars::schema_for!(T)).unwrap_or_default();
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+    }
+    // OpenAI 要求名称仅含字母、数字、下划线与连字符
+    let name: String = T::schema_name()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    ResponseFormat::JsonSchema { name, schema }
+}
+
+/// 从模型输出中取出 JSON 文本：去掉 Markdown 代码块，截取首个对象或数组
+pub fn extract_json(text: &str) -> &str {
+    let mut s = text.trim();
+    if let Some(rest) = s.strip_prefix("```") {
+        let rest = rest.strip_prefix("json").unwrap_or(rest);
+        s = rest.trim_end().strip_suffix("```").unwrap_or(rest).trim();
+    }
+    let start = s.find(['{', '[']);
+    let end = s.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => &s[start..=end],
+        _ => s,
+    }
+}
+
+/// 按类型 `T` 的 JSON Schema 约束生成并解析结构化输出，解析失败时最多修正 [`DEFAULT_MAX_REPAIRS`] 次
+pub async fn chat_json<T>(model: &dyn ChatModel, req: ChatRequest) -> Result<T>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    chat_json_with(model, req, DEFAULT_MAX_REPAIRS).await
+}
+
+/// 同 [`chat_json`]，指定修正次数上限
+pub async fn chat_json_with<T>(
+    model: &dyn ChatModel,
+    mut req: ChatRequest,
+    max_repairs: usize,
+) -> Result<T>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let format = json_schema_for::<T>();
+    if let ResponseFormat::JsonSchema { schema, .. } = &format {
+        // 不支持 response_format 的兼容接口仍可依据提示输出
+        let instruction = format!(
+            "Respond only with a JSON value that matches this JSON Schema, without any other text:\n{}",
+            schema
+        );
+        let at = req
+            .messages
+            .iter()
+            .take_while(|m| m.role == Role::System)
+            .count();
+        req.messages.insert(at, ChatMessage::system(instruction));
+    }
+    req.response_format = Some(format);
+
+    let mut attempt = 0;
+    loop {
+        let resp = model.complete(&req).await?;
+        let err = match serde_json::from_str::<T>(extract_json(&resp.content)) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if attempt >= max_repairs {
+            return Err(KbError::Serialization {
+                format: "json".to_string(),
+                message: format!(
+                    "structured output invalid after {} repairs: {}",
+                    max_repairs, err
+                ),
+            });
+        }
+        attempt += 1;
+        debug!(attempt, error = %err, "结构化输出解析失败，要求模型修正");
+        req.messages.push(ChatMessage::assistant(resp.content));
+        req.messages.push(ChatMessage::user(format!(
+            "The previous output is invalid: {}. Reply again with only the corrected JSON.",
+            err
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatResponse;
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct QueryClass {
+        intent: String,
+        confidence: f32,
+    }
+
+    /// 依次返回预设输出，并记录收到的请求
+    struct ScriptedChat {
+        outputs: Mutex<Vec<&'static str>>,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedChat {
+        fn new(outputs: Vec<&'static str>) -> Self {
+            Self {
+                outputs: Mutex::new(outputs),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatModel for ScriptedChat {
+        async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+            self.requests.lock().unwrap().push(req.clone());
+            Ok(ChatResponse {
+                content: self.outputs.lock().unwrap().remove(0).to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn request() -> ChatRequest {
+        ChatRequest::new(vec![
+            ChatMessage::system("Classify the query."),
+            ChatMessage::user("如何申请退款"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_repairs_invalid_output() {
+        let chat = ScriptedChat::new(vec![
+            r#"{"intent": "refund"}"#,
+            "```json\n{\"intent\": \"refund\", \"confidence\": 0.9}\n```",
+        ]);
+        let out: QueryClass = chat_json(&chat, request()).await.unwrap();
+        assert_eq!(out.intent, "refund");
+
+        let requests = chat.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(matches!(
+            &requests[0].response_format,
+            Some(ResponseFormat::JsonSchema { name, .. }) if name == "QueryClass"
+        ));
+        assert_eq!(requests[0].messages[1].role, Role::System);
+        let repair = requests[1].messages.last().unwrap();
+        assert!(repair.content.contains("confidence"));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_repairs() {
+        let chat = ScriptedChat::new(vec!["not json", "still not json"]);
+        let err = chat_json_with::<QueryClass>(&chat, request(), 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KbError::Serialization { .. }));
+        assert_eq!(chat.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_extract_json() {
+        assert_eq!(extract_json("Here you go: {\"a\": 1} thanks"), "{\"a\": 1}");
+        assert_eq!(extract_json("```json\n[1, 2]\n```"), "[1, 2]");
+        assert_eq!(extract_json("plain"), "plain");
+    }
+}
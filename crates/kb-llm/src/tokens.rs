@@ -0,0 +1,103 @@
+/// 粗略估算文本的 token 数：CJK 等宽字符按 1 个 token，其余按约 4 个字符 1 个 token
+///
+/// 不同模型的分词器差异较大，估算值仅用于切分批次与预算控制，应预留余量。
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut wide = 0usize;
+    let mut narrow = 0usize;
+    for c in text.chars() {
+        if is_wide(c) {
+            wide += 1;
+        } else {
+            narrow += 1;
+        }
+    }
+    wide + narrow.div_ceil(4)
+}
+
+fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF       // 韩文字母
+        | 0x2E80..=0x9FFF     // CJK 部首、假名、统一汉字
+        | 0xAC00..=0xD7AF     // 韩文音节
+        | 0xF900..=0xFAFF     // CJK 兼容汉字
+        | 0xFF00..=0xFFEF     // 全角符号
+        | 0x20000..=0x2FFFF) // CJK 扩展
+}
+
+/// 按字节计长的限制（如分块大小）对应 `tokens` 个 token 时的保守字节数：
+/// CJK 字符约 3 字节 1 个 token，英文约 4 字节 1 个 token，取较小者
+pub fn bytes_for_tokens(tokens: usize) -> usize {
+    tokens * 3
+}
+
+/// 截取不超过 `max_tokens` 的最长前缀，保证在字符边界处截断
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> &str {
+    let end = prefix_end(text, max_tokens);
+    &text[..end]
+}
+
+/// 按 `max_tokens` 将文本切分为若干连续片段，优先在空白处断开
+pub fn split_by_tokens(text: &str, max_tokens: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = prefix_end(rest, max_tokens.max(1));
+        if end == 0 {
+            // 单个字符即超出预算时至少前进一个字符
+            end = rest
+                .chars()
+                .next()
+                .map(char::len_utf8)
+                .unwrap_or(rest.len());
+        }
+        if end < rest.len() {
+            if let Some(ws) = rest[..end].rfind(char::is_whitespace) {
+                if ws > end / 2 {
+                    end = ws + rest[ws..].chars().next().map(char::len_utf8).unwrap_or(1);
+                }
+            }
+        }
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    pieces
+}
+
+/// 估算 token 数不超过 `max_tokens` 的最长前缀的字节长度
+fn prefix_end(text: &str, max_tokens: usize) -> usize {
+    let mut wide = 0usize;
+    let mut narrow = 0usize;
+    for (i, c) in text.char_indices() {
+        if is_wide(c) {
+            wide += 1;
+        } else {
+            narrow += 1;
+        }
+        if wide + narrow.div_ceil(4) > max_tokens {
+            return i;
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("向量检索"), 4);
+    }
+
+    #[test]
+    fn test_truncate_and_split_respect_char_boundaries() {
+        assert_eq!(truncate_to_tokens("向量检索引擎", 3), "向量检");
+        let text = "知识库 rust 向量检索 hybrid search";
+        let pieces = split_by_tokens(text, 4);
+        assert_eq!(pieces.concat(), text);
+        assert!(pieces.iter().all(|p| estimate_tokens(p) <= 4));
+    }
+}
@@ -0,0 +1,317 @@
+use kb_error::{KbError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::tokens::estimate_tokens;
+use crate::ChatRequest;
+
+/// 一次或多次调用的 token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub embedding_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn chat(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            ..Default::default()
+        }
+    }
+
+    pub fn embedding(embedding_tokens: u64) -> Self {
+        Self {
+            embedding_tokens,
+            ..Default::default()
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens + self.embedding_tokens
+    }
+
+    /// 提供商未返回用量时按文本估算
+    pub fn estimate_chat(req: &ChatRequest, completion: &str) -> Self {
+        let prompt: usize = req
+            .messages
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum();
+        Self::chat(prompt as u64, estimate_tokens(completion) as u64)
+    }
+
+    pub fn estimate_embedding(texts: &[String]) -> Self {
+        Self::embedding(texts.iter().map(|t| estimate_tokens(t) as u64).sum())
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.embedding_tokens += other.embedding_tokens;
+    }
+}
+
+/// 单个模型的价格（每千 token）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub prompt_per_1k: f64,
+    #[serde(default)]
+    pub completion_per_1k: f64,
+    #[serde(default)]
+    pub embedding_per_1k: f64,
+}
+
+/// 按模型名配置的价格表，未配置的模型按零费用计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PriceTable(pub HashMap<String, ModelPrice>);
+
+impl PriceTable {
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> f64 {
+        let Some(price) = self.0.get(model) else {
+            return 0.0;
+        };
+        (usage.prompt_tokens as f64 * price.prompt_per_1k
+            + usage.completion_tokens as f64 * price.completion_per_1k
+            + usage.embedding_tokens as f64 * price.embedding_per_1k)
+            / 1000.0
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<Mutex<HashMap<String, TokenUsage>>>;
+}
+
+/// 记录一次模型调用的用量，仅在 [`collect_usage`] 的作用域内生效
+///
+/// 用量按任务局部变量归集，`tokio::spawn` 出的子任务中的调用不会计入。
+pub fn record(model: &str, usage: TokenUsage) {
+    let _ = CURRENT.try_with(|current| {
+        *current
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default() += usage;
+    });
+}
+
+/// 执行 `f` 并归集其间所有模型调用的用量
+pub async fn collect_usage<F: Future>(f: F) -> (F::Output, UsageReport) {
+    let current = Arc::new(Mutex::new(HashMap::new()));
+    let output = CURRENT.scope(current.clone(), f).await;
+    let by_model = std::mem::take(&mut *current.lock().unwrap());
+    (output, UsageReport { by_model })
+}
+
+/// 一次请求内按模型归集的用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub by_model: HashMap<String, TokenUsage>,
+}
+
+impl UsageReport {
+    pub fn total(&self) -> TokenUsage {
+        let mut total = TokenUsage::default();
+        for usage in self.by_model.values() {
+            total += *usage;
+        }
+        total
+    }
+
+    pub fn cost(&self, prices: &PriceTable) -> f64 {
+        self.by_model
+            .iter()
+            .map(|(model, usage)| prices.cost(model, usage))
+            .sum()
+    }
+}
+
+/// 租户配额，均为空表示不限
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaLimit {
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub max_cost: Option<f64>,
+}
+
+/// 配额配置：按租户覆盖，未配置的租户使用 `default`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub default: Option<QuotaLimit>,
+    #[serde(default)]
+    pub tenants: HashMap<String, QuotaLimit>,
+}
+
+impl QuotaConfig {
+    fn limit_for(&self, tenant: &str) -> Option<&QuotaLimit> {
+        self.tenants.get(tenant).or(self.default.as_ref())
+    }
+}
+
+/// 累计用量与费用
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub usage: TokenUsage,
+    pub cost: f64,
+    pub requests: u64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, usage: TokenUsage, cost: f64) {
+        self.usage += usage;
+        self.cost += cost;
+        self.requests += 1;
+    }
+}
+
+/// 按租户与用户汇总的用量快照
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSnapshot {
+    pub tenants: HashMap<String, UsageTotals>,
+    pub users: HashMap<String, HashMap<String, UsageTotals>>,
+}
+
+/// 按租户与用户累计用量并执行配额检查（进程内累计，重启或 [`UsageLedger::reset`] 后清零）
+#[derive(Default)]
+pub struct UsageLedger {
+    prices: PriceTable,
+    quotas: QuotaConfig,
+    totals: Mutex<UsageSnapshot>,
+}
+
+impl UsageLedger {
+    pub fn new(prices: PriceTable, quotas: QuotaConfig) -> Self {
+        Self {
+            prices,
+            quotas,
+            totals: Mutex::new(UsageSnapshot::default()),
+        }
+    }
+
+    pub fn prices(&self) -> &PriceTable {
+        &self.prices
+    }
+
+    /// 请求开始前检查租户是否已超出配额
+    pub fn check_quota(&self, tenant: &str) -> Result<()> {
+        let Some(limit) = self.quotas.limit_for(tenant) else {
+            return Ok(());
+        };
+        let totals = self.totals.lock().unwrap();
+        let Some(used) = totals.tenants.get(tenant) else {
+            return Ok(());
+        };
+        if let Some(max) = limit.max_tokens {
+            if used.usage.total() >= max {
+                return Err(KbError::QuotaExceeded {
+                    resource: format!("tenant {} tokens", tenant),
+                    limit: max.to_string(),
+                });
+            }
+        }
+        if let Some(max) = limit.max_cost {
+            if used.cost >= max {
+                return Err(KbError::QuotaExceeded {
+                    resource: format!("tenant {} cost", tenant),
+                    limit: format!("{:.4}", max),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// 计入一次请求的用量，返回本次请求的费用
+    pub fn record(&self, tenant: &str, user: &str, report: &UsageReport) -> f64 {
+        let usage = report.total();
+        let cost = report.cost(&self.prices);
+        let mut totals = self.totals.lock().unwrap();
+        totals
+            .tenants
+            .entry(tenant.to_string())
+            .or_default()
+            .add(usage, cost);
+        totals
+            .users
+            .entry(tenant.to_string())
+            .or_default()
+            .entry(user.to_string())
+            .or_default()
+            .add(usage, cost);
+        cost
+    }
+
+    pub fn snapshot(&self) -> UsageSnapshot {
+        self.totals.lock().unwrap().clone()
+    }
+
+    /// 清零累计用量（如按账期结算后）
+    pub fn reset(&self) {
+        *self.totals.lock().unwrap() = UsageSnapshot::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices() -> PriceTable {
+        PriceTable(HashMap::from([(
+            "gpt-4o".to_string(),
+            ModelPrice {
+                prompt_per_1k: 0.005,
+                completion_per_1k: 0.015,
+                embedding_per_1k: 0.0,
+            },
+        )]))
+    }
+
+    #[tokio::test]
+    async fn test_collect_usage_groups_by_model() {
+        record("outside", TokenUsage::chat(1, 1));
+        let (_, report) = collect_usage(async {
+            record("gpt-4o", TokenUsage::chat(1000, 100));
+            record("gpt-4o", TokenUsage::chat(1000, 100));
+            record("embed", TokenUsage::embedding(50));
+        })
+        .await;
+        assert_eq!(report.by_model.len(), 2);
+        assert_eq!(report.total().total(), 2250);
+        assert!((report.cost(&prices()) - 0.013).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ledger_enforces_tenant_quota() {
+        let quotas = QuotaConfig {
+            default: Some(QuotaLimit {
+                max_tokens: Some(1000),
+                max_cost: None,
+            }),
+            ..Default::default()
+        };
+        let ledger = UsageLedger::new(prices(), quotas);
+        let report = UsageReport {
+            by_model: HashMap::from([("gpt-4o".to_string(), TokenUsage::chat(900, 100))]),
+        };
+        assert!(ledger.check_quota("acme").is_ok());
+        ledger.record("acme", "alice", &report);
+        assert!(matches!(
+            ledger.check_quota("acme"),
+            Err(KbError::QuotaExceeded { .. })
+        ));
+        assert!(ledger.check_quota("other").is_ok());
+        assert_eq!(ledger.snapshot().users["acme"]["alice"].requests, 1);
+    }
+}
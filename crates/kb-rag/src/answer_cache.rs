@@ -0,0 +1,482 @@
+use async_trait::async_trait;
+use kb_core::{Citation, QueryRequest, QueryResponse};
+use kb_error::Result;
+use kb_llm::{ChatStream, EmbedModel};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, instrument, warn};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagMeta};
+
+tokio::task_local! {
+    static TENANT: String;
+}
+
+/// 以指定租户身份执行 `f`，其间的回答缓存按租户隔离
+pub async fn with_tenant<F: Future>(tenant: impl Into<String>, f: F) -> F::Output {
+    TENANT.scope(tenant.into(), f).await
+}
+
+/// 当前任务的租户，未设置时为空
+pub fn current_tenant() -> Option<String> {
+    TENANT.try_with(Clone::clone).ok()
+}
+
+/// 语义回答缓存配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerCacheConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 与历史查询的向量余弦相似度达到该阈值才视为同一问题
+    #[serde(default = "default_similarity_threshold")]
+    pub similarity_threshold: f32,
+    /// 缓存有效期（秒）
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// 最多缓存的回答数，超出时淘汰最早写入的条目
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_similarity_threshold() -> f32 {
+    0.95
+}
+
+fn default_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_max_entries() -> usize {
+    1000
+}
+
+impl Default for AnswerCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            similarity_threshold: default_similarity_threshold(),
+            ttl_secs: default_ttl_secs(),
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+/// 回答缓存统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnswerCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+    pub entries: usize,
+}
+
+struct CacheEntry {
+    /// 影响检索结果的请求参数（模式、top_k、过滤条件等）
+    scope: String,
+    embedding: Vec<f32>,
+    response: QueryResponse,
+    documents: HashSet<String>,
+    created_at: Instant,
+}
+
+/// 语义回答缓存包装引擎：相同检索范围内与历史查询足够相似的问题直接返回缓存的回答
+///
+/// 引用文档被重新索引时相关条目失效；无引用的回答不缓存，以免新文档入库后仍返回"未找到"。
+pub struct CachedRagEngine {
+    inner: Arc<dyn RagEngine>,
+    embed: Arc<dyn EmbedModel>,
+    config: AnswerCacheConfig,
+    entries: Mutex<Vec<CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl CachedRagEngine {
+    pub fn new(
+        inner: Arc<dyn RagEngine>,
+        embed: Arc<dyn EmbedModel>,
+        config: AnswerCacheConfig,
+    ) -> Self {
+        Self {
+            inner,
+            embed,
+            config,
+            entries: Mutex::new(Vec::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// 使引用了指定文档的缓存回答失效（文档重新索引或删除时调用），返回失效条数
+    pub fn invalidate_document(&self, document_id: &str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|e| !e.documents.contains(document_id));
+        let removed = before - entries.len();
+        self.invalidations
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        if removed > 0 {
+            debug!(document_id, removed, "回答缓存失效");
+        }
+        removed
+    }
+
+    /// 清空缓存，返回清除的条数
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.len();
+        entries.clear();
+        self.invalidations
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    pub fn stats(&self) -> AnswerCacheStats {
+        AnswerCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_secs(self.config.ttl_secs)
+    }
+
+    /// 缓存范围：仅当租户、提示模板版本与检索参数都一致时缓存回答才可复用
+    fn scope(req: &QueryRequest) -> String {
+        let prompt = kb_llm::current_prompt();
+        serde_json::json!([
+            current_tenant(),
+            prompt.name,
+            prompt.version,
+            req.mode,
+            req.top_k,
+            req.rerank,
+            req.include_raw_matches,
+            req.filters,
+            req.expansion,
+            req.parent_window
+        ])
+        .to_string()
+    }
+
+    fn lookup(&self, scope: &str, embedding: &[f32]) -> Option<QueryResponse> {
+        let ttl = self.ttl();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.created_at.elapsed() < ttl);
+        entries
+            .iter()
+            .filter(|e| e.scope == scope)
+            .map(|e| (cosine(&e.embedding, embedding), e))
+            .filter(|(score, _)| *score >= self.config.similarity_threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(score, e)| {
+                debug!(similarity = score, "回答缓存命中");
+                e.response.clone()
+            })
+    }
+
+    fn insert(&self, scope: String, embedding: Vec<f32>, response: &QueryResponse) {
+        if response.citations.is_empty() || self.config.max_entries == 0 {
+            return;
+        }
+        let mut response = response.clone();
+        response.usage = None;
+        response.rerank_latency_ms = None;
+        let entry = CacheEntry {
+            scope,
+            embedding,
+            documents: response
+                .citations
+                .iter()
+                .map(|c| c.document_id.clone())
+                .collect(),
+            response,
+            created_at: Instant::now(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        // 条目按写入时间有序，超出容量时淘汰最早的
+        while entries.len() >= self.config.max_entries {
+            entries.remove(0);
+        }
+        entries.push(entry);
+    }
+
+    async fn embed_query(&self, query: &str) -> Option<Vec<f32>> {
+        match self.embed.embed(&[query.to_string()]).await {
+            Ok(mut vectors) => vectors.pop(),
+            Err(e) => {
+                warn!(error = %e, "回答缓存向量化查询失败，跳过缓存");
+                None
+            }
+        }
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+#[async_trait]
+impl RagEngine for CachedRagEngine {
+    #[instrument(skip(self, req))]
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        // 依赖对话历史的追问不能按问题文本复用回答
+        let has_history = req.history.as_ref().is_some_and(|h| !h.is_empty());
+        if !self.config.enabled || has_history {
+            return self.inner.query(req).await;
+        }
+        let start_time = Instant::now();
+        let Some(embedding) = self.embed_query(&req.query).await else {
+            return self.inner.query(req).await;
+        };
+        let scope = Self::scope(&req);
+        if let Some(mut response) = self.lookup(&scope, &embedding) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            response.cache_hit = true;
+            response.latency_ms = start_time.elapsed().as_millis() as i64;
+            return Ok(response);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let response = self.inner.query(req).await?;
+        self.insert(scope, embedding, &response);
+        Ok(response)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.inner.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.inner.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.inner.generate_stream(req, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        let result = self
+            .inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await;
+        // 无论成功与否都使旧回答失效，避免部分写入后仍返回过期内容
+        self.invalidate_document(document_id);
+        result
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::HashingEmbedModel;
+
+    /// 统计查询次数，引用文档固定为 "doc"
+    #[derive(Default)]
+    struct CountingEngine {
+        queries: AtomicU64,
+    }
+
+    #[async_trait]
+    impl RagEngine for CountingEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            Ok(QueryResponse {
+                answer: format!("answer: {}", req.query),
+                citations: vec![Citation {
+                    document_id: "doc".to_string(),
+                    chunk_id: "c1".to_string(),
+                    page: None,
+                    score: 1.0,
+                    snippet: "snippet".to_string(),
+                    header: None,
+                    text: None,
+                }],
+                ..Default::default()
+            })
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn cached(threshold: f32) -> (Arc<CountingEngine>, CachedRagEngine) {
+        let inner = Arc::new(CountingEngine::default());
+        let engine = CachedRagEngine::new(
+            inner.clone(),
+            Arc::new(HashingEmbedModel::default()),
+            AnswerCacheConfig {
+                similarity_threshold: threshold,
+                ..Default::default()
+            },
+        );
+        (inner, engine)
+    }
+
+    fn request(query: &str, tenant: &str) -> QueryRequest {
+        QueryRequest {
+            query: query.to_string(),
+            filters: Some(serde_json::json!({ "tenant_id": tenant })),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_similar_query_hits_within_same_scope() {
+        let (inner, engine) = cached(0.9);
+        let first = engine
+            .query(request("How do I request a refund?", "acme"))
+            .await
+            .unwrap();
+        assert!(!first.cache_hit);
+
+        let second = engine
+            .query(request("how do i request a refund", "acme"))
+            .await
+            .unwrap();
+        assert!(second.cache_hit);
+        assert_eq!(second.answer, first.answer);
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 1);
+
+        // 不同租户或不相关的问题不命中
+        engine
+            .query(request("How do I request a refund?", "other"))
+            .await
+            .unwrap();
+        engine
+            .query(request("Where can I download invoices?", "acme"))
+            .await
+            .unwrap();
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 3);
+        assert_eq!(engine.stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_scope_includes_tenant_and_prompt_version() {
+        let (inner, engine) = cached(0.9);
+        let req = || request("退款流程", "acme");
+        with_tenant("t1", engine.query(req())).await.unwrap();
+        assert!(
+            with_tenant("t1", engine.query(req()))
+                .await
+                .unwrap()
+                .cache_hit
+        );
+        assert!(
+            !with_tenant("t2", engine.query(req()))
+                .await
+                .unwrap()
+                .cache_hit
+        );
+
+        let v2 = Arc::new(kb_llm::PromptTemplate {
+            version: 2,
+            ..Default::default()
+        });
+        let resp = with_tenant("t1", kb_llm::with_prompt(v2, engine.query(req())))
+            .await
+            .unwrap();
+        assert!(!resp.cache_hit);
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reindexing_cited_document_invalidates() {
+        let (inner, engine) = cached(0.9);
+        engine.query(request("退款流程", "acme")).await.unwrap();
+        engine
+            .add_document_text("other-doc", "text", None)
+            .await
+            .unwrap();
+        assert!(
+            engine
+                .query(request("退款流程", "acme"))
+                .await
+                .unwrap()
+                .cache_hit
+        );
+
+        engine.add_document_text("doc", "text", None).await.unwrap();
+        assert!(
+            !engine
+                .query(request("退款流程", "acme"))
+                .await
+                .unwrap()
+                .cache_hit
+        );
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_not_returned() {
+        let inner = Arc::new(CountingEngine::default());
+        let engine = CachedRagEngine::new(
+            inner.clone(),
+            Arc::new(HashingEmbedModel::default()),
+            AnswerCacheConfig {
+                ttl_secs: 0,
+                ..Default::default()
+            },
+        );
+        engine.query(request("退款流程", "acme")).await.unwrap();
+        assert!(
+            !engine
+                .query(request("退款流程", "acme"))
+                .await
+                .unwrap()
+                .cache_hit
+        );
+        assert_eq!(inner.queries.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,363 @@
+//! 可回答性判断：检索结果相关度不足时跳过生成，返回最接近的文档
+
+use async_trait::async_trait;
+use kb_core::{Citation, InsufficientEvidence, QueryRequest, QueryResponse, SuggestedDocument};
+use kb_error::Result;
+use kb_llm::ChatStream;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::engine::{
+    EngineStats, HealthStatus, RagEngine, RagEngineConfig, RagMeta, NO_RESULT_ANSWER,
+};
+
+/// 证据不足时的回答
+pub const INSUFFICIENT_EVIDENCE_ANSWER: &str =
+    "抱歉，知识库中没有与该问题足够相关的内容，无法给出可靠的回答。可以参考以下最接近的文档。";
+
+/// 可回答性判断配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnswerabilityConfig {
+    pub enabled: bool,
+    /// 未重排时的得分下限，为空时取 `RagEngineConfig::similarity_threshold`
+    pub min_similarity: Option<f32>,
+    /// 重排后的得分下限，为空时与未重排相同
+    pub min_rerank_score: Option<f32>,
+    /// 至少需要几条引用达到下限
+    pub min_supporting: usize,
+    /// 证据不足时列出的文档数
+    pub suggestions: usize,
+}
+
+impl Default for AnswerabilityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_similarity: None,
+            min_rerank_score: None,
+            min_supporting: 1,
+            suggestions: 3,
+        }
+    }
+}
+
+/// 相关度门控
+#[derive(Debug, Clone)]
+pub struct AnswerabilityGate {
+    config: AnswerabilityConfig,
+    min_similarity: f32,
+}
+
+impl AnswerabilityGate {
+    /// 未单独配置下限时使用引擎配置的 `similarity_threshold`
+    pub fn new(config: AnswerabilityConfig, engine_config: &RagEngineConfig) -> Self {
+        let min_similarity = config
+            .min_similarity
+            .unwrap_or(engine_config.similarity_threshold);
+        Self {
+            config,
+            min_similarity,
+        }
+    }
+
+    pub fn threshold(&self, reranked: bool) -> f32 {
+        match self.config.min_rerank_score {
+            Some(score) if reranked => score,
+            _ => self.min_similarity,
+        }
+    }
+
+    /// 判断引用能否支撑回答；不足时返回说明与最接近的文档
+    pub fn assess(&self, citations: &[Citation], reranked: bool) -> Option<InsufficientEvidence> {
+        if !self.config.enabled {
+            return None;
+        }
+        let threshold = self.threshold(reranked);
+        let supporting = citations.iter().filter(|c| c.score >= threshold).count();
+        if supporting >= self.config.min_supporting.max(1) {
+            return None;
+        }
+
+        let mut ranked: Vec<&Citation> = citations.iter().collect();
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut suggested_documents: Vec<SuggestedDocument> = Vec::new();
+        for c in ranked {
+            if suggested_documents.len() >= self.config.suggestions {
+                break;
+            }
+            if suggested_documents
+                .iter()
+                .any(|d| d.document_id == c.document_id)
+            {
+                continue;
+            }
+            suggested_documents.push(SuggestedDocument {
+                document_id: c.document_id.clone(),
+                page: c.page,
+                score: c.score,
+                snippet: c.snippet.clone(),
+            });
+        }
+        Some(InsufficientEvidence {
+            reason: if citations.is_empty() {
+                "no_results"
+            } else {
+                "low_relevance"
+            }
+            .to_string(),
+            best_score: citations.iter().map(|c| c.score).reduce(f32::max),
+            threshold,
+            suggested_documents,
+        })
+    }
+
+    /// 证据不足时的回答文本
+    pub fn answer(evidence: &InsufficientEvidence) -> &'static str {
+        if evidence.suggested_documents.is_empty() {
+            NO_RESULT_ANSWER
+        } else {
+            INSUFFICIENT_EVIDENCE_ANSWER
+        }
+    }
+}
+
+/// 可回答性门控包装引擎：检索结果相关度不足时不调用模型，直接返回证据不足的响应
+///
+/// 应位于重排之外，以便按重排后的得分判断。
+pub struct GatedRagEngine {
+    inner: Arc<dyn RagEngine>,
+    gate: AnswerabilityGate,
+    rerank_by_default: bool,
+}
+
+impl GatedRagEngine {
+    pub fn new(inner: Arc<dyn RagEngine>, gate: AnswerabilityGate) -> Self {
+        Self {
+            inner,
+            gate,
+            rerank_by_default: false,
+        }
+    }
+
+    /// 请求未指定 `rerank` 时是否视为已重排，应与重排引擎的默认值一致
+    pub fn with_rerank_default(mut self, enabled: bool) -> Self {
+        self.rerank_by_default = enabled;
+        self
+    }
+
+    /// 按请求是否重排选择下限，判断引用能否支撑回答
+    pub fn assess(
+        &self,
+        req: &QueryRequest,
+        citations: &[Citation],
+    ) -> Option<InsufficientEvidence> {
+        let evidence = self
+            .gate
+            .assess(citations, req.rerank.unwrap_or(self.rerank_by_default))?;
+        info!(
+            reason = %evidence.reason,
+            best_score = evidence.best_score.unwrap_or_default(),
+            threshold = evidence.threshold,
+            "证据不足，跳过回答生成"
+        );
+        Some(evidence)
+    }
+
+    pub(crate) fn insufficient_response(
+        req: &QueryRequest,
+        evidence: InsufficientEvidence,
+    ) -> QueryResponse {
+        QueryResponse {
+            answer: AnswerabilityGate::answer(&evidence).to_string(),
+            mode: req.mode.clone().unwrap_or_else(|| "rag".to_string()),
+            insufficient_evidence: Some(evidence),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl RagEngine for GatedRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        if !self.gate.config.enabled {
+            return self.inner.query(req).await;
+        }
+        let start_time = std::time::Instant::now();
+        let citations = self.inner.retrieve(&req).await?;
+        let mut response = match self.assess(&req, &citations) {
+            Some(evidence) => Self::insufficient_response(&req, evidence),
+            None => self.inner.generate(&req, citations).await?,
+        };
+        response.latency_ms = start_time.elapsed().as_millis() as i64;
+        Ok(response)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.inner.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        match self.assess(req, &citations) {
+            Some(evidence) => Ok(Self::insufficient_response(req, evidence)),
+            None => self.inner.generate(req, citations).await,
+        }
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        match self.assess(req, &citations) {
+            Some(evidence) => {
+                let answer = AnswerabilityGate::answer(&evidence).to_string();
+                Ok(Box::pin(futures::stream::once(async { Ok(answer) })))
+            }
+            None => self.inner.generate_stream(req, citations).await,
+        }
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn citation(doc: &str, chunk: usize, score: f32) -> Citation {
+        Citation {
+            document_id: doc.to_string(),
+            chunk_id: format!("{}#{}", doc, chunk),
+            page: Some(1),
+            score,
+            snippet: format!("{} snippet {}", doc, chunk),
+            header: None,
+            text: None,
+        }
+    }
+
+    /// 检索返回固定引用，生成时记录调用
+    struct FixedEngine {
+        citations: Vec<Citation>,
+    }
+
+    #[async_trait]
+    impl RagEngine for FixedEngine {
+        async fn query(&self, _req: QueryRequest) -> Result<QueryResponse> {
+            unreachable!("门控引擎应分别调用检索与生成")
+        }
+
+        async fn retrieve(&self, _req: &QueryRequest) -> Result<Vec<Citation>> {
+            Ok(self.citations.clone())
+        }
+
+        async fn generate(
+            &self,
+            _req: &QueryRequest,
+            citations: Vec<Citation>,
+        ) -> Result<QueryResponse> {
+            Ok(QueryResponse {
+                answer: "generated".to_string(),
+                citations,
+                ..Default::default()
+            })
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn engine(citations: Vec<Citation>, config: AnswerabilityConfig) -> GatedRagEngine {
+        let engine_config = RagEngineConfig {
+            similarity_threshold: 0.5,
+            ..Default::default()
+        };
+        GatedRagEngine::new(
+            Arc::new(FixedEngine { citations }),
+            AnswerabilityGate::new(config, &engine_config),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_weak_context_skips_generation() {
+        let engine = engine(
+            vec![
+                citation("a", 0, 0.31),
+                citation("b", 0, 0.42),
+                citation("b", 1, 0.40),
+                citation("c", 0, 0.12),
+            ],
+            AnswerabilityConfig {
+                suggestions: 2,
+                ..Default::default()
+            },
+        );
+        let resp = engine.query(QueryRequest::default()).await.unwrap();
+        assert_eq!(resp.answer, INSUFFICIENT_EVIDENCE_ANSWER);
+        assert!(resp.citations.is_empty());
+        let evidence = resp.insufficient_evidence.unwrap();
+        assert_eq!(evidence.reason, "low_relevance");
+        assert_eq!(evidence.best_score, Some(0.42));
+        assert_eq!(evidence.threshold, 0.5);
+        let docs: Vec<&str> = evidence
+            .suggested_documents
+            .iter()
+            .map(|d| d.document_id.as_str())
+            .collect();
+        assert_eq!(docs, vec!["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_rerank_threshold_applies_to_reranked_requests() {
+        let config = AnswerabilityConfig {
+            min_rerank_score: Some(0.3),
+            ..Default::default()
+        };
+        let gated = engine(vec![citation("a", 0, 0.35)], config).with_rerank_default(true);
+        let resp = gated.query(QueryRequest::default()).await.unwrap();
+        assert_eq!(resp.answer, "generated");
+
+        let req = QueryRequest {
+            rerank: Some(false),
+            ..Default::default()
+        };
+        let resp = gated.query(req).await.unwrap();
+        assert!(resp.insufficient_evidence.is_some());
+
+        let empty = engine(Vec::new(), AnswerabilityConfig::default());
+        let resp = empty.query(QueryRequest::default()).await.unwrap();
+        assert_eq!(resp.answer, NO_RESULT_ANSWER);
+        assert_eq!(resp.insufficient_evidence.unwrap().reason, "no_results");
+    }
+}
@@ -0,0 +1,257 @@
+//! 上下文组装：按优先级整条装入引用，直到 token 预算用尽
+
+use kb_core::Citation;
+use kb_llm::tokens::{estimate_tokens, truncate_to_tokens};
+use serde::{Deserialize, Serialize};
+
+/// 压缩后的引用末尾的省略标记
+const ELLIPSIS: &str = "…";
+
+/// 引用之间的分隔符
+const SEPARATOR: &str = "\n\n";
+
+/// 展示片段的字符上限
+const DISPLAY_SNIPPET_CHARS: usize = 240;
+
+/// 上下文组装配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// 对话模型上下文窗口（token），模型注册表中有记录时以注册表为准
+    pub context_window: usize,
+    /// 为回答预留的 token 数，模型注册表中有最大输出时以注册表为准
+    pub answer_reserve: usize,
+    /// 检索上下文的 token 上限
+    pub max_context_tokens: usize,
+    /// 是否压缩排名靠后的引用
+    pub compress_low_ranked: bool,
+    /// 压缩时保持完整的前 N 条引用
+    pub full_chunks: usize,
+    /// 压缩后单条引用的 token 上限
+    pub compressed_chunk_tokens: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            context_window: 8192,
+            answer_reserve: 1024,
+            max_context_tokens: 3000,
+            compress_low_ranked: false,
+            full_chunks: 3,
+            compressed_chunk_tokens: 128,
+        }
+    }
+}
+
+impl ContextConfig {
+    /// 检索上下文可用的 token 数：窗口扣除回答预留、system 提示与用户问题
+    pub fn budget(&self, system_prompt: &str, query: &str) -> usize {
+        let prompt = estimate_tokens(system_prompt) + estimate_tokens(query);
+        self.context_window
+            .saturating_sub(self.answer_reserve + prompt)
+            .min(self.max_context_tokens)
+    }
+
+    /// 按当前任务的提示模板（见 [`kb_llm::with_prompt`]）计算回答该问题时的上下文预算
+    pub fn query_budget(&self, query: &str) -> usize {
+        let prompt = kb_llm::current_prompt().render("", query);
+        self.budget(&prompt.system, &prompt.user)
+    }
+}
+
+/// 装入上下文的一条引用
+#[derive(Debug, Clone)]
+pub struct ContextPart {
+    /// 在输入引用中的下标，上下文中以 `[index + 1]` 标注
+    pub index: usize,
+    /// 实际发送给模型的片段
+    pub snippet: String,
+    pub compressed: bool,
+}
+
+/// 组装结果
+#[derive(Debug, Clone, Default)]
+pub struct AssembledContext {
+    pub text: String,
+    /// 按输入顺序排列的已装入引用
+    pub parts: Vec<ContextPart>,
+    /// 估算的 token 数
+    pub tokens: usize,
+}
+
+impl AssembledContext {
+    /// 因预算不足未装入的引用条数
+    pub fn omitted(&self, total: usize) -> usize {
+        total.saturating_sub(self.parts.len())
+    }
+}
+
+/// 展示用片段：超过上限时按字符截断并加省略号，完整文本另存于 `Citation::text`
+pub fn display_snippet(text: &str) -> String {
+    match text.char_indices().nth(DISPLAY_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// 单条引用在上下文中的格式，有上下文标题时置于片段之前
+pub fn format_citation(index: usize, citation: &Citation, snippet: &str) -> String {
+    let header = citation
+        .header
+        .as_deref()
+        .map(|h| format!("{}\n", h))
+        .unwrap_or_default();
+    format!(
+        "[{}] (doc={} page={:?} score={:.3})\n{}{}",
+        index + 1,
+        citation.document_id,
+        citation.page,
+        citation.score,
+        header,
+        snippet
+    )
+}
+
+/// 按得分从高到低整条装入引用，超出预算的引用跳过；启用压缩时排名靠后的引用只保留开头部分
+///
+/// 编号沿用引用在输入中的位置，未装入的引用留空号，保证 `[n]` 始终对应 `citations[n - 1]`。
+pub fn assemble(config: &ContextConfig, citations: &[Citation], budget: usize) -> AssembledContext {
+    let mut order: Vec<usize> = (0..citations.len()).collect();
+    order.sort_by(|&a, &b| citations[b].score.total_cmp(&citations[a].score));
+
+    let separator = estimate_tokens(SEPARATOR);
+    let mut used = 0;
+    let mut parts: Vec<ContextPart> = Vec::new();
+    for (rank, &i) in order.iter().enumerate() {
+        let citation = &citations[i];
+        let content = citation.content();
+        let mut snippet = content.to_string();
+        let mut compressed = false;
+        if config.compress_low_ranked && rank >= config.full_chunks {
+            let cut = truncate_to_tokens(content, config.compressed_chunk_tokens);
+            if cut.len() < content.len() {
+                snippet = format!("{}{}", cut.trim_end(), ELLIPSIS);
+                compressed = true;
+            }
+        }
+        let tokens = estimate_tokens(&format_citation(i, citation, &snippet)) + separator;
+        if used + tokens > budget {
+            continue;
+        }
+        used += tokens;
+        parts.push(ContextPart {
+            index: i,
+            snippet,
+            compressed,
+        });
+    }
+
+    // 排名第一的引用单条即超出预算时，截取其开头部分，避免上下文为空
+    if parts.is_empty() {
+        if let Some(&i) = order.first() {
+            let citation = &citations[i];
+            let header = estimate_tokens(&format_citation(i, citation, ELLIPSIS)) + separator;
+            let cut = truncate_to_tokens(citation.content(), budget.saturating_sub(header));
+            if !cut.is_empty() {
+                let snippet = format!("{}{}", cut.trim_end(), ELLIPSIS);
+                used = estimate_tokens(&format_citation(i, citation, &snippet)) + separator;
+                parts.push(ContextPart {
+                    index: i,
+                    snippet,
+                    compressed: true,
+                });
+            }
+        }
+    }
+
+    parts.sort_by_key(|part| part.index);
+    let text = parts
+        .iter()
+        .map(|p| format_citation(p.index, &citations[p.index], &p.snippet))
+        .collect::<Vec<_>>()
+        .join(SEPARATOR);
+    AssembledContext {
+        text,
+        parts,
+        tokens: used,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn citation(id: &str, score: f32, snippet: &str) -> Citation {
+        Citation {
+            document_id: id.to_string(),
+            chunk_id: format!("{}#0", id),
+            page: None,
+            score,
+            snippet: snippet.to_string(),
+            header: None,
+            text: None,
+        }
+    }
+
+    #[test]
+    fn test_packs_whole_citations_by_score() {
+        let citations = vec![
+            citation("low", 0.5, &"filler text ".repeat(40)),
+            citation("high", 0.9, "refunds are processed within 7 days"),
+            citation("mid", 0.7, "contact support to request a refund"),
+        ];
+        let config = ContextConfig::default();
+        let assembled = assemble(&config, &citations, 60);
+        let included: Vec<usize> = assembled.parts.iter().map(|p| p.index).collect();
+        assert_eq!(included, vec![1, 2]);
+        assert_eq!(assembled.omitted(citations.len()), 1);
+        assert!(assembled.tokens <= 60);
+        // 编号沿用输入位置
+        assert!(assembled.text.starts_with("[2] (doc=high"));
+        assert!(assembled.text.contains("[3] (doc=mid"));
+        assert!(!assembled.text.contains("filler"));
+    }
+
+    #[test]
+    fn test_truncates_on_char_boundary_when_nothing_fits() {
+        let citations = vec![citation("zh", 0.9, &"退款申请需在七天内提交。".repeat(50))];
+        let assembled = assemble(&ContextConfig::default(), &citations, 40);
+        assert_eq!(assembled.parts.len(), 1);
+        assert!(assembled.parts[0].compressed);
+        assert!(assembled.parts[0].snippet.ends_with(ELLIPSIS));
+        assert!(assembled.tokens <= 40);
+    }
+
+    #[test]
+    fn test_compresses_low_ranked_citations() {
+        let long = "word ".repeat(200);
+        let citations = vec![
+            citation("a", 0.9, &long),
+            citation("b", 0.8, &long),
+            citation("c", 0.7, &long),
+        ];
+        let config = ContextConfig {
+            compress_low_ranked: true,
+            full_chunks: 1,
+            compressed_chunk_tokens: 20,
+            ..Default::default()
+        };
+        let assembled = assemble(&config, &citations, 1000);
+        let compressed: Vec<bool> = assembled.parts.iter().map(|p| p.compressed).collect();
+        assert_eq!(compressed, vec![false, true, true]);
+        assert_eq!(assembled.parts[0].snippet, long);
+    }
+
+    #[test]
+    fn test_budget_reserves_prompt_and_answer() {
+        let config = ContextConfig {
+            context_window: 4096,
+            answer_reserve: 1024,
+            max_context_tokens: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(config.budget("abcd", "你好"), 4096 - 1024 - 1 - 2);
+        assert_eq!(ContextConfig::default().budget("", ""), 3000);
+    }
+}
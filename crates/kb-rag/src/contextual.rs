@@ -0,0 +1,211 @@
+//! 上下文标题：为分块生成文档标题、章节路径与可选的文档摘要，拼在嵌入文本与发送给模型的上下文之前
+
+use kb_llm::tokens::truncate_to_tokens;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::engine::RagMeta;
+
+/// 上下文标题配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextualHeaderConfig {
+    /// 启用后先按 Markdown 标题切分章节再分块，块的边界与编号（`文档ID#序号`）随之改变，
+    /// 切换该选项后应重建索引
+    pub enabled: bool,
+    /// 是否请对话模型为每个文档写一句摘要加入标题；同一文档分多次写入时只生成一次
+    pub summary: bool,
+    /// 生成摘要时文档正文的 token 上限
+    pub summary_input_tokens: usize,
+}
+
+impl Default for ContextualHeaderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            summary: false,
+            summary_input_tokens: 2000,
+        }
+    }
+}
+
+const SUMMARY_SYSTEM_PROMPT: &str = "Summarize what the document is about in one sentence, naming its subject so that any excerpt of it can be understood in context. Keep the language of the document. Output only the sentence.";
+
+/// 按 Markdown 标题切出的章节
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// 由外到内的标题
+    pub path: Vec<String>,
+    pub text: String,
+}
+
+/// 按 Markdown 标题（`#` 至 `######`）切分章节，标题行本身不计入正文；无标题的文本为一个章节
+pub fn split_sections(text: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut body = String::new();
+    let flush = |stack: &[(usize, String)], body: &mut String, sections: &mut Vec<Section>| {
+        if !body.trim().is_empty() {
+            sections.push(Section {
+                path: stack.iter().map(|(_, title)| title.clone()).collect(),
+                text: body.trim().to_string(),
+            });
+        }
+        body.clear();
+    };
+
+    for line in text.lines() {
+        match heading(line) {
+            Some((level, title)) => {
+                flush(&stack, &mut body, &mut sections);
+                while stack.last().is_some_and(|(l, _)| *l >= level) {
+                    stack.pop();
+                }
+                stack.push((level, title.to_string()));
+            }
+            None => {
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+    }
+    flush(&stack, &mut body, &mut sections);
+    sections
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let line = line.trim_start();
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let title = line[level..]
+        .strip_prefix(' ')?
+        .trim()
+        .trim_end_matches('#')
+        .trim();
+    (!title.is_empty()).then_some((level, title))
+}
+
+/// 文档标题：优先取 `custom_fields.title`，其次为来源，最后为文档 ID
+pub fn document_title(document_id: &str, meta: Option<&RagMeta>) -> String {
+    meta.and_then(|m| {
+        m.custom_fields
+            .as_ref()
+            .and_then(|f| f.get("title"))
+            .and_then(|t| t.as_str())
+            .or(m.source.as_deref())
+    })
+    .filter(|t| !t.trim().is_empty())
+    .unwrap_or(document_id)
+    .trim()
+    .to_string()
+}
+
+/// 组装标题文本
+pub fn build_header(title: &str, path: &[String], summary: Option<&str>) -> String {
+    let mut lines = vec![format!("Document: {}", title)];
+    if !path.is_empty() {
+        lines.push(format!("Section: {}", path.join(" > ")));
+    }
+    if let Some(summary) = summary {
+        lines.push(format!("Summary: {}", summary));
+    }
+    lines.join("\n")
+}
+
+/// 缓存的摘要条数上限，超出时淘汰最早写入的
+const SUMMARY_CACHE_CAPACITY: usize = 1024;
+
+/// 按文档 ID 缓存的摘要：分页或分段写入的文档以首次写入的文本生成摘要，后续写入复用
+#[derive(Default)]
+pub struct SummaryCache {
+    entries: Mutex<(HashMap<String, String>, VecDeque<String>)>,
+}
+
+impl SummaryCache {
+    pub fn get(&self, document_id: &str) -> Option<String> {
+        self.entries.lock().unwrap().0.get(document_id).cloned()
+    }
+
+    pub fn insert(&self, document_id: &str, summary: String) {
+        let mut guard = self.entries.lock().unwrap();
+        let (map, order) = &mut *guard;
+        if map.insert(document_id.to_string(), summary).is_none() {
+            order.push_back(document_id.to_string());
+        }
+        while order.len() > SUMMARY_CACHE_CAPACITY {
+            if let Some(oldest) = order.pop_front() {
+                map.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// 请对话模型为文档写一句摘要；失败时返回 `None`，标题中不含摘要
+pub async fn summarize(
+    chat: &dyn ChatModel,
+    text: &str,
+    max_input_tokens: usize,
+) -> Option<String> {
+    let req = ChatRequest::new(vec![
+        ChatMessage::system(SUMMARY_SYSTEM_PROMPT),
+        ChatMessage::user(truncate_to_tokens(text.trim(), max_input_tokens)),
+    ])
+    .with_temperature(0.0);
+    match chat.complete(&req).await {
+        Ok(resp) => {
+            let summary = resp.content.trim().lines().next().unwrap_or("").trim();
+            (!summary.is_empty()).then(|| summary.to_string())
+        }
+        Err(e) => {
+            warn!(error = %e, "文档摘要生成失败，标题中不含摘要");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sections_tracks_heading_path() {
+        let text = "intro line\n# Refund Policy\n## Limits\nthe limit is 30 days\n## Exceptions\nsale items\n# Shipping\nfive days";
+        let sections = split_sections(text);
+        let paths: Vec<String> = sections.iter().map(|s| s.path.join(" > ")).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "",
+                "Refund Policy > Limits",
+                "Refund Policy > Exceptions",
+                "Shipping"
+            ]
+        );
+        assert_eq!(sections[1].text, "the limit is 30 days");
+        assert_eq!(heading("#hashtag"), None);
+    }
+
+    #[test]
+    fn test_header_uses_meta_title() {
+        let meta = RagMeta {
+            source: Some("policies.pdf".to_string()),
+            custom_fields: Some(serde_json::json!({ "title": "Refund Policy" })),
+            ..Default::default()
+        };
+        assert_eq!(document_title("doc-1", Some(&meta)), "Refund Policy");
+        assert_eq!(document_title("doc-1", None), "doc-1");
+        assert_eq!(
+            build_header(
+                "Refund Policy",
+                &["Limits".to_string()],
+                Some("Rules for refunds.")
+            ),
+            "Document: Refund Policy\nSection: Limits\nSummary: Rules for refunds."
+        );
+    }
+}
@@ -1,20 +1,71 @@
+use crate::context::{self, AssembledContext, ContextConfig};
+use crate::contextual::{self, ContextualHeaderConfig, SummaryCache};
+use crate::parent::ParentChunkConfig;
 use async_trait::async_trait;
 use chrono::Utc;
 use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::Result as KbResult;
-use kb_llm::{ChatModel, EmbedModel};
+use kb_llm::tokens::bytes_for_tokens;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream, EmbedModel, RenderedPrompt};
 use rig::Embed;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
 use tracing::instrument;
 
+/// 未检索到任何引用时的回答
+pub(crate) const NO_RESULT_ANSWER: &str = "抱歉，我在知识库中没有找到相关的信息来回答您的问题。";
+
+pub(crate) fn map_chat_error(e: kb_error::KbError) -> kb_error::KbError {
+    kb_error::KbError::LlmService {
+        provider: "chat".to_string(),
+        message: e.to_string(),
+        retry_after: e.retry_after(),
+    }
+}
+
 /// RAG 引擎的统一抽象接口
 #[async_trait]
 pub trait RagEngine: Send + Sync {
     /// 执行查询
     async fn query(&self, req: QueryRequest) -> KbResult<QueryResponse>;
 
+    /// 仅执行检索，返回候选引用而不调用 LLM
+    ///
+    /// 默认实现退化为完整查询；具备独立检索能力的引擎应覆盖此方法以避免多余的生成开销。
+    async fn retrieve(&self, req: &QueryRequest) -> KbResult<Vec<Citation>> {
+        Ok(self.query(req.clone()).await?.citations)
+    }
+
+    /// 基于外部给定的引用生成回答（例如经过重排或融合后的结果）
+    ///
+    /// 默认实现返回错误：重新查询得到的回答可能引用给定引用之外的分块，
+    /// 支持重排、融合等流程的引擎必须覆盖此方法。
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        _citations: Vec<Citation>,
+    ) -> KbResult<QueryResponse> {
+        Err(kb_error::KbError::InvalidRequest {
+            reason: format!(
+                "引擎不支持基于给定引用生成回答 (mode={})",
+                req.mode.as_deref().unwrap_or("default")
+            ),
+        })
+    }
+
+    /// 基于给定引用流式生成回答，返回增量文本流
+    ///
+    /// 默认实现等待完整回答后一次性返回；接入流式 LLM 的引擎应覆盖此方法。
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> KbResult<ChatStream> {
+        let answer = self.generate(req, citations).await?.answer;
+        Ok(Box::pin(futures::stream::once(async move { Ok(answer) })))
+    }
+
     /// 添加文档文本
     async fn add_document_text(
         &self,
@@ -107,8 +158,21 @@ pub struct RagDocumentChunk {
     pub source: Option<String>,
     pub created_at: i64,
     pub custom_fields: Option<Value>,
-    #[embed]
+    /// 所属父块 ID，仅按父子结构索引的子块有值
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// 子块在父块中的序号
+    #[serde(default)]
+    pub position: Option<usize>,
+    /// 上下文标题（文档标题、章节路径、可选的文档摘要），启用上下文标题时生成
+    #[serde(default)]
+    pub header: Option<String>,
+    /// 原文，用于展示
     pub text: String,
+    /// 嵌入与发送给模型的文本：有上下文标题时为标题加原文，否则与原文相同
+    #[embed]
+    #[serde(default)]
+    pub embed_text: String,
 }
 
 impl RagDocumentChunk {
@@ -119,6 +183,7 @@ impl RagDocumentChunk {
         page: Option<i32>,
         meta: Option<RagMeta>,
     ) -> Self {
+        let text = text.into();
         let mut meta = meta.unwrap_or_default();
         // 确保 chunk 拥有明确的创建时间
         let created_at = meta.resolved_created_at();
@@ -132,10 +197,38 @@ impl RagDocumentChunk {
             source: meta.source.take(),
             created_at,
             custom_fields: meta.custom_fields.take(),
-            text: text.into(),
+            parent_id: None,
+            position: None,
+            header: None,
+            embed_text: text.clone(),
+            text,
         }
     }
 
+    /// 设置上下文标题，嵌入文本随之改为标题加原文
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        let header = header.into();
+        self.embed_text = format!("{}\n\n{}", header, self.text);
+        self.header = Some(header);
+        self
+    }
+
+    /// 嵌入文本；旧数据未存储时退回原文
+    pub fn embedding_text(&self) -> &str {
+        if self.embed_text.is_empty() {
+            &self.text
+        } else {
+            &self.embed_text
+        }
+    }
+
+    /// 标记为父块下的子块
+    pub fn with_parent(mut self, parent_id: impl Into<String>, position: usize) -> Self {
+        self.parent_id = Some(parent_id.into());
+        self.position = Some(position);
+        self
+    }
+
     pub fn as_meta(&self) -> RagMeta {
         RagMeta {
             tenant_id: self.tenant_id.clone(),
@@ -171,30 +264,119 @@ pub struct BaseRagEngine {
     pub chat_model: Arc<dyn ChatModel>,
     pub embed_model: Arc<dyn EmbedModel>,
     pub config: RagEngineConfig,
+    summaries: SummaryCache,
 }
 
 /// RAG 引擎配置
 #[derive(Debug, Clone)]
 pub struct RagEngineConfig {
-    pub max_context_length: usize,
+    /// 上下文组装的 token 预算
+    pub context: ContextConfig,
     pub default_top_k: u16,
     pub similarity_threshold: f32,
     pub enable_reranking: bool,
     pub chunk_size: usize,
     pub chunk_overlap: usize,
+    /// 向量维度，为空时创建集合前探测向量模型
+    pub embedding_dimension: Option<usize>,
+    /// 父子分块（small-to-big）
+    pub parent: ParentChunkConfig,
+    /// 分块的上下文标题
+    pub headers: ContextualHeaderConfig,
 }
 
 impl Default for RagEngineConfig {
     fn default() -> Self {
         Self {
-            max_context_length: 8000,
+            context: ContextConfig::default(),
             default_top_k: 5,
             similarity_threshold: 0.7,
             enable_reranking: false,
             chunk_size: 1000,
             chunk_overlap: 200,
+            embedding_dimension: None,
+            parent: ParentChunkConfig::default(),
+            headers: ContextualHeaderConfig::default(),
+        }
+    }
+}
+
+impl RagEngineConfig {
+    /// 按模型注册表中的元数据收紧配置：向量维度、分块不超过向量模型单条输入上限、
+    /// 上下文按对话模型的窗口与最大输出预留
+    pub fn with_models(
+        mut self,
+        chat: Option<&kb_llm::ModelInfo>,
+        embed: Option<&kb_llm::ModelInfo>,
+    ) -> Self {
+        if let Some(embed) = embed {
+            self.embedding_dimension = embed.embedding_dimension.or(self.embedding_dimension);
+            if let Some(max_input) = embed.max_input_tokens {
+                self.chunk_size = self.chunk_size.min(bytes_for_tokens(max_input));
+                self.chunk_overlap = self.chunk_overlap.min(self.chunk_size / 2);
+            }
+        }
+        if let Some(window) = chat.and_then(|c| c.context_window) {
+            self.context.context_window = window;
+            let reserve = chat
+                .and_then(|c| c.max_output_tokens)
+                .unwrap_or(self.context.answer_reserve);
+            self.context.answer_reserve = reserve.min(window / 2);
+        }
+        self
+    }
+}
+
+/// 按词切分文本，`size` 与 `overlap` 以字符计（估算每个词约5个字符）
+fn split_words(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    if words.is_empty() {
+        return chunks;
+    }
+
+    let mut current_chunk = String::new();
+    let mut word_count = 0;
+    let target_words = size / 5;
+    let overlap_words = overlap / 5;
+
+    for word in words {
+        if word_count >= target_words && !current_chunk.is_empty() {
+            chunks.push(current_chunk.trim().to_string());
+
+            // 保留重叠部分
+            if overlap_words > 0 && word_count > overlap_words {
+                let chunk_words: Vec<&str> = current_chunk.split_whitespace().collect();
+                let overlap_start = chunk_words.len().saturating_sub(overlap_words);
+                current_chunk = chunk_words[overlap_start..].join(" ");
+                current_chunk.push(' ');
+                word_count = overlap_words;
+            } else {
+                current_chunk.clear();
+                word_count = 0;
+            }
         }
+
+        if !current_chunk.is_empty() {
+            current_chunk.push(' ');
+        }
+        current_chunk.push_str(word);
+        word_count += 1;
     }
+
+    if !current_chunk.trim().is_empty() {
+        chunks.push(current_chunk.trim().to_string());
+    }
+
+    chunks
+}
+
+/// 32 位 FNV-1a 哈希
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
 }
 
 impl BaseRagEngine {
@@ -207,77 +389,139 @@ impl BaseRagEngine {
             chat_model,
             embed_model,
             config,
+            summaries: SummaryCache::default(),
         }
     }
 
     /// 通用的文本分块逻辑
     pub fn chunk_text(&self, text: &str) -> Vec<String> {
-        let mut chunks = Vec::new();
-        let words: Vec<&str> = text.split_whitespace().collect();
+        split_words(text, self.config.chunk_size, self.config.chunk_overlap)
+    }
 
-        if words.is_empty() {
-            return chunks;
-        }
+    /// 将文本分块并结合元数据生成统一的 `RagDocumentChunk` 列表
+    pub fn chunk_document(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Vec<RagDocumentChunk> {
+        self.chunk_document_with_summary(document_id, text, page, meta, None)
+    }
 
-        let mut current_chunk = String::new();
-        let mut word_count = 0;
-        let target_words = self.config.chunk_size / 5; // 估算每个词约5个字符
-        let overlap_words = self.config.chunk_overlap / 5;
-
-        for word in words {
-            if word_count >= target_words && !current_chunk.is_empty() {
-                chunks.push(current_chunk.trim().to_string());
-
-                // 保留重叠部分
-                if overlap_words > 0 && word_count > overlap_words {
-                    let chunk_words: Vec<&str> = current_chunk.split_whitespace().collect();
-                    let overlap_start = chunk_words.len().saturating_sub(overlap_words);
-                    current_chunk = chunk_words[overlap_start..].join(" ");
-                    current_chunk.push(' ');
-                    word_count = overlap_words;
-                } else {
-                    current_chunk.clear();
-                    word_count = 0;
+    /// 写入前分块；启用文档摘要时先请对话模型为文档写一句摘要加入各块的上下文标题
+    ///
+    /// 同一文档分页或分段写入时，摘要由首次写入的文本生成并按文档 ID 复用。
+    pub async fn prepare_document(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Vec<RagDocumentChunk> {
+        let headers = &self.config.headers;
+        let summary = if headers.enabled && headers.summary {
+            match self.summaries.get(document_id) {
+                Some(summary) => Some(summary),
+                None => {
+                    let summary = contextual::summarize(
+                        self.chat_model.as_ref(),
+                        text,
+                        headers.summary_input_tokens,
+                    )
+                    .await;
+                    if let Some(summary) = &summary {
+                        self.summaries.insert(document_id, summary.clone());
+                    }
+                    summary
                 }
             }
+        } else {
+            None
+        };
+        self.chunk_document_with_summary(document_id, text, page, meta, summary.as_deref())
+    }
 
-            if !current_chunk.is_empty() {
-                current_chunk.push(' ');
-            }
-            current_chunk.push_str(word);
-            word_count += 1;
+    /// 启用上下文标题时按章节分块，各块附带文档标题、章节路径与摘要
+    fn chunk_document_with_summary(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+        summary: Option<&str>,
+    ) -> Vec<RagDocumentChunk> {
+        let mut next_index = 0;
+        if !self.config.headers.enabled {
+            return self.chunk_section(document_id, text, page, &meta, &mut next_index);
         }
-
-        if !current_chunk.trim().is_empty() {
-            chunks.push(current_chunk.trim().to_string());
+        let title = contextual::document_title(document_id, meta.as_ref());
+        let mut chunks = Vec::new();
+        for section in contextual::split_sections(text) {
+            let header = contextual::build_header(&title, &section.path, summary);
+            chunks.extend(
+                self.chunk_section(document_id, &section.text, page, &meta, &mut next_index)
+                    .into_iter()
+                    .map(|chunk| chunk.with_header(header.clone())),
+            );
         }
-
         chunks
     }
 
-    /// 将文本分块并结合元数据生成统一的 `RagDocumentChunk` 列表
-    pub fn chunk_document(
+    /// 分块，`next_index` 为下一个块（父子分块时为父块）的序号
+    fn chunk_section(
         &self,
         document_id: &str,
         text: &str,
         page: Option<i32>,
-        meta: Option<RagMeta>,
+        meta: &Option<RagMeta>,
+        next_index: &mut usize,
     ) -> Vec<RagDocumentChunk> {
-        let chunks = self.chunk_text(text);
-        chunks
-            .into_iter()
-            .enumerate()
-            .map(|(idx, chunk_text)| {
-                let chunk_id = format!("{}#{}", document_id, idx);
-                RagDocumentChunk::from_text(
+        let mut chunks = Vec::new();
+        if !self.config.parent.enabled {
+            for chunk_text in self.chunk_text(text) {
+                let chunk_id = format!("{}#{}", document_id, next_index);
+                *next_index += 1;
+                chunks.push(RagDocumentChunk::from_text(
                     document_id.to_string(),
                     chunk_id,
                     chunk_text,
                     page,
                     meta.clone(),
-                )
-            })
-            .collect()
+                ));
+            }
+            return chunks;
+        }
+
+        // 父子分块：父块之间不重叠，子块按 `child_chunk_size` 切分并记录父块与序号
+        for parent_text in split_words(text, self.config.chunk_size, 0) {
+            // 同一文档可能分多次写入，序号之外附加内容哈希以免父块 ID 重复
+            let parent_id = format!("{}#{}-{:08x}", document_id, next_index, fnv1a(&parent_text));
+            *next_index += 1;
+            for (position, child_text) in
+                split_words(&parent_text, self.config.parent.child_chunk_size, 0)
+                    .into_iter()
+                    .enumerate()
+            {
+                chunks.push(
+                    RagDocumentChunk::from_text(
+                        document_id.to_string(),
+                        format!("{}.{}", parent_id, position),
+                        child_text,
+                        page,
+                        meta.clone(),
+                    )
+                    .with_parent(parent_id.clone(), position),
+                );
+            }
+        }
+        chunks
+    }
+
+    /// 请求是否需要把命中的子块扩展为父块窗口
+    pub fn expand_parents(&self, req: &QueryRequest) -> bool {
+        req.parent_window
+            .unwrap_or(self.config.parent.expand_by_default)
     }
 
     /// 通用的上下文格式化逻辑
@@ -285,46 +529,115 @@ impl BaseRagEngine {
         citations
             .iter()
             .enumerate()
-            .map(|(i, citation)| {
-                format!(
-                    "[{}] (doc={} page={:?} score={:.3})\n{}",
-                    i + 1,
-                    citation.document_id,
-                    citation.page,
-                    citation.score,
-                    citation.snippet
-                )
-            })
+            .map(|(i, citation)| context::format_citation(i, citation, citation.content()))
             .collect::<Vec<_>>()
             .join("\n\n")
     }
 
+    /// 检索上下文的 token 预算，扣除当前模板的 system 提示、用户问题与回答预留
+    pub fn context_budget(&self, query: &str) -> usize {
+        self.config.context.query_budget(query)
+    }
+
+    /// 按 token 预算组装上下文，预算扣除当前模板的 system 提示、用户问题与回答预留
+    pub fn assemble_context(&self, query: &str, citations: &[Citation]) -> AssembledContext {
+        let budget = self.context_budget(query);
+        let assembled = context::assemble(&self.config.context, citations, budget);
+        let compressed = assembled.parts.iter().filter(|p| p.compressed).count();
+        if compressed > 0 || assembled.parts.len() < citations.len() {
+            tracing::debug!(
+                budget,
+                tokens = assembled.tokens,
+                included = assembled.parts.len(),
+                omitted = assembled.omitted(citations.len()),
+                compressed,
+                "上下文超出预算，已按得分取舍引用"
+            );
+        }
+        assembled
+    }
+
+    /// 基于引用生成完整的查询响应，供各引擎的 `generate` 复用
+    pub async fn answer_with_citations(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+        default_mode: &str,
+    ) -> KbResult<QueryResponse> {
+        let start_time = std::time::Instant::now();
+        let mode = req.mode.clone().unwrap_or_else(|| default_mode.to_string());
+
+        if citations.is_empty() {
+            return Ok(QueryResponse {
+                answer: NO_RESULT_ANSWER.to_string(),
+                mode,
+                latency_ms: start_time.elapsed().as_millis() as i64,
+                ..Default::default()
+            });
+        }
+
+        let assembled = self.assemble_context(&req.query, &citations);
+        let answer = self.generate_answer(&assembled.text, &req.query).await?;
+        let context_chunk_ids = assembled
+            .parts
+            .iter()
+            .map(|p| citations[p.index].chunk_id.clone())
+            .collect();
+        let contexts = assembled.parts.into_iter().map(|p| p.snippet).collect();
+
+        Ok(QueryResponse {
+            answer,
+            citations,
+            contexts,
+            context_chunk_ids,
+            mode,
+            latency_ms: start_time.elapsed().as_millis() as i64,
+            ..Default::default()
+        })
+    }
+
+    /// 基于引用流式生成回答，供各引擎的 `generate_stream` 复用
+    pub async fn stream_with_citations(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> KbResult<ChatStream> {
+        if citations.is_empty() {
+            return Ok(Box::pin(futures::stream::once(async {
+                Ok(NO_RESULT_ANSWER.to_string())
+            })));
+        }
+        let assembled = self.assemble_context(&req.query, &citations);
+        self.generate_answer_stream(&assembled.text, &req.query)
+            .await
+    }
+
+    /// 以当前任务的提示模板（见 [`kb_llm::with_prompt`]）构造回答请求
+    fn answer_request(context: &str, query: &str) -> ChatRequest {
+        let RenderedPrompt { system, user } = kb_llm::current_prompt().render(context, query);
+        ChatRequest::new(vec![ChatMessage::system(system), ChatMessage::user(user)])
+            .with_temperature(0.2)
+    }
+
     /// 通用的 LLM 查询逻辑
     #[instrument(skip(self, context, query))]
     pub async fn generate_answer(&self, context: &str, query: &str) -> KbResult<String> {
-        let system = "You are a helpful assistant. Answer the user's question based on the provided context. If the context doesn't contain enough information to answer the question, say so clearly. Always cite your sources using [1], [2], etc. when referencing information from the context.";
-
-        // 检查上下文长度
-        let context_length = context.len();
-        let truncated_context = if context_length > self.config.max_context_length {
-            tracing::warn!(
-                "Context length {} exceeds maximum {}, truncating",
-                context_length,
-                self.config.max_context_length
-            );
-            &context[..self.config.max_context_length]
-        } else {
-            context
-        };
+        let req = Self::answer_request(context, query);
+        self.chat_model
+            .complete(&req)
+            .await
+            .map(|resp| resp.content)
+            .map_err(map_chat_error)
+    }
 
+    /// 通用的 LLM 流式查询逻辑
+    #[instrument(skip(self, context, query))]
+    pub async fn generate_answer_stream(&self, context: &str, query: &str) -> KbResult<ChatStream> {
+        let req = Self::answer_request(context, query);
         self.chat_model
-            .chat(system, truncated_context, query)
+            .complete_stream(&req)
             .await
-            .map_err(|e| kb_error::KbError::LlmService {
-                provider: "chat".to_string(),
-                message: e.to_string(),
-                retry_after: e.retry_after(),
-            })
+            .map_err(map_chat_error)
     }
 
     /// 计算余弦相似度
@@ -364,6 +677,7 @@ impl RagEngine for NoopRagEngine {
             contexts: vec![],
             mode: req.mode.unwrap_or_else(|| "noop".to_string()),
             latency_ms: 0,
+            ..Default::default()
         })
     }
 
@@ -377,3 +691,97 @@ impl RagEngine for NoopRagEngine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::{ChatResponse, HashingEmbedModel, ModelInfo, PromptTemplate};
+    use std::sync::Mutex;
+
+    /// 记录收到的请求
+    #[derive(Default)]
+    struct RecordingChat(Mutex<Vec<ChatRequest>>);
+
+    #[async_trait]
+    impl ChatModel for RecordingChat {
+        async fn complete(&self, req: &ChatRequest) -> KbResult<ChatResponse> {
+            self.0.lock().unwrap().push(req.clone());
+            Ok(ChatResponse::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_answer_uses_current_prompt() {
+        let chat = Arc::new(RecordingChat::default());
+        let base = BaseRagEngine::new(
+            chat.clone(),
+            Arc::new(HashingEmbedModel::default()),
+            RagEngineConfig::default(),
+        );
+        base.generate_answer("[1] ctx", "q").await.unwrap();
+        let template = Arc::new(PromptTemplate {
+            system: "Reply in {language}.".to_string(),
+            language: "Chinese".to_string(),
+            ..Default::default()
+        });
+        kb_llm::with_prompt(template, base.generate_answer("[1] ctx", "q"))
+            .await
+            .unwrap();
+
+        let requests = chat.0.lock().unwrap();
+        assert!(requests[0].messages[0].content.contains("[1], [2]"));
+        assert_eq!(requests[1].messages[0].content, "Reply in Chinese.");
+        assert_eq!(requests[1].messages[1].content, "q\n\nContext:\n[1] ctx");
+    }
+
+    /// 以固定摘要回答并计数
+    #[derive(Default)]
+    struct SummaryChat(Mutex<usize>);
+
+    #[async_trait]
+    impl ChatModel for SummaryChat {
+        async fn complete(&self, _req: &ChatRequest) -> KbResult<ChatResponse> {
+            *self.0.lock().unwrap() += 1;
+            Ok(ChatResponse {
+                content: "About refunds.".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summary_is_generated_once_per_document() {
+        let chat = Arc::new(SummaryChat::default());
+        let mut config = RagEngineConfig::default();
+        config.headers.enabled = true;
+        config.headers.summary = true;
+        let base = BaseRagEngine::new(chat.clone(), Arc::new(HashingEmbedModel::default()), config);
+        for page in 1..=3 {
+            let chunks = base
+                .prepare_document("doc", "refund terms", Some(page), None)
+                .await;
+            let header = chunks[0].header.as_deref().unwrap();
+            assert!(header.ends_with("Summary: About refunds."));
+        }
+        base.prepare_document("other", "shipping", None, None).await;
+        assert_eq!(*chat.0.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_config_respects_model_limits() {
+        let chat = ModelInfo::chat(2048, 512);
+        let embed = ModelInfo::embedding(768, 256);
+        let config = RagEngineConfig::default().with_models(Some(&chat), Some(&embed));
+        assert_eq!(config.embedding_dimension, Some(768));
+        assert_eq!(config.chunk_size, 768);
+        assert!(config.chunk_overlap <= config.chunk_size / 2);
+        assert_eq!(config.context.context_window, 2048);
+        assert_eq!(config.context.answer_reserve, 512);
+        assert_eq!(config.context.budget("", ""), 1536);
+
+        // 未知模型保持默认值
+        let config = RagEngineConfig::default().with_models(None, None);
+        assert_eq!(config.chunk_size, RagEngineConfig::default().chunk_size);
+        assert_eq!(config.embedding_dimension, None);
+    }
+}
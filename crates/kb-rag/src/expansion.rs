@@ -0,0 +1,462 @@
+//! 检索扩展：多查询改写后以 RRF 融合，或以假设性回答（HyDE）检索
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use kb_core::{Citation, QueryExpansion, QueryRequest, QueryResponse};
+use kb_error::Result;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{debug, instrument, warn};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagMeta};
+use crate::hybrid::{fuse_results, EngineResult, FusionStrategy, HybridConfig};
+
+/// 检索扩展配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExpansionConfig {
+    /// 请求未指定 `expansion` 时使用的方式
+    pub default: QueryExpansion,
+    /// 多查询模式下生成的改写问题数（不含原问题）
+    pub paraphrases: usize,
+    /// RRF 常数 k
+    pub rrf_k: f32,
+    /// 假设性回答的 token 上限
+    pub hyde_max_tokens: u32,
+}
+
+impl Default for ExpansionConfig {
+    fn default() -> Self {
+        Self {
+            default: QueryExpansion::None,
+            paraphrases: 3,
+            rrf_k: 60.0,
+            hyde_max_tokens: 256,
+        }
+    }
+}
+
+const MULTI_QUERY_SYSTEM_PROMPT: &str = "You help a search engine find documents in a knowledge base. Users often ask short or vague questions. Rewrite the question into {n} different search queries covering its likely intents: expand abbreviations and add the specific terms and synonyms that relevant documents would contain. Keep the language of the question.";
+
+const HYDE_SYSTEM_PROMPT: &str = "Write a short passage that answers the question as it would appear in a document from the knowledge base, using the terminology such a document would use. Keep the language of the question. Output only the passage.";
+
+/// 多查询改写的结构化输出
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Paraphrases {
+    queries: Vec<String>,
+}
+
+/// 检索扩展包装引擎：按请求的 `expansion` 扩大召回，生成回答时仍使用原问题
+///
+/// 应位于重排之内，使重排针对原问题对融合后的候选排序。
+pub struct ExpandedRagEngine {
+    inner: Arc<dyn RagEngine>,
+    chat: Arc<dyn ChatModel>,
+    config: ExpansionConfig,
+}
+
+impl ExpandedRagEngine {
+    pub fn new(
+        inner: Arc<dyn RagEngine>,
+        chat: Arc<dyn ChatModel>,
+        config: ExpansionConfig,
+    ) -> Self {
+        Self {
+            inner,
+            chat,
+            config,
+        }
+    }
+
+    fn expansion(&self, req: &QueryRequest) -> QueryExpansion {
+        req.expansion.unwrap_or(self.config.default)
+    }
+
+    /// 生成改写问题（已去掉与原问题重复的项），失败时返回空列表
+    pub async fn paraphrase(&self, query: &str) -> Vec<String> {
+        let n = self.config.paraphrases;
+        if n == 0 {
+            return Vec::new();
+        }
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(MULTI_QUERY_SYSTEM_PROMPT.replace("{n}", &n.to_string())),
+            ChatMessage::user(query),
+        ])
+        .with_temperature(0.3);
+        let out = match kb_llm::chat_json::<Paraphrases>(self.chat.as_ref(), req).await {
+            Ok(out) => out,
+            Err(e) => {
+                warn!(error = %e, "多查询改写失败，仅以原问题检索");
+                return Vec::new();
+            }
+        };
+        let mut seen = HashSet::from([query.trim().to_lowercase()]);
+        out.queries
+            .into_iter()
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty() && seen.insert(q.to_lowercase()))
+            .take(n)
+            .collect()
+    }
+
+    /// 生成假设性回答，失败或输出为空时返回 `None`
+    pub async fn hypothetical_answer(&self, query: &str) -> Option<String> {
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(HYDE_SYSTEM_PROMPT),
+            ChatMessage::user(query),
+        ])
+        .with_temperature(0.2)
+        .with_max_tokens(self.config.hyde_max_tokens);
+        match self.chat.complete(&req).await {
+            Ok(resp) => Some(resp.content.trim().to_string()).filter(|s| !s.is_empty()),
+            Err(e) => {
+                warn!(error = %e, "假设性回答生成失败，以原问题检索");
+                None
+            }
+        }
+    }
+
+    /// 原问题与改写问题分别检索后以 RRF 融合；改写问题检索失败时跳过
+    ///
+    /// 结果按 RRF 排序，但得分取各路检索中该分块的最高原始得分：RRF 得分量级只与排名有关，
+    /// 外层按相似度阈值判断的可回答性门控与重排都需要原始得分。
+    #[instrument(skip(self, req))]
+    async fn multi_query_retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        let mut queries = vec![req.query.clone()];
+        queries.extend(self.paraphrase(&req.query).await);
+        debug!(queries = ?queries, "多查询检索");
+
+        let results = join_all(queries.iter().map(|q| {
+            let mut sub = req.clone();
+            sub.query = q.clone();
+            async move { self.inner.retrieve(&sub).await }
+        }))
+        .await;
+
+        let mut ranked = Vec::new();
+        let mut longest = 0;
+        for (i, result) in results.into_iter().enumerate() {
+            let citations = match result {
+                Ok(citations) => citations,
+                Err(e) if i == 0 => return Err(e),
+                Err(e) => {
+                    warn!(error = %e, query = %queries[i], "改写问题检索失败，已跳过");
+                    continue;
+                }
+            };
+            longest = longest.max(citations.len());
+            for (rank, citation) in citations.into_iter().enumerate() {
+                ranked.push(EngineResult {
+                    citation,
+                    engine_type: format!("query#{}", i),
+                    original_rank: rank,
+                    normalized_score: 0.0,
+                });
+            }
+        }
+
+        let fusion = HybridConfig {
+            fusion_strategy: FusionStrategy::RRF {
+                k: self.config.rrf_k,
+            },
+            min_score_threshold: 0.0,
+            ..Default::default()
+        };
+        let key = |c: &Citation| format!("{}#{}", c.document_id, c.chunk_id);
+        let mut best: HashMap<String, f32> = HashMap::new();
+        for r in &ranked {
+            let score = best.entry(key(&r.citation)).or_insert(f32::MIN);
+            *score = score.max(r.citation.score);
+        }
+        let mut fused = fuse_results(&fusion, &ranked);
+        fused.truncate(req.top_k.map(usize::from).unwrap_or(longest));
+        for citation in &mut fused {
+            citation.score = best[&key(citation)];
+        }
+        Ok(fused)
+    }
+
+    /// 以假设性回答代替原问题检索
+    #[instrument(skip(self, req))]
+    async fn hyde_retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        match self.hypothetical_answer(&req.query).await {
+            Some(passage) => {
+                debug!(passage = %passage, "HyDE 检索");
+                let mut sub = req.clone();
+                sub.query = passage;
+                self.inner.retrieve(&sub).await
+            }
+            None => self.inner.retrieve(req).await,
+        }
+    }
+}
+
+#[async_trait]
+impl RagEngine for ExpandedRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        if self.expansion(&req) == QueryExpansion::None {
+            return self.inner.query(req).await;
+        }
+        let start_time = std::time::Instant::now();
+        let citations = self.retrieve(&req).await?;
+        let mut response = self.inner.generate(&req, citations).await?;
+        response.latency_ms = start_time.elapsed().as_millis() as i64;
+        Ok(response)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        match self.expansion(req) {
+            QueryExpansion::None => self.inner.retrieve(req).await,
+            QueryExpansion::MultiQuery => self.multi_query_retrieve(req).await,
+            QueryExpansion::Hyde => self.hyde_retrieve(req).await,
+        }
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.inner.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.inner.generate_stream(req, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+/// 检索评测用例
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalCase {
+    pub query: String,
+    /// 相关文档的 document_id
+    pub relevant: Vec<String>,
+}
+
+/// 一种扩展方式在评测集上的结果（按文档计）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalReport {
+    pub expansion: QueryExpansion,
+    pub cases: usize,
+    /// 平均 recall@top_k
+    pub recall: f32,
+    /// 首个相关文档排名倒数的平均值
+    pub mrr: f32,
+}
+
+/// 以指定扩展方式检索评测集，各方式使用同一组用例即可直接比较
+pub async fn evaluate(
+    engine: &dyn RagEngine,
+    cases: &[EvalCase],
+    expansion: QueryExpansion,
+    top_k: u16,
+) -> Result<EvalReport> {
+    let mut recall = 0.0;
+    let mut mrr = 0.0;
+    for case in cases {
+        let req = QueryRequest {
+            query: case.query.clone(),
+            top_k: Some(top_k),
+            expansion: Some(expansion),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await?;
+        let mut documents: Vec<&str> = Vec::new();
+        for c in &citations {
+            if !documents.contains(&c.document_id.as_str()) {
+                documents.push(&c.document_id);
+            }
+        }
+        let relevant: HashSet<&str> = case.relevant.iter().map(String::as_str).collect();
+        if !relevant.is_empty() {
+            let found = documents.iter().filter(|d| relevant.contains(*d)).count();
+            recall += found as f32 / relevant.len() as f32;
+        }
+        if let Some(rank) = documents.iter().position(|d| relevant.contains(d)) {
+            mrr += 1.0 / (rank + 1) as f32;
+        }
+    }
+    let n = cases.len().max(1) as f32;
+    Ok(EvalReport {
+        expansion,
+        cases: cases.len(),
+        recall: recall / n,
+        mrr: mrr / n,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::ChatResponse;
+
+    const CORPUS: &[(&str, &str)] = &[
+        (
+            "refund",
+            "refund policy: purchases returned within 7 days get a full refund",
+        ),
+        ("shipping", "orders ship within 3 business days"),
+        (
+            "password",
+            "reset your password from the account settings page",
+        ),
+    ];
+
+    /// 按词重叠数检索的词法引擎
+    struct KeywordEngine;
+
+    #[async_trait]
+    impl RagEngine for KeywordEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            let citations = self.retrieve(&req).await?;
+            self.generate(&req, citations).await
+        }
+
+        async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+            let words: HashSet<String> = req
+                .query
+                .to_lowercase()
+                .split(|c: char| !c.is_alphanumeric())
+                .map(str::to_string)
+                .collect();
+            let mut hits: Vec<Citation> = CORPUS
+                .iter()
+                .filter_map(|(id, text)| {
+                    let overlap = text
+                        .split(|c: char| !c.is_alphanumeric())
+                        .filter(|w| w.len() > 2 && words.contains(*w))
+                        .count();
+                    (overlap > 0).then(|| Citation {
+                        document_id: id.to_string(),
+                        chunk_id: format!("{}#0", id),
+                        page: None,
+                        score: overlap as f32,
+                        snippet: text.to_string(),
+                        header: None,
+                        text: None,
+                    })
+                })
+                .collect();
+            hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+            hits.truncate(req.top_k.unwrap_or(5) as usize);
+            Ok(hits)
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 按问题返回预设的改写问题或假设性回答
+    struct ScriptedChat;
+
+    #[async_trait]
+    impl ChatModel for ScriptedChat {
+        async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+            let question = &req.messages.last().unwrap().content;
+            let multi_query = req.response_format.is_some();
+            let content = match (question.as_str(), multi_query) {
+                ("money back?", true) => {
+                    r#"{"queries": ["refund policy", "refund for returned purchases"]}"#
+                }
+                ("money back?", false) => {
+                    "You get a full refund if purchases are returned in time."
+                }
+                ("can't log in", true) => r#"{"queries": ["reset password", "can't log in"]}"#,
+                ("can't log in", false) => "Reset your password on the account settings page.",
+                _ => "",
+            };
+            Ok(ChatResponse {
+                content: content.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn engine() -> ExpandedRagEngine {
+        ExpandedRagEngine::new(
+            Arc::new(KeywordEngine),
+            Arc::new(ScriptedChat),
+            ExpansionConfig::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_multi_query_fuses_paraphrase_results() {
+        let engine = engine();
+        assert_eq!(
+            engine.paraphrase("can't log in").await,
+            vec!["reset password".to_string()]
+        );
+        let req = QueryRequest {
+            query: "money back?".to_string(),
+            top_k: Some(2),
+            expansion: Some(QueryExpansion::MultiQuery),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        // 两条改写问题都命中 refund，按 RRF 排在首位；得分为各路检索中的最高词重叠数
+        assert_eq!(citations[0].document_id, "refund");
+        assert_eq!(citations[0].score, 4.0);
+    }
+
+    #[tokio::test]
+    async fn test_expansion_improves_recall_on_vague_questions() {
+        let engine = engine();
+        let cases = vec![
+            EvalCase {
+                query: "money back?".to_string(),
+                relevant: vec!["refund".to_string()],
+            },
+            EvalCase {
+                query: "can't log in".to_string(),
+                relevant: vec!["password".to_string()],
+            },
+        ];
+        let mut reports = Vec::new();
+        for expansion in [
+            QueryExpansion::None,
+            QueryExpansion::MultiQuery,
+            QueryExpansion::Hyde,
+        ] {
+            reports.push(evaluate(&engine, &cases, expansion, 3).await.unwrap());
+        }
+        assert_eq!(reports[0].recall, 0.0);
+        assert_eq!(reports[1].recall, 1.0);
+        assert_eq!(reports[2].recall, 1.0);
+        assert_eq!(reports[1].mrr, 1.0);
+    }
+}
@@ -0,0 +1,528 @@
+use async_trait::async_trait;
+use kb_core::{
+    Citation, GroundingIssue, GroundingReport, QueryRequest, QueryResponse, SentenceGrounding,
+};
+use kb_error::Result;
+use kb_llm::tokens::truncate_to_tokens;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagMeta};
+
+/// 引用核验配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroundingConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 去掉引用标记后不少于该字符数的句子视为需要引用的陈述句
+    #[serde(default = "default_min_claim_chars")]
+    pub min_claim_chars: usize,
+    /// 是否调用对话模型判断引用内容是否支持句子
+    #[serde(default)]
+    pub entailment: bool,
+    /// 蕴含得分低于该值时标记为不被支持
+    #[serde(default = "default_entailment_threshold")]
+    pub entailment_threshold: f32,
+    /// 单次蕴含判断最多核验的句子数
+    #[serde(default = "default_max_entailment_claims")]
+    pub max_entailment_claims: usize,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_min_claim_chars() -> usize {
+    12
+}
+
+fn default_entailment_threshold() -> f32 {
+    0.5
+}
+
+fn default_max_entailment_claims() -> usize {
+    20
+}
+
+impl Default for GroundingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            min_claim_chars: default_min_claim_chars(),
+            entailment: false,
+            entailment_threshold: default_entailment_threshold(),
+            max_entailment_claims: default_max_entailment_claims(),
+        }
+    }
+}
+
+/// 蕴含判断时每条引用内容的 token 上限
+const ENTAILMENT_SNIPPET_TOKENS: usize = 300;
+
+const ENTAILMENT_SYSTEM_PROMPT: &str = "You verify whether claims are supported by their cited sources. For each claim, give a score between 0 and 1: 1 if the sources fully support the claim, 0 if they do not support it or contradict it. Return one score per claim, in order.";
+
+/// 蕴含判断的结构化输出
+#[derive(Debug, Deserialize, JsonSchema)]
+struct EntailmentScores {
+    /// 与输入的句子一一对应
+    scores: Vec<f32>,
+}
+
+/// 切分句子：按中英文句末标点与换行断开，句末标点之后的引用标记归入前一句
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            push_sentence(&mut sentences, &mut current);
+            continue;
+        }
+        current.push(c);
+        let ends = match c {
+            '。' | '！' | '？' | '；' => true,
+            // 英文句点后需跟空白或结尾，避免拆开小数与缩写
+            '.' | '!' | '?' => chars.peek().is_none_or(|n| n.is_whitespace() || *n == '['),
+            _ => false,
+        };
+        if ends {
+            push_sentence(&mut sentences, &mut current);
+        }
+    }
+    push_sentence(&mut sentences, &mut current);
+    sentences
+}
+
+fn push_sentence(sentences: &mut Vec<String>, current: &mut String) {
+    let text = std::mem::take(current);
+    let mut rest = text.trim();
+    // 开头的引用标记属于前一句，如 "Refunds take 7 days. [1]"
+    if let Some(prev) = sentences.last_mut() {
+        let mut moved = false;
+        while let Some((marker, after)) = leading_marker(rest) {
+            prev.push_str(marker);
+            rest = after.trim_start();
+            moved = true;
+        }
+        if moved {
+            rest = rest.trim_start_matches(['.', '。']).trim_start();
+        }
+    }
+    if !rest.is_empty() {
+        sentences.push(rest.to_string());
+    }
+}
+
+fn leading_marker(text: &str) -> Option<(&str, &str)> {
+    if !text.starts_with('[') {
+        return None;
+    }
+    let end = text.find(']')?;
+    parse_marker(&text[1..end])?;
+    Some(text.split_at(end + 1))
+}
+
+/// 解析引用标记内部，如 `1`、`1, 2`、`1-3`，非引用标记返回 `None`
+fn parse_marker(inner: &str) -> Option<Vec<usize>> {
+    let mut out = Vec::new();
+    for part in inner.split([',', '，', '、']) {
+        let part = part.trim();
+        match part.split_once(['-', '–']) {
+            Some((a, b)) => {
+                let (a, b) = (
+                    a.trim().parse::<usize>().ok()?,
+                    b.trim().parse::<usize>().ok()?,
+                );
+                if b < a || b - a > 20 {
+                    return None;
+                }
+                out.extend(a..=b);
+            }
+            None => out.push(part.parse().ok()?),
+        }
+    }
+    Some(out)
+}
+
+/// 提取句中的引用编号（去重，保持出现顺序）及去掉标记后的文本
+pub fn parse_citations(sentence: &str) -> (Vec<usize>, String) {
+    let mut numbers = Vec::new();
+    let mut text = String::with_capacity(sentence.len());
+    let mut rest = sentence;
+    while let Some(start) = rest.find('[') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find(']')
+            .and_then(|end| Some((parse_marker(&after[..end])?, end)))
+        {
+            Some((found, end)) => {
+                for n in found {
+                    if !numbers.contains(&n) {
+                        numbers.push(n);
+                    }
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('[');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    (numbers, text.trim().to_string())
+}
+
+/// 回答的引用核验：解析引用标记，标出无引用或引用无效的句子，可选由对话模型打分
+pub struct GroundingChecker {
+    chat: Option<Arc<dyn ChatModel>>,
+    config: GroundingConfig,
+}
+
+impl GroundingChecker {
+    pub fn new(config: GroundingConfig) -> Self {
+        Self { chat: None, config }
+    }
+
+    /// 设置蕴含判断使用的对话模型
+    pub fn with_chat_model(mut self, chat: Arc<dyn ChatModel>) -> Self {
+        self.chat = Some(chat);
+        self
+    }
+
+    /// 核验回答；`context_chunk_ids` 为实际发送给模型的引用，为空时不做该项检查
+    pub async fn check(
+        &self,
+        answer: &str,
+        citations: &[Citation],
+        context_chunk_ids: &[String],
+    ) -> GroundingReport {
+        let mut sentences = Vec::new();
+        let mut claims = 0;
+        let mut cited_claims = 0;
+        let mut invalid_citations = 0;
+        for sentence in split_sentences(answer) {
+            let (numbers, text) = parse_citations(&sentence);
+            let is_claim = text.chars().count() >= self.config.min_claim_chars;
+            if !is_claim && numbers.is_empty() {
+                continue;
+            }
+            let mut issues = Vec::new();
+            let mut valid = 0;
+            for &n in &numbers {
+                let issue = match citations.get(n.wrapping_sub(1)) {
+                    None => Some(GroundingIssue::OutOfRange),
+                    Some(c)
+                        if !context_chunk_ids.is_empty()
+                            && !context_chunk_ids.contains(&c.chunk_id) =>
+                    {
+                        Some(GroundingIssue::NotInContext)
+                    }
+                    Some(_) => None,
+                };
+                match issue {
+                    Some(issue) => {
+                        invalid_citations += 1;
+                        if !issues.contains(&issue) {
+                            issues.push(issue);
+                        }
+                    }
+                    None => valid += 1,
+                }
+            }
+            if is_claim {
+                claims += 1;
+                if numbers.is_empty() {
+                    issues.push(GroundingIssue::Uncited);
+                } else if valid > 0 {
+                    cited_claims += 1;
+                }
+            }
+            sentences.push(SentenceGrounding {
+                text: sentence,
+                citations: numbers,
+                issues,
+                entailment: None,
+            });
+        }
+
+        let uncited = sentences
+            .iter()
+            .filter(|s| s.issues.contains(&GroundingIssue::Uncited))
+            .count();
+        let mut report = GroundingReport {
+            citation_coverage: if claims == 0 {
+                1.0
+            } else {
+                cited_claims as f32 / claims as f32
+            },
+            uncited,
+            invalid_citations,
+            unsupported: None,
+            sentences,
+        };
+        if self.config.entailment {
+            self.score_entailment(&mut report, citations).await;
+        }
+        debug!(
+            sentences = report.sentences.len(),
+            coverage = report.citation_coverage,
+            uncited = report.uncited,
+            invalid = report.invalid_citations,
+            unsupported = ?report.unsupported,
+            "引用核验"
+        );
+        report
+    }
+
+    /// 对带有效引用的句子做蕴含判断，失败时保留未打分的报告
+    async fn score_entailment(&self, report: &mut GroundingReport, citations: &[Citation]) {
+        let Some(chat) = &self.chat else {
+            return;
+        };
+        let targets: Vec<usize> = report
+            .sentences
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.citations.iter().any(|&n| n >= 1 && n <= citations.len()))
+            .map(|(i, _)| i)
+            .take(self.config.max_entailment_claims)
+            .collect();
+        if targets.is_empty() {
+            report.unsupported = Some(0);
+            return;
+        }
+
+        let claims = targets
+            .iter()
+            .enumerate()
+            .map(|(k, &i)| {
+                let sentence = &report.sentences[i];
+                let sources = sentence
+                    .citations
+                    .iter()
+                    .filter_map(|&n| citations.get(n.wrapping_sub(1)).map(|c| (n, c)))
+                    .map(|(n, c)| {
+                        format!(
+                            "[{}] {}",
+                            n,
+                            truncate_to_tokens(c.content(), ENTAILMENT_SNIPPET_TOKENS)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "Claim {}: {}\nSources:\n{}",
+                    k + 1,
+                    parse_citations(&sentence.text).1,
+                    sources
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(ENTAILMENT_SYSTEM_PROMPT),
+            ChatMessage::user(claims),
+        ])
+        .with_temperature(0.0);
+
+        let scores = match kb_llm::chat_json::<EntailmentScores>(chat.as_ref(), req).await {
+            Ok(out) if out.scores.len() == targets.len() => out.scores,
+            Ok(out) => {
+                warn!(
+                    expected = targets.len(),
+                    got = out.scores.len(),
+                    "蕴含判断返回的得分数量不符"
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(error = %e, "蕴含判断失败");
+                return;
+            }
+        };
+
+        let mut unsupported = 0;
+        for (&i, score) in targets.iter().zip(scores) {
+            let score = score.clamp(0.0, 1.0);
+            let sentence = &mut report.sentences[i];
+            sentence.entailment = Some(score);
+            if score < self.config.entailment_threshold {
+                sentence.issues.push(GroundingIssue::Unsupported);
+                unsupported += 1;
+            }
+        }
+        report.unsupported = Some(unsupported);
+    }
+}
+
+/// 在回答后附加引用核验报告的 RAG 引擎包装
+pub struct GroundedRagEngine {
+    inner: Arc<dyn RagEngine>,
+    checker: Arc<GroundingChecker>,
+}
+
+impl GroundedRagEngine {
+    pub fn new(inner: Arc<dyn RagEngine>, checker: Arc<GroundingChecker>) -> Self {
+        Self { inner, checker }
+    }
+
+    async fn attach(&self, mut resp: QueryResponse) -> QueryResponse {
+        if !resp.citations.is_empty() {
+            let report = self
+                .checker
+                .check(&resp.answer, &resp.citations, &resp.context_chunk_ids)
+                .await;
+            resp.grounding = Some(report);
+        }
+        resp
+    }
+}
+
+#[async_trait]
+impl RagEngine for GroundedRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        let resp = self.inner.query(req).await?;
+        Ok(self.attach(resp).await)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.inner.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        let resp = self.inner.generate(req, citations).await?;
+        Ok(self.attach(resp).await)
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.inner.generate_stream(req, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::ChatResponse;
+
+    fn citation(chunk_id: &str, snippet: &str) -> Citation {
+        Citation {
+            document_id: "doc".to_string(),
+            chunk_id: chunk_id.to_string(),
+            page: None,
+            score: 0.9,
+            snippet: snippet.to_string(),
+            header: None,
+            text: None,
+        }
+    }
+
+    #[test]
+    fn test_split_and_parse_markers() {
+        let sentences = split_sentences(
+            "Refunds take 7 days. [1] Version 2.5 added it [2, 3].\n退款需要提交申请。[1-2]好的",
+        );
+        assert_eq!(
+            sentences,
+            vec![
+                "Refunds take 7 days.[1]",
+                "Version 2.5 added it [2, 3].",
+                "退款需要提交申请。[1-2]",
+                "好的"
+            ]
+        );
+        assert_eq!(
+            parse_citations("Version 2.5 added it [2, 3][2] [note]."),
+            (vec![2, 3], "Version 2.5 added it  [note].".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flags_uncited_and_invalid_citations() {
+        let citations = vec![citation("a#0", "refunds"), citation("b#0", "shipping")];
+        let checker = GroundingChecker::new(GroundingConfig::default());
+        let answer =
+            "Refunds are processed within seven days [1]. Shipping is free for all orders. \
+                      Returns need a receipt [5]. Exchanges are handled by stores [2]. OK.";
+        let report = checker
+            .check(answer, &citations, &["a#0".to_string()])
+            .await;
+        let issues: Vec<Vec<GroundingIssue>> =
+            report.sentences.iter().map(|s| s.issues.clone()).collect();
+        assert_eq!(
+            issues,
+            vec![
+                vec![],
+                vec![GroundingIssue::Uncited],
+                vec![GroundingIssue::OutOfRange],
+                vec![GroundingIssue::NotInContext],
+            ]
+        );
+        assert_eq!(report.uncited, 1);
+        assert_eq!(report.invalid_citations, 2);
+        assert_eq!(report.citation_coverage, 0.25);
+        assert_eq!(report.unsupported, None);
+    }
+
+    struct FixedChat(&'static str);
+
+    #[async_trait]
+    impl ChatModel for FixedChat {
+        async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+            Ok(ChatResponse {
+                content: self.0.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entailment_marks_unsupported() {
+        let citations = vec![citation("a#0", "Refunds take seven days.")];
+        let checker = GroundingChecker::new(GroundingConfig {
+            entailment: true,
+            ..Default::default()
+        })
+        .with_chat_model(Arc::new(FixedChat(r#"{"scores": [0.9, 0.1]}"#)));
+        let answer = "Refunds take seven days [1]. Refunds are paid in cash [1].";
+        let report = checker.check(answer, &citations, &[]).await;
+        assert_eq!(report.unsupported, Some(1));
+        assert_eq!(report.sentences[0].entailment, Some(0.9));
+        assert_eq!(
+            report.sentences[1].issues,
+            vec![GroundingIssue::Unsupported]
+        );
+    }
+}
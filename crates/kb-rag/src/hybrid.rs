@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::Result;
+use kb_llm::ChatStream;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
@@ -90,11 +91,12 @@ impl Default for HybridConfig {
 
 /// 检索结果与来源引擎信息
 #[derive(Debug, Clone)]
-struct EngineResult {
-    citation: Citation,
-    engine_type: String,
-    original_rank: usize,
-    normalized_score: f32,
+pub(crate) struct EngineResult {
+    pub(crate) citation: Citation,
+    /// 来源标识，未配置权重的来源按 1.0 计
+    pub(crate) engine_type: String,
+    pub(crate) original_rank: usize,
+    pub(crate) normalized_score: f32,
 }
 
 impl HybridRagEngine {
@@ -138,8 +140,8 @@ impl HybridRagEngine {
 
         // 1. 向量检索
         debug!("执行向量检索");
-        let vector_response = self.vector_engine.query(search_req.clone()).await?;
-        for (rank, citation) in vector_response.citations.into_iter().enumerate() {
+        let vector_citations = self.vector_engine.retrieve(&search_req).await?;
+        for (rank, citation) in vector_citations.into_iter().enumerate() {
             all_results.push(EngineResult {
                 citation,
                 engine_type: "vector".to_string(),
@@ -151,8 +153,8 @@ impl HybridRagEngine {
         // 2. 词汇检索（如果可用）
         if let Some(ref lexical_engine) = self.lexical_engine {
             debug!("执行词汇检索");
-            let lexical_response = lexical_engine.query(search_req.clone()).await?;
-            for (rank, citation) in lexical_response.citations.into_iter().enumerate() {
+            let lexical_citations = lexical_engine.retrieve(&search_req).await?;
+            for (rank, citation) in lexical_citations.into_iter().enumerate() {
                 all_results.push(EngineResult {
                     citation,
                     engine_type: "lexical".to_string(),
@@ -165,8 +167,8 @@ impl HybridRagEngine {
         // 3. 图检索（如果可用）
         if let Some(ref graph_engine) = self.graph_engine {
             debug!("执行图检索");
-            let graph_response = graph_engine.query(search_req).await?;
-            for (rank, citation) in graph_response.citations.into_iter().enumerate() {
+            let graph_citations = graph_engine.retrieve(&search_req).await?;
+            for (rank, citation) in graph_citations.into_iter().enumerate() {
                 all_results.push(EngineResult {
                     citation,
                     engine_type: "graph".to_string(),
@@ -183,8 +185,18 @@ impl HybridRagEngine {
         // 4. 分数归一化
         self.normalize_scores(&mut all_results)?;
 
-        // 5. 结果融合
-        let fused_results = self.fuse_results(&all_results)?;
+        // 5. 结果融合：按融合得分排序，得分换回各路检索中的最高原始得分，
+        // 使外层按相似度阈值判断的可回答性门控与重排仍然适用
+        let key = |c: &Citation| format!("{}#{}", c.document_id, c.chunk_id);
+        let mut best: HashMap<String, f32> = HashMap::new();
+        for r in &all_results {
+            let score = best.entry(key(&r.citation)).or_insert(f32::MIN);
+            *score = score.max(r.citation.score);
+        }
+        let mut fused_results = self.fuse_results(&all_results)?;
+        for citation in &mut fused_results {
+            citation.score = best[&key(citation)];
+        }
 
         // 6. 去重（如果启用）
         let deduplicated = if self.config.enable_deduplication {
@@ -279,69 +291,7 @@ impl HybridRagEngine {
 
     /// 融合来自不同引擎的结果
     fn fuse_results(&self, results: &[EngineResult]) -> Result<Vec<Citation>> {
-        let mut citation_scores: HashMap<String, (Citation, f32, Vec<String>)> = HashMap::new();
-
-        for result in results {
-            let key = format!(
-                "{}#{}",
-                result.citation.document_id, result.citation.chunk_id
-            );
-
-            let weight = match result.engine_type.as_str() {
-                "vector" => self.config.vector_weight,
-                "lexical" => self.config.lexical_weight,
-                "graph" => self.config.graph_weight,
-                _ => 1.0,
-            };
-
-            let (citation, combined_score, engines) = citation_scores
-                .entry(key)
-                .or_insert_with(|| (result.citation.clone(), 0.0, Vec::new()));
-
-            // 根据融合策略计算分数
-            let contribution = match self.config.fusion_strategy {
-                FusionStrategy::WeightedSum => result.normalized_score * weight,
-                FusionStrategy::RRF { k } => weight / (k + result.original_rank as f32 + 1.0),
-                FusionStrategy::CombSum => result.normalized_score,
-                FusionStrategy::CombMNZ => result.normalized_score * weight,
-            };
-
-            *combined_score += contribution;
-            engines.push(result.engine_type.clone());
-
-            // 更新引用信息（保留最高分数的版本）
-            if result.citation.score > citation.score {
-                *citation = result.citation.clone();
-            }
-        }
-
-        // CombMNZ 需要乘以匹配引擎数量
-        if matches!(self.config.fusion_strategy, FusionStrategy::CombMNZ) {
-            for (_, (_, score, engines)) in citation_scores.iter_mut() {
-                *score *= engines.len() as f32;
-            }
-        }
-
-        // 转换为最终结果并排序
-        let mut final_results: Vec<Citation> = citation_scores
-            .into_iter()
-            .filter_map(|(_, (mut citation, score, _))| {
-                if score >= self.config.min_score_threshold {
-                    citation.score = score;
-                    Some(citation)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        final_results.sort_by(|a, b| {
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
-        Ok(final_results)
+        Ok(fuse_results(&self.config, results))
     }
 
     /// 去重结果
@@ -382,6 +332,73 @@ impl HybridRagEngine {
     }
 }
 
+/// 按融合策略合并多路检索结果，多查询扩展也复用此处的 RRF
+pub(crate) fn fuse_results(config: &HybridConfig, results: &[EngineResult]) -> Vec<Citation> {
+    let mut citation_scores: HashMap<String, (Citation, f32, Vec<String>)> = HashMap::new();
+
+    for result in results {
+        let key = format!(
+            "{}#{}",
+            result.citation.document_id, result.citation.chunk_id
+        );
+
+        let weight = match result.engine_type.as_str() {
+            "vector" => config.vector_weight,
+            "lexical" => config.lexical_weight,
+            "graph" => config.graph_weight,
+            _ => 1.0,
+        };
+
+        let (citation, combined_score, engines) = citation_scores
+            .entry(key)
+            .or_insert_with(|| (result.citation.clone(), 0.0, Vec::new()));
+
+        // 根据融合策略计算分数
+        let contribution = match config.fusion_strategy {
+            FusionStrategy::WeightedSum => result.normalized_score * weight,
+            FusionStrategy::RRF { k } => weight / (k + result.original_rank as f32 + 1.0),
+            FusionStrategy::CombSum => result.normalized_score,
+            FusionStrategy::CombMNZ => result.normalized_score * weight,
+        };
+
+        *combined_score += contribution;
+        engines.push(result.engine_type.clone());
+
+        // 更新引用信息（保留最高分数的版本）
+        if result.citation.score > citation.score {
+            *citation = result.citation.clone();
+        }
+    }
+
+    // CombMNZ 需要乘以匹配引擎数量
+    if matches!(config.fusion_strategy, FusionStrategy::CombMNZ) {
+        for (_, (_, score, engines)) in citation_scores.iter_mut() {
+            *score *= engines.len() as f32;
+        }
+    }
+
+    // 转换为最终结果并排序
+    let mut final_results: Vec<Citation> = citation_scores
+        .into_iter()
+        .filter_map(|(_, (mut citation, score, _))| {
+            if score >= config.min_score_threshold {
+                citation.score = score;
+                Some(citation)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    final_results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    final_results
+}
+
 /// 混合检索统计信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HybridStats {
@@ -408,33 +425,37 @@ impl RagEngine for HybridRagEngine {
                 contexts: vec![],
                 mode: "hybrid".to_string(),
                 latency_ms: start_time.elapsed().as_millis() as i64,
+                ..Default::default()
             });
         }
 
-        // 使用向量引擎来生成回答（因为它有完整的 LLM 集成）
-        let vector_response = self
-            .vector_engine
-            .query(QueryRequest {
-                query: req.query.clone(),
-                mode: Some("vector".to_string()),
-                top_k: Some(citations.len() as u16),
-                filters: req.filters.clone(),
-                rerank: req.rerank,
-                include_raw_matches: req.include_raw_matches,
-                stream: req.stream,
-            })
-            .await?;
+        let mut response = self.generate(&req, citations).await?;
+        response.latency_ms = start_time.elapsed().as_millis() as i64;
+
+        Ok(response)
+    }
 
-        // 替换引用结果为混合检索的结果
-        let contexts: Vec<String> = citations.iter().map(|c| c.snippet.clone()).collect();
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.perform_hybrid_search(req).await
+    }
 
-        Ok(QueryResponse {
-            answer: vector_response.answer,
-            citations,
-            contexts,
-            mode: "hybrid".to_string(),
-            latency_ms: start_time.elapsed().as_millis() as i64,
-        })
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        // 使用向量引擎来生成回答（因为它有完整的 LLM 集成）
+        let mut response = self.vector_engine.generate(req, citations).await?;
+        response.mode = "hybrid".to_string();
+        Ok(response)
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.vector_engine.generate_stream(req, citations).await
     }
 
     async fn add_document_text_with_meta(
@@ -575,6 +596,8 @@ mod tests {
                 page: None,
                 score: 0.9,
                 snippet: "test".to_string(),
+                header: None,
+                text: None,
             },
             Citation {
                 document_id: "doc1".to_string(),
@@ -582,6 +605,8 @@ mod tests {
                 page: None,
                 score: 0.8,
                 snippet: "test".to_string(),
+                header: None,
+                text: None,
             },
         ];
 
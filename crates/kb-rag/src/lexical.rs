@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::Result;
+use kb_llm::ChatStream;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
@@ -98,6 +99,8 @@ pub struct LexicalSearchResult {
     pub score: f32,
     pub matched_terms: Vec<String>,
     pub snippet: String,
+    /// 完整的分块文本
+    pub content: String,
 }
 
 impl LexicalRagEngine {
@@ -203,6 +206,7 @@ impl LexicalRagEngine {
                         score,
                         matched_terms,
                         snippet: self.extract_snippet(&doc_info.content, &query_tokens),
+                        content: doc_info.content.clone(),
                     });
                 }
             }
@@ -416,50 +420,87 @@ impl RagEngine for LexicalRagEngine {
     async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
         let start_time = std::time::Instant::now();
 
-        // 执行词汇搜索
-        let search_results = self
-            .search(&req.query, req.top_k.map(|k| k as usize))
-            .await?;
+        let citations = self.retrieve(&req).await?;
 
-        if search_results.is_empty() {
+        if citations.is_empty() {
             return Ok(QueryResponse {
                 answer: "没有找到相关的文档内容".to_string(),
                 citations: vec![],
                 contexts: vec![],
                 mode: "lexical".to_string(),
                 latency_ms: start_time.elapsed().as_millis() as i64,
+                ..Default::default()
             });
         }
 
-        // 转换为引用格式
-        let citations: Vec<Citation> = search_results
+        // 按 token 预算组装上下文
+        let assembled = self.base.assemble_context(&req.query, &citations);
+        let context_chunk_ids = assembled
+            .parts
             .iter()
-            .enumerate()
-            .map(|(_idx, result)| Citation {
-                document_id: result.document_id.clone(),
-                chunk_id: result.chunk_id.clone(),
-                page: None, // 从文档信息中获取
-                score: result.score,
-                snippet: result.snippet.clone(),
-            })
+            .map(|p| citations[p.index].chunk_id.clone())
             .collect();
 
-        // 格式化上下文
-        let context = self.base.format_context(&citations);
-        let contexts: Vec<String> = citations.iter().map(|c| c.snippet.clone()).collect();
-
         // 生成回答
-        let answer = self.base.generate_answer(&context, &req.query).await?;
+        let answer = self
+            .base
+            .generate_answer(&assembled.text, &req.query)
+            .await?;
+        let contexts = assembled.parts.into_iter().map(|p| p.snippet).collect();
 
         Ok(QueryResponse {
             answer,
             citations,
             contexts,
+            context_chunk_ids,
             mode: "lexical".to_string(),
             latency_ms: start_time.elapsed().as_millis() as i64,
+            ..Default::default()
         })
     }
 
+    #[instrument(skip(self, req))]
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        // 执行词汇搜索
+        let search_results = self
+            .search(&req.query, req.top_k.map(|k| k as usize))
+            .await?;
+
+        // 转换为引用格式
+        let citations = search_results
+            .into_iter()
+            .map(|result| Citation {
+                document_id: result.document_id,
+                chunk_id: result.chunk_id,
+                page: None, // 从文档信息中获取
+                score: result.score,
+                snippet: result.snippet,
+                header: None,
+                text: Some(result.content),
+            })
+            .collect();
+
+        Ok(citations)
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.base
+            .answer_with_citations(req, citations, "lexical")
+            .await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.base.stream_with_citations(req, citations).await
+    }
+
     async fn add_document_text_with_meta(
         &self,
         document_id: &str,
@@ -505,13 +546,14 @@ mod tests {
 
     #[async_trait]
     impl kb_llm::ChatModel for MockChatModel {
-        async fn chat(
+        async fn complete(
             &self,
-            _system: &str,
-            _context: &str,
-            _query: &str,
-        ) -> kb_llm::Result<String> {
-            Ok("Mock response".to_string())
+            _req: &kb_llm::ChatRequest,
+        ) -> kb_llm::Result<kb_llm::ChatResponse> {
+            Ok(kb_llm::ChatResponse {
+                content: "Mock response".to_string(),
+                ..Default::default()
+            })
         }
     }
 
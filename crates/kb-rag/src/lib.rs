@@ -1,30 +1,65 @@
+pub mod answer_cache;
+pub mod answerability;
+pub mod context;
+pub mod contextual;
 pub mod engine;
+pub mod expansion;
+pub mod grounding;
 pub mod hybrid;
 pub mod lexical;
 pub mod memory;
 pub mod multi_provider;
-pub mod qdrantss;
+pub mod multihop;
+pub mod parent;
 pub mod qdrant;
+pub mod qdrantss;
 pub mod rerank;
+pub mod reranking;
+pub mod rewrite;
+pub mod router;
+pub mod tools;
 
 // 重新导出新的模块化架构
+pub use answer_cache::{
+    current_tenant, with_tenant, AnswerCacheConfig, AnswerCacheStats, CachedRagEngine,
+};
+pub use answerability::{
+    AnswerabilityConfig, AnswerabilityGate, GatedRagEngine, INSUFFICIENT_EVIDENCE_ANSWER,
+};
+pub use context::{AssembledContext, ContextConfig, ContextPart};
+pub use contextual::ContextualHeaderConfig;
 pub use engine::{
     BaseRagEngine, EngineStats, GraphRagEngine, HealthStatus, NoopRagEngine, RagDocumentChunk,
     RagEngine, RagEngineConfig, RagMeta,
 };
+pub use expansion::{evaluate, EvalCase, EvalReport, ExpandedRagEngine, ExpansionConfig};
+pub use grounding::{GroundedRagEngine, GroundingChecker, GroundingConfig};
 pub use hybrid::{FusionStrategy, HybridConfig, HybridRagEngine, HybridStats, ScoreNormalization};
 pub use lexical::{LexicalConfig, LexicalIndexStats, LexicalRagEngine};
 pub use memory::MemoryRagEngine;
 pub use multi_provider::{MultiProviderRagEngine as RealMultiProviderRagEngine, StorageType};
+pub use multihop::{MultiHopConfig, MultiHopRagEngine, QueryPlanner, MULTIHOP_MODE};
+pub use parent::{expand_to_windows, ParentChunkConfig};
 pub use qdrantss::QdrantRagEngine;
-pub use rerank::{Reranker, RerankerFactory};
+pub use rerank::{Reranker, RerankerConfig, RerankerFactory, RerankerSpec};
+pub use reranking::RerankingRagEngine;
+pub use rewrite::{ConversationalRagEngine, QueryRewriter, Rewrite, RewriteConfig};
+pub use router::{QueryRouter, Route, RouteDecision, RoutedRagEngine, RouterConfig, AUTO_MODE};
+pub use tools::{
+    AgentEvent, AgentRun, GraphEntityLookupTool, KnowledgeBaseSearchTool, RagTool, ToolAgent,
+    ToolSet,
+};
 
 // 重新导出核心类型
-pub use kb_core::{Citation, QueryRequest, QueryResponse};
+pub use kb_core::{
+    Citation, ConversationTurn, GroundingReport, QueryExpansion, QueryRequest, QueryResponse,
+    SubQuestion,
+};
 pub use kb_error::{KbError, Result};
 
 // 兼容性别名和占位实现
 use async_trait::async_trait;
+use kb_llm::ChatStream;
 use std::sync::Arc;
 
 pub use engine::NoopRagEngine as DefaultRagEngine;
@@ -35,26 +70,48 @@ pub struct RigInMemoryRagEngine(MemoryRagEngine);
 pub struct MultiProviderRagEngine(RealMultiProviderRagEngine);
 
 impl RigQdrantRagEngine {
+    /// 兼容构造：使用确定性的特征哈希向量，仅用于测试与 CI；生产环境使用 `with_models`
     pub async fn new(
         url: String,
         collection: String,
         _embed_model: String,
         chat_model: Arc<dyn kb_llm::ChatModel>,
     ) -> Result<Self> {
-        // 为了兼容性，我们创建一个默认的嵌入模型
-        // 在实际使用中，应该从配置或环境变量中获取真正的嵌入模型
-        let embed_model = Arc::new(memory::MockEmbedModel);
-        let engine = QdrantRagEngine::new(url, collection, chat_model, embed_model, None).await?;
+        let embed_model = Arc::new(kb_llm::HashingEmbedModel::default());
+        Self::with_models(url, collection, chat_model, embed_model, None).await
+    }
+
+    /// 使用配置的向量模型（含缓存、分批与限流包装）与引擎配置（分块、上下文限制等）
+    pub async fn with_models(
+        url: String,
+        collection: String,
+        chat_model: Arc<dyn kb_llm::ChatModel>,
+        embed_model: Arc<dyn kb_llm::EmbedModel>,
+        config: Option<RagEngineConfig>,
+    ) -> Result<Self> {
+        let engine = QdrantRagEngine::new(url, collection, chat_model, embed_model, config).await?;
         Ok(Self(engine))
     }
 }
 
 impl RigInMemoryRagEngine {
+    /// 兼容构造：使用确定性的特征哈希向量，仅用于测试与 CI；生产环境使用 `with_models`
     pub fn new(_embed_model: String, chat_model: Arc<dyn kb_llm::ChatModel>) -> Self {
-        // 为了兼容性，我们创建一个默认的嵌入模型
-        let embed_model = Arc::new(memory::MockEmbedModel);
-        let engine = MemoryRagEngine::from_models(chat_model, embed_model, None);
-        Self(engine)
+        let embed_model = Arc::new(kb_llm::HashingEmbedModel::default());
+        Self::with_models(chat_model, embed_model, None)
+    }
+
+    /// 使用配置的向量模型与引擎配置
+    pub fn with_models(
+        chat_model: Arc<dyn kb_llm::ChatModel>,
+        embed_model: Arc<dyn kb_llm::EmbedModel>,
+        config: Option<RagEngineConfig>,
+    ) -> Self {
+        Self(MemoryRagEngine::from_models(
+            chat_model,
+            embed_model,
+            config,
+        ))
     }
 }
 
@@ -63,7 +120,16 @@ impl MultiProviderRagEngine {
         chat_model: Arc<dyn kb_llm::ChatModel>,
         embed_model: Arc<dyn kb_llm::EmbedModel>,
     ) -> Self {
-        let engine = RealMultiProviderRagEngine::new_memory(chat_model, embed_model, None);
+        Self::with_config(chat_model, embed_model, None)
+    }
+
+    /// 指定引擎配置（如按模型注册表收紧的分块与上下文限制）
+    pub fn with_config(
+        chat_model: Arc<dyn kb_llm::ChatModel>,
+        embed_model: Arc<dyn kb_llm::EmbedModel>,
+        config: Option<RagEngineConfig>,
+    ) -> Self {
+        let engine = RealMultiProviderRagEngine::new_memory(chat_model, embed_model, config);
         Self(engine)
     }
 }
@@ -75,6 +141,26 @@ impl RagEngine for RigQdrantRagEngine {
         self.0.query(req).await
     }
 
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.0.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.0.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.0.generate_stream(req, citations).await
+    }
+
     async fn add_document_text_with_meta(
         &self,
         document_id: &str,
@@ -94,6 +180,26 @@ impl RagEngine for RigInMemoryRagEngine {
         self.0.query(req).await
     }
 
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.0.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.0.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.0.generate_stream(req, citations).await
+    }
+
     async fn add_document_text_with_meta(
         &self,
         document_id: &str,
@@ -113,6 +219,26 @@ impl RagEngine for MultiProviderRagEngine {
         self.0.query(req).await
     }
 
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.0.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.0.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.0.generate_stream(req, citations).await
+    }
+
     async fn add_document_text_with_meta(
         &self,
         document_id: &str,
@@ -138,6 +264,7 @@ impl GraphRagEngine for DefaultGraphRagEngine {
             contexts: vec![],
             mode: req.mode.unwrap_or_else(|| "graph".into()),
             latency_ms: 0,
+            ..Default::default()
         })
     }
 
@@ -382,3 +509,48 @@ pub async fn index_web_url(
     let text = html2text::from_read(body.as_bytes(), 80);
     engine.add_document_text(document_id, &text, None).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::{ChatModel, ChatRequest, ChatResponse, HashingEmbedModel};
+
+    struct StubChatModel;
+
+    #[async_trait]
+    impl ChatModel for StubChatModel {
+        async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+            Ok(ChatResponse::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_wrapper_applies_engine_config() {
+        let config = RagEngineConfig {
+            headers: ContextualHeaderConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = RigInMemoryRagEngine::with_models(
+            Arc::new(StubChatModel),
+            Arc::new(HashingEmbedModel::default()),
+            Some(config),
+        );
+        engine
+            .add_document_text("policy", "# Refund Policy\nthe limit is 30 days", None)
+            .await
+            .unwrap();
+
+        let req = QueryRequest {
+            query: "refund limit".to_string(),
+            ..Default::default()
+        };
+        let citation = engine.retrieve(&req).await.unwrap().remove(0);
+        assert_eq!(
+            citation.header.as_deref(),
+            Some("Document: policy\nSection: Refund Policy")
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use crate::engine::{BaseRagEngine, RagDocumentChunk, RagEngine, RagEngineConfig, RagMeta};
+use crate::parent::expand_to_windows;
 use async_trait::async_trait;
 use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::{KbError, Result};
-use kb_llm::{ChatModel, EmbedModel};
+use kb_llm::{ChatModel, ChatStream, EmbedModel, HashingEmbedModel};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -18,6 +19,38 @@ pub struct MemoryChunk {
     pub page: Option<i32>,
     pub meta: Option<RagMeta>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub position: Option<usize>,
+    #[serde(default)]
+    pub header: Option<String>,
+}
+
+impl MemoryChunk {
+    /// 转换为统一的分块结构
+    fn to_document_chunk(&self) -> RagDocumentChunk {
+        let meta = self.meta.clone().unwrap_or_default();
+        let chunk = RagDocumentChunk {
+            document_id: self.document_id.clone(),
+            chunk_id: self.id.clone(),
+            page: self.page,
+            tenant_id: meta.tenant_id,
+            tags: meta.tags,
+            source: meta.source,
+            created_at: self.created_at.timestamp(),
+            custom_fields: meta.custom_fields,
+            parent_id: self.parent_id.clone(),
+            position: self.position,
+            header: None,
+            text: self.text.clone(),
+            embed_text: self.text.clone(),
+        };
+        match &self.header {
+            Some(header) => chunk.with_header(header.clone()),
+            None => chunk,
+        }
+    }
 }
 
 /// 基于内存的 RAG 引擎
@@ -46,12 +79,12 @@ impl MemoryRagEngine {
         chat_model: Arc<dyn kb_llm::ChatModel>,
     ) -> Result<Self> {
         // 兼容 RigQdrantRagEngine::new
-        let embed_model = Arc::new(MockEmbedModel);
+        let embed_model = Arc::new(HashingEmbedModel::default());
         Ok(Self::new_internal(chat_model, embed_model, None))
     }
 
     pub fn new_memory(_embed_model: String, chat_model: Arc<dyn kb_llm::ChatModel>) -> Self {
-        let embed_model = Arc::new(MockEmbedModel);
+        let embed_model = Arc::new(HashingEmbedModel::default());
         Self::new_internal(chat_model, embed_model, None)
     }
 
@@ -72,20 +105,6 @@ impl MemoryRagEngine {
     }
 }
 
-/// Mock 嵌入模型用于兼容性
-pub struct MockEmbedModel;
-
-#[async_trait]
-impl EmbedModel for MockEmbedModel {
-    async fn embed(&self, texts: &[String]) -> kb_llm::Result<Vec<Vec<f32>>> {
-        // 返回固定维度的随机向量用于测试
-        Ok(texts
-            .iter()
-            .map(|_| vec![0.1, 0.2, 0.3, 0.4, 0.5])
-            .collect())
-    }
-}
-
 impl MemoryRagEngine {
     /// 获取当前索引的文档数量
     pub async fn document_count(&self) -> usize {
@@ -115,6 +134,27 @@ impl MemoryRagEngine {
         Ok(removed_count)
     }
 
+    /// 读取指定父块下的全部子块
+    async fn children_of(&self, hits: &[(f32, RagDocumentChunk)]) -> Vec<RagDocumentChunk> {
+        let parent_ids: std::collections::HashSet<&str> = hits
+            .iter()
+            .filter_map(|(_, c)| c.parent_id.as_deref())
+            .collect();
+        if parent_ids.is_empty() {
+            return Vec::new();
+        }
+        let chunks = self.chunks.read().await;
+        chunks
+            .iter()
+            .filter(|c| {
+                c.parent_id
+                    .as_deref()
+                    .is_some_and(|p| parent_ids.contains(p))
+            })
+            .map(MemoryChunk::to_document_chunk)
+            .collect()
+    }
+
     /// 向量相似度搜索
     #[instrument(skip(self, query_embedding))]
     async fn vector_search(
@@ -217,6 +257,25 @@ impl RagEngine for MemoryRagEngine {
     async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
         let start_time = std::time::Instant::now();
 
+        let citations = self.retrieve(&req).await?;
+        let results_count = citations.len();
+        let mut response = self.generate(&req, citations).await?;
+
+        let latency_ms = start_time.elapsed().as_millis() as i64;
+        response.latency_ms = latency_ms;
+
+        tracing::info!(
+            query = %req.query,
+            results_count = results_count,
+            latency_ms = latency_ms,
+            "Memory RAG query completed"
+        );
+
+        Ok(response)
+    }
+
+    #[instrument(skip(self, req))]
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
         // 生成查询向量
         let query_embedding = self
             .base
@@ -238,64 +297,56 @@ impl RagEngine for MemoryRagEngine {
             .vector_search(&query_embedding, top_k, req.filters.as_ref())
             .await?;
 
-        // 过滤低相似度结果
-        let filtered_results: Vec<(f32, MemoryChunk)> = search_results
-            .into_iter()
-            .filter(|(score, _)| *score >= self.base.config.similarity_threshold)
-            .collect();
-
-        if filtered_results.is_empty() {
-            return Ok(QueryResponse {
-                answer: "抱歉，我在知识库中没有找到相关的信息来回答您的问题。".to_string(),
-                citations: vec![],
-                contexts: vec![],
-                mode: req.mode.unwrap_or_else(|| "memory".to_string()),
-                latency_ms: start_time.elapsed().as_millis() as i64,
-            });
+        if self.base.expand_parents(req) {
+            let hits: Vec<(f32, RagDocumentChunk)> = search_results
+                .iter()
+                .map(|(score, chunk)| (*score, chunk.to_document_chunk()))
+                .collect();
+            let children = self.children_of(&hits).await;
+            if !children.is_empty() {
+                return Ok(expand_to_windows(
+                    &self.base.config.parent,
+                    &hits,
+                    &children,
+                    self.base.context_budget(&req.query),
+                ));
+            }
         }
 
-        // 构建引用和上下文
-        let mut citations = Vec::new();
-        let mut contexts = Vec::new();
-
-        for (score, chunk) in &filtered_results {
-            citations.push(Citation {
+        // 保留低相似度结果：是否足以回答由可回答性门控按 similarity_threshold 判断，
+        // 证据不足时据此列出最接近的文档
+        let citations = search_results
+            .into_iter()
+            .map(|(score, chunk)| Citation {
                 document_id: chunk.document_id.clone(),
                 chunk_id: chunk.id.clone(),
                 page: chunk.page,
-                score: *score,
-                snippet: if chunk.text.len() > 240 {
-                    chunk.text.chars().take(240).collect::<String>() + "..."
-                } else {
-                    chunk.text.clone()
-                },
-            });
-            contexts.push(chunk.text.clone());
-        }
-
-        // 格式化上下文并生成回答
-        let formatted_context = self.base.format_context(&citations);
-        let answer = self
-            .base
-            .generate_answer(&formatted_context, &req.query)
-            .await?;
+                score,
+                snippet: crate::context::display_snippet(&chunk.text),
+                header: chunk.header.clone(),
+                text: Some(chunk.text.clone()),
+            })
+            .collect();
 
-        let latency_ms = start_time.elapsed().as_millis() as i64;
+        Ok(citations)
+    }
 
-        tracing::info!(
-            query = %req.query,
-            results_count = filtered_results.len(),
-            latency_ms = latency_ms,
-            "Memory RAG query completed"
-        );
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.base
+            .answer_with_citations(req, citations, "memory")
+            .await
+    }
 
-        Ok(QueryResponse {
-            answer,
-            citations,
-            contexts,
-            mode: req.mode.unwrap_or_else(|| "memory".to_string()),
-            latency_ms,
-        })
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.base.stream_with_citations(req, citations).await
     }
 
     #[instrument(skip(self, text))]
@@ -309,7 +360,8 @@ impl RagEngine for MemoryRagEngine {
         // 分块处理文本并生成统一结构
         let chunk_records = self
             .base
-            .chunk_document(document_id, text, page, meta.clone());
+            .prepare_document(document_id, text, page, meta.clone())
+            .await;
 
         if chunk_records.is_empty() {
             tracing::warn!("No chunks created for document {}", document_id);
@@ -317,7 +369,10 @@ impl RagEngine for MemoryRagEngine {
         }
 
         // 为所有块生成嵌入
-        let embed_inputs: Vec<String> = chunk_records.iter().map(|c| c.text.clone()).collect();
+        let embed_inputs: Vec<String> = chunk_records
+            .iter()
+            .map(|c| c.embedding_text().to_string())
+            .collect();
         let embeddings = self
             .base
             .embed_model
@@ -341,7 +396,11 @@ impl RagEngine for MemoryRagEngine {
                 source,
                 created_at,
                 custom_fields,
+                parent_id,
+                position,
+                header,
                 text,
+                embed_text: _,
             } = chunk;
 
             let created_at_dt = chrono::DateTime::<chrono::Utc>::from_timestamp(created_at, 0)
@@ -363,6 +422,9 @@ impl RagEngine for MemoryRagEngine {
                 page,
                 meta: Some(meta),
                 created_at: created_at_dt,
+                parent_id,
+                position,
+                header,
             };
             new_chunks.push(memory_chunk);
         }
@@ -381,3 +443,156 @@ impl RagEngine for MemoryRagEngine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contextual::ContextualHeaderConfig;
+    use crate::parent::ParentChunkConfig;
+
+    struct StubChatModel;
+
+    #[async_trait]
+    impl ChatModel for StubChatModel {
+        async fn complete(&self, _req: &kb_llm::ChatRequest) -> Result<kb_llm::ChatResponse> {
+            Ok(kb_llm::ChatResponse::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hashing_embedder_ranks_relevant_document_first() {
+        let config = RagEngineConfig {
+            similarity_threshold: 0.0,
+            ..Default::default()
+        };
+        let engine = MemoryRagEngine::from_models(
+            Arc::new(StubChatModel),
+            Arc::new(HashingEmbedModel::default()),
+            Some(config),
+        );
+        engine
+            .add_document_text("rust", "Rust ownership and borrowing rules", None)
+            .await
+            .unwrap();
+        engine
+            .add_document_text("cooking", "How to bake sourdough bread at home", None)
+            .await
+            .unwrap();
+
+        let req = QueryRequest {
+            query: "borrowing rules in Rust".to_string(),
+            top_k: Some(2),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        assert_eq!(citations[0].document_id, "rust");
+        assert!(citations[0].score > citations[1].score);
+    }
+
+    #[tokio::test]
+    async fn test_child_hits_expand_to_parent_window() {
+        let config = RagEngineConfig {
+            chunk_size: 60,
+            parent: ParentChunkConfig {
+                enabled: true,
+                child_chunk_size: 20,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = MemoryRagEngine::from_models(
+            Arc::new(StubChatModel),
+            Arc::new(HashingEmbedModel::default()),
+            Some(config),
+        );
+        // 父块 12 个词、子块 4 个词：policy#0 含 3 个子块，policy#1 含 2 个子块
+        let text = "refunds accepted within thirty days after original delivery \
+                    shipping takes five days \
+                    warranty covers manufacturing defects excluding accidental water damage";
+        engine
+            .add_document_text("policy", text, None)
+            .await
+            .unwrap();
+        assert_eq!(engine.document_count().await, 5);
+
+        let req = QueryRequest {
+            query: "warranty manufacturing defects".to_string(),
+            top_k: Some(1),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        assert!(citations[0].chunk_id.starts_with("policy#1-"));
+        assert!(citations[0].chunk_id.ends_with(".0"));
+        assert_eq!(
+            citations[0].snippet,
+            "warranty covers manufacturing defects excluding accidental water damage"
+        );
+
+        let req = QueryRequest {
+            parent_window: Some(false),
+            ..req
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        assert_eq!(
+            citations[0].snippet,
+            "warranty covers manufacturing defects"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_contextual_header_is_embedded_but_not_displayed() {
+        let config = RagEngineConfig {
+            headers: ContextualHeaderConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = MemoryRagEngine::from_models(
+            Arc::new(StubChatModel),
+            Arc::new(HashingEmbedModel::default()),
+            Some(config),
+        );
+        engine
+            .add_document_text("policy", "# Refund Policy\nthe limit is 30 days", None)
+            .await
+            .unwrap();
+
+        let req = QueryRequest {
+            query: "refund limit".to_string(),
+            ..Default::default()
+        };
+        let citation = engine.retrieve(&req).await.unwrap().remove(0);
+        assert_eq!(citation.snippet, "the limit is 30 days");
+        assert_eq!(
+            citation.header.as_deref(),
+            Some("Document: policy\nSection: Refund Policy")
+        );
+        let context = crate::context::format_citation(0, &citation, &citation.snippet);
+        assert!(context.ends_with("Section: Refund Policy\nthe limit is 30 days"));
+    }
+
+    #[tokio::test]
+    async fn test_context_uses_full_chunk_text_behind_display_snippet() {
+        let engine = MemoryRagEngine::from_models(
+            Arc::new(StubChatModel),
+            Arc::new(HashingEmbedModel::default()),
+            None,
+        );
+        let text = "退款规则".repeat(80);
+        engine
+            .add_document_text("policy", &text, None)
+            .await
+            .unwrap();
+
+        let req = QueryRequest {
+            query: "退款规则".to_string(),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        assert_eq!(citations[0].snippet.chars().count(), 243);
+        assert!(citations[0].snippet.ends_with("..."));
+        let assembled = engine.base.assemble_context(&req.query, &citations);
+        assert!(assembled.text.ends_with(&text));
+    }
+}
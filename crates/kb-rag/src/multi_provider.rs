@@ -2,9 +2,9 @@ use crate::engine::{RagEngine, RagEngineConfig, RagMeta};
 use crate::memory::MemoryRagEngine;
 use crate::qdrantss::QdrantRagEngine;
 use async_trait::async_trait;
-use kb_core::{QueryRequest, QueryResponse};
+use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::{KbError, Result};
-use kb_llm::{ChatModel, EmbedModel};
+use kb_llm::{ChatModel, ChatStream, EmbedModel};
 use std::sync::Arc;
 use tracing::{info, instrument};
 
@@ -181,6 +181,9 @@ impl MultiProviderRagEngine {
                     rerank: Some(false),
                     stream: Some(false),
                     include_raw_matches: Some(false),
+                    history: None,
+                    expansion: None,
+                    parent_window: None,
                 };
 
                 match self.engine.query(test_query).await {
@@ -226,6 +229,26 @@ impl RagEngine for MultiProviderRagEngine {
         self.engine.query(req).await
     }
 
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        self.engine.retrieve(req).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.engine.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.engine.generate_stream(req, citations).await
+    }
+
     #[instrument(skip(self, text))]
     async fn add_document_text_with_meta(
         &self,
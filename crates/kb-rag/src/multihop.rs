@@ -0,0 +1,509 @@
+//! 多跳查询：把问题拆分为子问题分别检索，按子问题分组组装上下文后合成回答
+
+use async_trait::async_trait;
+use futures::future::try_join_all;
+use kb_core::{Citation, QueryRequest, QueryResponse, SubQuestion};
+use kb_error::{KbError, Result};
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream, RenderedPrompt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{debug, instrument, warn};
+
+use crate::answerability::GatedRagEngine;
+use crate::context::{self, ContextConfig};
+use crate::engine::{
+    map_chat_error, EngineStats, HealthStatus, RagEngine, RagMeta, NO_RESULT_ANSWER,
+};
+
+/// 请求中 `mode` 取该值时走多跳查询
+pub const MULTIHOP_MODE: &str = "multihop";
+
+/// 多跳查询配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MultiHopConfig {
+    /// 子问题数量上限
+    pub max_sub_questions: usize,
+    /// 每个子问题检索的引用数
+    pub top_k_per_question: u16,
+    /// 每个子问题可占用的上下文 token 数
+    pub tokens_per_question: usize,
+}
+
+impl Default for MultiHopConfig {
+    fn default() -> Self {
+        Self {
+            max_sub_questions: 4,
+            top_k_per_question: 4,
+            tokens_per_question: 800,
+        }
+    }
+}
+
+const PLANNER_SYSTEM_PROMPT: &str = "Decompose the user's question into the smallest set of self-contained sub-questions that each need a separate search in a knowledge base, for example one per product or entity being compared. Use at most {n} sub-questions. If a single search is enough, return the question itself as the only sub-question. Keep the language of the question.";
+
+const SYNTHESIS_INSTRUCTION: &str = "The context is grouped by sub-question. Answer each sub-question from its own sources, then combine the findings into one answer to the original question.";
+
+/// 拆分计划的结构化输出
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Plan {
+    sub_questions: Vec<String>,
+}
+
+/// 子问题规划器
+pub struct QueryPlanner {
+    chat: Arc<dyn ChatModel>,
+    max_sub_questions: usize,
+}
+
+impl QueryPlanner {
+    pub fn new(chat: Arc<dyn ChatModel>, max_sub_questions: usize) -> Self {
+        Self {
+            chat,
+            max_sub_questions: max_sub_questions.max(1),
+        }
+    }
+
+    /// 拆分子问题；规划失败或结果为空时以原问题作为唯一子问题
+    #[instrument(skip(self))]
+    pub async fn plan(&self, query: &str) -> Vec<String> {
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(
+                PLANNER_SYSTEM_PROMPT.replace("{n}", &self.max_sub_questions.to_string()),
+            ),
+            ChatMessage::user(query),
+        ])
+        .with_temperature(0.0);
+        let plan = match kb_llm::chat_json::<Plan>(self.chat.as_ref(), req).await {
+            Ok(plan) => plan.sub_questions,
+            Err(e) => {
+                warn!(error = %e, "子问题拆分失败，按单个问题检索");
+                Vec::new()
+            }
+        };
+        let mut seen = HashSet::new();
+        let questions: Vec<String> = plan
+            .into_iter()
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty() && seen.insert(q.to_lowercase()))
+            .take(self.max_sub_questions)
+            .collect();
+        if questions.is_empty() {
+            vec![query.to_string()]
+        } else {
+            questions
+        }
+    }
+}
+
+/// 多跳查询包装引擎：`mode` 为 `multihop` 的请求拆分子问题检索并合成回答，其余请求原样转发
+///
+/// 合成回答依赖按子问题分组的上下文，多跳请求不支持流式生成。
+pub struct MultiHopRagEngine {
+    inner: Arc<dyn RagEngine>,
+    chat: Arc<dyn ChatModel>,
+    planner: QueryPlanner,
+    config: MultiHopConfig,
+    gate: Option<Arc<GatedRagEngine>>,
+}
+
+/// 各子问题的检索结果合并后的引用
+struct Retrieved {
+    questions: Vec<String>,
+    citations: Vec<Citation>,
+    /// 各子问题的引用在 `citations` 中的下标
+    groups: Vec<Vec<usize>>,
+}
+
+impl MultiHopRagEngine {
+    pub fn new(
+        inner: Arc<dyn RagEngine>,
+        chat: Arc<dyn ChatModel>,
+        config: MultiHopConfig,
+    ) -> Self {
+        Self {
+            inner,
+            planner: QueryPlanner::new(chat.clone(), config.max_sub_questions),
+            chat,
+            config,
+            gate: None,
+        }
+    }
+
+    /// 设置可回答性门控：合并后的子问题引用相关度不足时不合成回答
+    pub fn with_gate(mut self, gate: Arc<GatedRagEngine>) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    fn is_multihop(req: &QueryRequest) -> bool {
+        req.mode.as_deref() == Some(MULTIHOP_MODE)
+    }
+
+    /// 逐个子问题检索，同一分块只保留一份，编号按首次出现的顺序
+    #[instrument(skip(self, req))]
+    async fn retrieve_sub_questions(&self, req: &QueryRequest) -> Result<Retrieved> {
+        let questions = self.planner.plan(&req.query).await;
+        debug!(sub_questions = ?questions, "多跳检索");
+
+        let results = try_join_all(questions.iter().map(|q| {
+            let mut sub = req.clone();
+            sub.query = q.clone();
+            sub.mode = None;
+            sub.top_k = Some(self.config.top_k_per_question);
+            async move { self.inner.retrieve(&sub).await }
+        }))
+        .await?;
+
+        let mut citations = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut groups = Vec::with_capacity(results.len());
+        for result in results {
+            let mut group = Vec::with_capacity(result.len());
+            for citation in result {
+                let key = format!("{}#{}", citation.document_id, citation.chunk_id);
+                let index = *positions.entry(key).or_insert_with(|| {
+                    citations.push(citation);
+                    citations.len() - 1
+                });
+                if !group.contains(&index) {
+                    group.push(index);
+                }
+            }
+            groups.push(group);
+        }
+        Ok(Retrieved {
+            questions,
+            citations,
+            groups,
+        })
+    }
+
+    /// 按子问题分组组装上下文，每组单独计算 token 预算；返回上下文文本与装入的引用下标及片段
+    fn assemble(&self, retrieved: &Retrieved) -> (String, Vec<(usize, String)>) {
+        let config = ContextConfig::default();
+        let mut sections = Vec::new();
+        let mut included: Vec<(usize, String)> = Vec::new();
+        for (question, group) in retrieved.questions.iter().zip(&retrieved.groups) {
+            let group_citations: Vec<Citation> = group
+                .iter()
+                .map(|&i| retrieved.citations[i].clone())
+                .collect();
+            let assembled =
+                context::assemble(&config, &group_citations, self.config.tokens_per_question);
+            let mut lines = vec![format!("Sub-question {}: {}", sections.len() + 1, question)];
+            for part in assembled.parts {
+                let index = group[part.index];
+                // 多个子问题命中同一分块时只在首次出现处展开
+                if !included.iter().any(|(i, _)| *i == index) {
+                    lines.push(context::format_citation(
+                        index,
+                        &retrieved.citations[index],
+                        &part.snippet,
+                    ));
+                    included.push((index, part.snippet));
+                } else {
+                    lines.push(format!("[{}] (see above)", index + 1));
+                }
+            }
+            sections.push(lines.join("\n\n"));
+        }
+        (sections.join("\n\n"), included)
+    }
+
+    async fn synthesize(&self, context: &str, query: &str) -> Result<String> {
+        let RenderedPrompt { system, user } = kb_llm::current_prompt().render(context, query);
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(format!(
+                "{}\n\n{}",
+                system.trim_end(),
+                SYNTHESIS_INSTRUCTION
+            )),
+            ChatMessage::user(user),
+        ])
+        .with_temperature(0.2);
+        self.chat
+            .complete(&req)
+            .await
+            .map(|resp| resp.content)
+            .map_err(map_chat_error)
+    }
+}
+
+#[async_trait]
+impl RagEngine for MultiHopRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        if !Self::is_multihop(&req) {
+            return self.inner.query(req).await;
+        }
+        let start_time = std::time::Instant::now();
+        let retrieved = self.retrieve_sub_questions(&req).await?;
+        let sub_questions = retrieved
+            .questions
+            .iter()
+            .zip(&retrieved.groups)
+            .map(|(question, group)| SubQuestion {
+                question: question.clone(),
+                citations: group.clone(),
+            })
+            .collect();
+
+        if let Some(evidence) = self
+            .gate
+            .as_ref()
+            .and_then(|gate| gate.assess(&req, &retrieved.citations))
+        {
+            let mut response = GatedRagEngine::insufficient_response(&req, evidence);
+            response.sub_questions = sub_questions;
+            response.latency_ms = start_time.elapsed().as_millis() as i64;
+            return Ok(response);
+        }
+
+        if retrieved.citations.is_empty() {
+            return Ok(QueryResponse {
+                answer: NO_RESULT_ANSWER.to_string(),
+                mode: MULTIHOP_MODE.to_string(),
+                sub_questions,
+                latency_ms: start_time.elapsed().as_millis() as i64,
+                ..Default::default()
+            });
+        }
+
+        let (context, included) = self.assemble(&retrieved);
+        let answer = self.synthesize(&context, &req.query).await?;
+        let context_chunk_ids = included
+            .iter()
+            .map(|(i, _)| retrieved.citations[*i].chunk_id.clone())
+            .collect();
+        Ok(QueryResponse {
+            answer,
+            citations: retrieved.citations,
+            contexts: included.into_iter().map(|(_, snippet)| snippet).collect(),
+            context_chunk_ids,
+            mode: MULTIHOP_MODE.to_string(),
+            latency_ms: start_time.elapsed().as_millis() as i64,
+            sub_questions,
+            ..Default::default()
+        })
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        if !Self::is_multihop(req) {
+            return self.inner.retrieve(req).await;
+        }
+        Ok(self.retrieve_sub_questions(req).await?.citations)
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.inner.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        // 合并后的引用已丢失子问题分组，交给下游只会得到未经合成的普通回答
+        if Self::is_multihop(req) {
+            return Err(KbError::InvalidRequest {
+                reason: "多跳查询不支持流式生成，请使用非流式查询".to_string(),
+            });
+        }
+        self.inner.generate_stream(req, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::ChatResponse;
+    use std::sync::Mutex;
+
+    /// 规划请求返回固定的子问题，回答请求记录上下文
+    struct ScriptedChat {
+        plan: &'static str,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    #[async_trait]
+    impl ChatModel for ScriptedChat {
+        async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+            let content = if req.response_format.is_some() {
+                self.plan.to_string()
+            } else {
+                self.requests.lock().unwrap().push(req.clone());
+                "A 支持七天退款 [1]，B 支持三十天退款 [3]。".to_string()
+            };
+            Ok(ChatResponse {
+                content,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// 按问题中的产品名返回引用，两个产品共享一条通用条款
+    struct ProductEngine;
+
+    #[async_trait]
+    impl RagEngine for ProductEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            Ok(QueryResponse {
+                answer: format!("single:{}", req.query),
+                ..Default::default()
+            })
+        }
+
+        async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+            assert_eq!(req.top_k, Some(4));
+            let citation = |id: &str, snippet: &str| Citation {
+                document_id: id.to_string(),
+                chunk_id: format!("{}#0", id),
+                page: None,
+                score: 0.9,
+                snippet: snippet.to_string(),
+                header: None,
+                text: None,
+            };
+            let product = if req.query.contains('A') { "a" } else { "b" };
+            Ok(vec![
+                citation(product, &format!("product {} refund terms", product)),
+                citation("common", "refunds require a receipt"),
+            ])
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn engine(plan: &'static str) -> (Arc<ScriptedChat>, MultiHopRagEngine) {
+        let chat = Arc::new(ScriptedChat {
+            plan,
+            requests: Mutex::new(Vec::new()),
+        });
+        let engine = MultiHopRagEngine::new(
+            Arc::new(ProductEngine),
+            chat.clone(),
+            MultiHopConfig::default(),
+        );
+        (chat, engine)
+    }
+
+    #[tokio::test]
+    async fn test_groups_citations_by_sub_question() {
+        let (chat, engine) = engine(
+            r#"{"sub_questions": ["refund policy of product A", "refund policy of product B"]}"#,
+        );
+        let req = QueryRequest {
+            query: "Compare the refund policy of product A and product B".to_string(),
+            mode: Some(MULTIHOP_MODE.to_string()),
+            ..Default::default()
+        };
+        let resp = engine.query(req).await.unwrap();
+        assert_eq!(resp.mode, MULTIHOP_MODE);
+        let ids: Vec<&str> = resp
+            .citations
+            .iter()
+            .map(|c| c.document_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["a", "common", "b"]);
+        assert_eq!(resp.sub_questions.len(), 2);
+        assert_eq!(resp.sub_questions[0].citations, vec![0, 1]);
+        assert_eq!(resp.sub_questions[1].citations, vec![2, 1]);
+        assert_eq!(resp.context_chunk_ids, vec!["a#0", "common#0", "b#0"]);
+
+        let requests = chat.requests.lock().unwrap();
+        let user = &requests[0].messages[1].content;
+        assert!(user.contains("Sub-question 1: refund policy of product A"));
+        assert!(user.contains("Sub-question 2: refund policy of product B\n\n[3] (doc=b"));
+        assert!(user.contains("[2] (see above)"));
+        assert!(requests[0].messages[0]
+            .content
+            .ends_with(SYNTHESIS_INSTRUCTION));
+    }
+
+    #[tokio::test]
+    async fn test_gate_skips_synthesis_on_weak_evidence() {
+        let (chat, engine) = engine(r#"{"sub_questions": ["product A", "product B"]}"#);
+        let gate = crate::answerability::AnswerabilityGate::new(
+            crate::answerability::AnswerabilityConfig {
+                min_similarity: Some(0.95),
+                ..Default::default()
+            },
+            &crate::engine::RagEngineConfig::default(),
+        );
+        let engine = engine.with_gate(Arc::new(GatedRagEngine::new(Arc::new(ProductEngine), gate)));
+        let req = QueryRequest {
+            query: "Compare product A and product B".to_string(),
+            mode: Some(MULTIHOP_MODE.to_string()),
+            ..Default::default()
+        };
+        let resp = engine.query(req).await.unwrap();
+        let evidence = resp.insufficient_evidence.unwrap();
+        assert_eq!(evidence.reason, "low_relevance");
+        assert_eq!(resp.sub_questions.len(), 2);
+        assert!(chat.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_plan_falls_back_to_single_question() {
+        let (_, engine) = engine("not json");
+        assert_eq!(engine.planner.plan("退款政策").await, vec!["退款政策"]);
+
+        // 其他模式原样转发
+        let req = QueryRequest {
+            query: "退款政策".to_string(),
+            mode: Some("rag".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(engine.query(req).await.unwrap().answer, "single:退款政策");
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejects_multihop() {
+        let (_, engine) = engine("not json");
+        let req = QueryRequest {
+            query: "退款政策".to_string(),
+            mode: Some(MULTIHOP_MODE.to_string()),
+            ..Default::default()
+        };
+        let err = engine
+            .generate_stream(&req, Vec::new())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, KbError::InvalidRequest { .. }));
+    }
+}
@@ -0,0 +1,267 @@
+//! 父子分块（small-to-big）：以小块检索保证匹配精度，回答时把命中的子块扩展为所在父块的窗口
+
+use kb_core::Citation;
+use kb_llm::tokens::estimate_tokens;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::engine::RagDocumentChunk;
+
+/// 父子分块配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParentChunkConfig {
+    /// 是否按父子结构索引：按 `chunk_size` 切出父块，再把父块切成子块分别嵌入
+    pub enabled: bool,
+    /// 子块大小（字符）
+    pub child_chunk_size: usize,
+    /// 命中子块向两侧扩展的子块数，为空时扩展到整个父块
+    pub neighbours: Option<usize>,
+    /// 请求未指定 `parent_window` 时是否扩展
+    pub expand_by_default: bool,
+}
+
+impl Default for ParentChunkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            child_chunk_size: 200,
+            neighbours: None,
+            expand_by_default: true,
+        }
+    }
+}
+
+impl ParentChunkConfig {
+    /// 单个父块最多包含的子块数，用于估算批量读取子块的条数
+    pub fn children_per_parent(&self, chunk_size: usize) -> usize {
+        chunk_size.div_ceil(self.child_chunk_size.max(1)) + 1
+    }
+}
+
+/// 同一父块中连续的子块区间
+struct Window<'a> {
+    parent_id: &'a str,
+    start: usize,
+    end: usize,
+    /// 区间内得分最高的命中
+    best: &'a RagDocumentChunk,
+    score: f32,
+}
+
+/// 把命中的子块扩展为父块窗口：同一父块中重叠的窗口合并为一条引用，
+/// 按得分从高到低装入，超出 `budget` 的窗口以命中子块为中心收窄；
+/// 连命中子块本身都放不下时不再装入（至少保留一条引用）
+///
+/// `children` 为命中子块所在父块的全部子块；未记录父块的命中原样返回。
+pub fn expand_to_windows(
+    config: &ParentChunkConfig,
+    hits: &[(f32, RagDocumentChunk)],
+    children: &[RagDocumentChunk],
+    budget: usize,
+) -> Vec<Citation> {
+    // (父块, 序号) -> 子块；父块 -> 序号范围
+    let mut by_position: HashMap<(&str, usize), &RagDocumentChunk> = HashMap::new();
+    let mut parent_range: HashMap<&str, (usize, usize)> = HashMap::new();
+    for child in children {
+        let (Some(parent_id), Some(position)) = (&child.parent_id, child.position) else {
+            continue;
+        };
+        by_position.insert((parent_id.as_str(), position), child);
+        let range = parent_range
+            .entry(parent_id.as_str())
+            .or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    }
+
+    let mut citations = Vec::new();
+    let mut windows: Vec<Window> = Vec::new();
+    for (score, hit) in hits {
+        let range = match (&hit.parent_id, hit.position) {
+            (Some(parent_id), Some(position)) => {
+                parent_range
+                    .get(parent_id.as_str())
+                    .map(|&(lo, hi)| match config.neighbours {
+                        Some(n) => (
+                            position.saturating_sub(n).max(lo),
+                            (position + n).min(hi).max(position),
+                        ),
+                        None => (lo.min(position), hi.max(position)),
+                    })
+            }
+            _ => None,
+        };
+        match range {
+            Some((start, end)) => windows.push(Window {
+                parent_id: hit.parent_id.as_deref().unwrap_or_default(),
+                start,
+                end,
+                best: hit,
+                score: *score,
+            }),
+            None => citations.push(citation(hit, *score, hit.text.clone())),
+        }
+    }
+
+    // 按父块与起点排序后合并重叠的窗口
+    windows.sort_by(|a, b| (a.parent_id, a.start).cmp(&(b.parent_id, b.start)));
+    let mut merged: Vec<Window> = Vec::new();
+    for w in windows {
+        match merged.last_mut() {
+            Some(last) if last.parent_id == w.parent_id && w.start <= last.end => {
+                last.end = last.end.max(w.end);
+                if w.score > last.score {
+                    last.score = w.score;
+                    last.best = w.best;
+                }
+            }
+            _ => merged.push(w),
+        }
+    }
+    merged.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut used: usize = citations.iter().map(|c| estimate_tokens(&c.snippet)).sum();
+    for w in merged {
+        let center = w.best.position.unwrap_or(w.start);
+        let text_of = |start: usize, end: usize| {
+            (start..=end)
+                .filter_map(|p| by_position.get(&(w.parent_id, p)))
+                .map(|c| c.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        // 整个窗口放不下时逐步缩小半径，最小为命中子块本身
+        let radius = (center - w.start).max(w.end - center);
+        let fitted = (0..=radius).rev().find_map(|r| {
+            let text = text_of(
+                center.saturating_sub(r).max(w.start),
+                (center + r).min(w.end),
+            );
+            (used + estimate_tokens(&text) <= budget).then_some(text)
+        });
+        let text = match fitted {
+            Some(text) if !text.is_empty() => text,
+            Some(_) => w.best.text.clone(),
+            None if citations.is_empty() => w.best.text.clone(),
+            // 预算已用尽，得分更低的窗口不再装入
+            None => break,
+        };
+        used += estimate_tokens(&text);
+        citations.push(citation(w.best, w.score, text));
+    }
+
+    citations.sort_by(|a, b| b.score.total_cmp(&a.score));
+    citations
+}
+
+fn citation(chunk: &RagDocumentChunk, score: f32, snippet: String) -> Citation {
+    Citation {
+        document_id: chunk.document_id.clone(),
+        chunk_id: chunk.chunk_id.clone(),
+        page: chunk.page,
+        score,
+        snippet,
+        header: chunk.header.clone(),
+        text: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 文档 d 的两个父块：d#0 含子块 c0..c3，d#1 含子块 c4、c5
+    fn children() -> Vec<RagDocumentChunk> {
+        (0..6)
+            .map(|i| {
+                let (parent, position) = if i < 4 { (0, i) } else { (1, i - 4) };
+                RagDocumentChunk::from_text(
+                    "d",
+                    format!("d#{}.{}", parent, position),
+                    format!("c{}", i),
+                    None,
+                    None,
+                )
+                .with_parent(format!("d#{}", parent), position)
+            })
+            .collect()
+    }
+
+    fn hit(children: &[RagDocumentChunk], i: usize, score: f32) -> (f32, RagDocumentChunk) {
+        (score, children[i].clone())
+    }
+
+    #[test]
+    fn test_hits_in_same_parent_share_one_window() {
+        let children = children();
+        let hits = vec![
+            hit(&children, 1, 0.9),
+            hit(&children, 3, 0.8),
+            hit(&children, 5, 0.6),
+        ];
+        let citations = expand_to_windows(&ParentChunkConfig::default(), &hits, &children, 1000);
+        let snippets: Vec<&str> = citations.iter().map(|c| c.snippet.as_str()).collect();
+        assert_eq!(snippets, vec!["c0 c1 c2 c3", "c4 c5"]);
+        assert_eq!(citations[0].chunk_id, "d#0.1");
+        assert_eq!(citations[1].chunk_id, "d#1.1");
+        assert_eq!(citations[0].score, 0.9);
+    }
+
+    #[test]
+    fn test_neighbour_windows_merge_and_shrink_to_budget() {
+        let children = children();
+        let config = ParentChunkConfig {
+            neighbours: Some(1),
+            ..Default::default()
+        };
+        // 重叠的窗口 [0,2] 与 [2,3] 合并；窗口不跨越父块
+        let hits = vec![hit(&children, 1, 0.9), hit(&children, 3, 0.7)];
+        let citations = expand_to_windows(&config, &hits, &children, 1000);
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0].snippet, "c0 c1 c2 c3");
+
+        // 预算只够命中子块本身
+        let hits = vec![hit(&children, 2, 0.9)];
+        let citations = expand_to_windows(&config, &hits, &children, 1);
+        assert_eq!(citations[0].snippet, "c2");
+    }
+
+    #[test]
+    fn test_stops_adding_windows_once_budget_is_spent() {
+        // 三个父块 p0..p2，各含两个较长的子块
+        let children: Vec<RagDocumentChunk> = (0..6)
+            .map(|i| {
+                RagDocumentChunk::from_text(
+                    "d",
+                    format!("d#{}.{}", i / 2, i % 2),
+                    format!("child {} ", i).repeat(20),
+                    None,
+                    None,
+                )
+                .with_parent(format!("d#{}", i / 2), i % 2)
+            })
+            .collect();
+        let hits = vec![
+            hit(&children, 0, 0.9),
+            hit(&children, 2, 0.8),
+            hit(&children, 4, 0.7),
+        ];
+        let window = format!("{} {}", children[0].text, children[1].text);
+        let budget = estimate_tokens(&window) + estimate_tokens(&children[2].text);
+        let citations = expand_to_windows(&ParentChunkConfig::default(), &hits, &children, budget);
+
+        // 第一个父块整窗装入，第二个收窄为命中子块，第三个不再装入
+        let ids: Vec<&str> = citations.iter().map(|c| c.chunk_id.as_str()).collect();
+        assert_eq!(ids, vec!["d#0.0", "d#1.0"]);
+        assert_eq!(citations[0].snippet, window);
+        assert_eq!(citations[1].snippet, children[2].text);
+        let used: usize = citations.iter().map(|c| estimate_tokens(&c.snippet)).sum();
+        assert!(used <= budget);
+
+        // 预算不足以装入任何窗口时仍保留得分最高的命中
+        let citations = expand_to_windows(&ParentChunkConfig::default(), &hits, &children, 1);
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0].snippet, children[0].text);
+    }
+}
@@ -152,20 +152,14 @@ impl RagEngine for QdrantRagEngine {
         let mut contexts = Vec::new();
 
         for (score, _point_id, chunk) in &results {
-            let snippet = if chunk.text.len() > 240 {
-                let mut snippet = chunk.text.chars().take(240).collect::<String>();
-                snippet.push_str("...");
-                snippet
-            } else {
-                chunk.text.clone()
-            };
-
             citations.push(Citation {
                 document_id: chunk.document_id.clone(),
                 chunk_id: chunk.chunk_id.clone(),
                 page: chunk.page,
                 score: *score as f32,
-                snippet,
+                snippet: crate::context::display_snippet(&chunk.text),
+                header: None,
+                text: Some(chunk.text.clone()),
             });
 
             contexts.push(chunk.text.clone());
@@ -179,6 +173,7 @@ impl RagEngine for QdrantRagEngine {
             contexts,
             mode: req.mode.unwrap_or_else(|| "qdrant".to_string()),
             latency_ms,
+            ..Default::default()
         })
     }
 
@@ -1,8 +1,9 @@
 use crate::engine::{BaseRagEngine, RagDocumentChunk, RagEngine, RagEngineConfig, RagMeta};
+use crate::parent::expand_to_windows;
 use async_trait::async_trait;
 use kb_core::{Citation, QueryRequest, QueryResponse};
 use kb_error::{KbError, Result};
-use kb_llm::{ChatModel, EmbedModel};
+use kb_llm::{ChatModel, ChatStream, EmbedModel};
 use qdrant_client::{
     qdrant::{
         vectors_config::Config, with_payload_selector::SelectorOptions, Condition,
@@ -45,13 +46,21 @@ impl QdrantRagEngine {
 
         let config = config.unwrap_or_default();
 
-        // 获取嵌入模型的向量维度
-        let vector_size = embed_model
-            .embed(&["test".to_string()])
-            .await?
-            .first()
-            .map(|v| v.len())
-            .unwrap_or(1536);
+        // 优先使用注册表给出的向量维度，未知时探测向量模型
+        let vector_size = match config.embedding_dimension {
+            Some(dimension) => dimension,
+            None => embed_model
+                .embed(&["test".to_string()])
+                .await?
+                .first()
+                .map(|v| v.len())
+                .filter(|d| *d > 0)
+                .ok_or_else(|| KbError::Configuration {
+                    key: "embedding_dimension".to_string(),
+                    reason: "embedding model returned no vector; set the dimension in models"
+                        .to_string(),
+                })?,
+        };
         info!("Using embedding dimension: {} ", vector_size);
 
         let engine = Self {
@@ -74,6 +83,8 @@ impl QdrantRagEngine {
             Ok(exists) => {
                 if !exists {
                     self.create_collection().await?;
+                } else {
+                    self.check_collection_dimension().await?;
                 }
             }
             Err(e) => {
@@ -85,6 +96,37 @@ impl QdrantRagEngine {
         Ok(())
     }
 
+    /// 已有 collection 的向量维度须与注册表或探测得到的维度一致，否则写入与检索都会失败
+    async fn check_collection_dimension(&self) -> Result<()> {
+        let info = match self.client.collection_info(&self.collection_name).await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Failed to read collection info: {}", e);
+                return Ok(());
+            }
+        };
+        let size = info
+            .result
+            .and_then(|i| i.config)
+            .and_then(|c| c.params)
+            .and_then(|p| p.vectors_config)
+            .and_then(|v| v.config)
+            .and_then(|c| match c {
+                Config::Params(params) => Some(params.size as usize),
+                _ => None,
+            });
+        match size {
+            Some(size) if size != self.vector_size => Err(KbError::Configuration {
+                key: "embedding_dimension".to_string(),
+                reason: format!(
+                    "collection {} has dimension {}, embedding model produces {}",
+                    self.collection_name, size, self.vector_size
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     /// 创建collection
     async fn create_collection(&self) -> Result<()> {
         let vectors_config = VectorsConfig {
@@ -154,7 +196,8 @@ impl QdrantRagEngine {
             collection_name: self.collection_name.clone(),
             vector: query_embedding,
             limit: top_k as u64,
-            score_threshold: Some(self.base.config.similarity_threshold),
+            // 不按相似度截断，低分结果由可回答性门控判断并作为推荐文档
+            score_threshold: None,
             with_payload: Some(WithPayloadSelector {
                 selector_options: Some(SelectorOptions::Enable(true)),
             }),
@@ -177,57 +220,7 @@ impl QdrantRagEngine {
 
         let mut results = Vec::new();
         for scored_point in search_result.result {
-            let payload = scored_point.payload;
-
-            // 转换payload为JSON并反序列化为KnowledgeChunk
-            let mut json_payload = serde_json::Map::new();
-            for (k, v) in payload {
-                if let Some(kind) = v.kind {
-                    let json_value = match kind {
-                        qdrant_client::qdrant::value::Kind::StringValue(s) => {
-                            serde_json::Value::String(s)
-                        }
-                        qdrant_client::qdrant::value::Kind::IntegerValue(i) => {
-                            serde_json::Value::Number(serde_json::Number::from(i))
-                        }
-                        qdrant_client::qdrant::value::Kind::DoubleValue(f) => {
-                            if let Some(num) = serde_json::Number::from_f64(f) {
-                                serde_json::Value::Number(num)
-                            } else {
-                                serde_json::Value::String(f.to_string())
-                            }
-                        }
-                        qdrant_client::qdrant::value::Kind::BoolValue(b) => {
-                            serde_json::Value::Bool(b)
-                        }
-                        qdrant_client::qdrant::value::Kind::ListValue(list) => {
-                            let arr: Vec<serde_json::Value> = list
-                                .values
-                                .into_iter()
-                                .map(|item| {
-                                    if let Some(kind) = item.kind {
-                                        match kind {
-                                            qdrant_client::qdrant::value::Kind::StringValue(s) => {
-                                                serde_json::Value::String(s)
-                                            }
-                                            _ => serde_json::Value::String("".to_string()),
-                                        }
-                                    } else {
-                                        serde_json::Value::String("".to_string())
-                                    }
-                                })
-                                .collect();
-                            serde_json::Value::Array(arr)
-                        }
-                        _ => serde_json::Value::String("".to_string()),
-                    };
-                    json_payload.insert(k, json_value);
-                }
-            }
-
-            // 尝试反序列化为KnowledgeChunk
-            match serde_json::from_value::<KnowledgeChunk>(serde_json::Value::Object(json_payload))
-            {
+            match payload_to_chunk(scored_point.payload) {
                 Ok(chunk) => results.push((scored_point.score, chunk)),
                 Err(e) => {
                     warn!("Failed to deserialize chunk: {}. Skipping.", e);
@@ -239,6 +232,77 @@ impl QdrantRagEngine {
         Ok(results)
     }
 
+    /// 读取命中子块所在父块的全部子块
+    async fn children_of(&self, hits: &[(f32, KnowledgeChunk)]) -> Result<Vec<KnowledgeChunk>> {
+        let mut parent_ids: Vec<&str> = hits
+            .iter()
+            .filter_map(|(_, c)| c.parent_id.as_deref())
+            .collect();
+        parent_ids.sort_unstable();
+        parent_ids.dedup();
+        if parent_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let filter = Filter {
+            should: parent_ids
+                .iter()
+                .map(|parent_id| Condition {
+                    condition_one_of: Some(
+                        qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition {
+                            key: "parent_id".to_string(),
+                            r#match: Some(Match {
+                                match_value: Some(
+                                    qdrant_client::qdrant::r#match::MatchValue::Keyword(
+                                        parent_id.to_string(),
+                                    ),
+                                ),
+                            }),
+                            ..Default::default()
+                        }),
+                    ),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let limit = parent_ids.len()
+            * self
+                .base
+                .config
+                .parent
+                .children_per_parent(self.base.config.chunk_size);
+        let scroll_points = ScrollPoints {
+            collection_name: self.collection_name.clone(),
+            filter: Some(filter),
+            limit: Some(limit as u32),
+            with_payload: Some(WithPayloadSelector {
+                selector_options: Some(SelectorOptions::Enable(true)),
+            }),
+            ..Default::default()
+        };
+
+        let response =
+            self.client
+                .scroll(scroll_points)
+                .await
+                .map_err(|e| KbError::VectorStore {
+                    operation: "scroll".to_string(),
+                    message: format!("Failed to fetch child chunks: {}", e),
+                })?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .filter_map(|point| match payload_to_chunk(point.payload) {
+                Ok(chunk) => Some(chunk),
+                Err(e) => {
+                    warn!("Failed to deserialize chunk: {}. Skipping.", e);
+                    None
+                }
+            })
+            .collect())
+    }
+
     /// 构建Qdrant过滤器
     fn build_qdrant_filter(&self, filters: &serde_json::Value) -> Result<Filter> {
         let mut conditions = Vec::new();
@@ -426,18 +490,85 @@ impl QdrantRagEngine {
     }
 }
 
+/// 转换payload为JSON并反序列化为KnowledgeChunk
+fn payload_to_chunk(
+    payload: std::collections::HashMap<String, Value>,
+) -> serde_json::Result<KnowledgeChunk> {
+    let mut json_payload = serde_json::Map::new();
+    for (k, v) in payload {
+        if let Some(kind) = v.kind {
+            let json_value = match kind {
+                qdrant_client::qdrant::value::Kind::StringValue(s) => serde_json::Value::String(s),
+                qdrant_client::qdrant::value::Kind::IntegerValue(i) => {
+                    serde_json::Value::Number(serde_json::Number::from(i))
+                }
+                qdrant_client::qdrant::value::Kind::DoubleValue(f) => {
+                    if let Some(num) = serde_json::Number::from_f64(f) {
+                        serde_json::Value::Number(num)
+                    } else {
+                        serde_json::Value::String(f.to_string())
+                    }
+                }
+                qdrant_client::qdrant::value::Kind::BoolValue(b) => serde_json::Value::Bool(b),
+                qdrant_client::qdrant::value::Kind::ListValue(list) => {
+                    let arr: Vec<serde_json::Value> = list
+                        .values
+                        .into_iter()
+                        .map(|item| {
+                            if let Some(kind) = item.kind {
+                                match kind {
+                                    qdrant_client::qdrant::value::Kind::StringValue(s) => {
+                                        serde_json::Value::String(s)
+                                    }
+                                    _ => serde_json::Value::String("".to_string()),
+                                }
+                            } else {
+                                serde_json::Value::String("".to_string())
+                            }
+                        })
+                        .collect();
+                    serde_json::Value::Array(arr)
+                }
+                qdrant_client::qdrant::value::Kind::NullValue(_) => serde_json::Value::Null,
+                _ => serde_json::Value::String("".to_string()),
+            };
+            json_payload.insert(k, json_value);
+        }
+    }
+
+    serde_json::from_value::<KnowledgeChunk>(serde_json::Value::Object(json_payload))
+}
+
 #[async_trait]
 impl RagEngine for QdrantRagEngine {
     #[instrument(skip(self, req))]
     async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
         let start_time = std::time::Instant::now();
 
+        let citations = self.retrieve(&req).await?;
+        let results_count = citations.len();
+        let mut response = self.generate(&req, citations).await?;
+
+        let latency_ms = start_time.elapsed().as_millis() as i64;
+        response.latency_ms = latency_ms;
+
+        info!(
+            query = %req.query,
+            results_count = results_count,
+            latency_ms = latency_ms,
+            "Qdrant RAG query completed"
+        );
+
+        Ok(response)
+    }
+
+    #[instrument(skip(self, req))]
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
         // 使用 VectorSearchRequest 构建查询
         let top_k = req.top_k.unwrap_or(self.base.config.default_top_k);
         let vector_req = VectorSearchRequest::builder()
             .query(&req.query)
             .samples(top_k as u64)
-            .threshold(self.base.config.similarity_threshold as f64)
             .build()
             .map_err(|e| KbError::VectorStore {
                 operation: "build_search_request".to_string(),
@@ -449,58 +580,51 @@ impl RagEngine for QdrantRagEngine {
             .vector_search_with_request(&vector_req, req.filters.as_ref())
             .await?;
 
-        if search_results.is_empty() {
-            return Ok(QueryResponse {
-                answer: "抱歉，我在知识库中没有找到相关的信息来回答您的问题。".to_string(),
-                citations: vec![],
-                contexts: vec![],
-                mode: req.mode.unwrap_or_else(|| "qdrant".to_string()),
-                latency_ms: start_time.elapsed().as_millis() as i64,
-            });
+        if self.base.expand_parents(req) {
+            let children = self.children_of(&search_results).await?;
+            if !children.is_empty() {
+                return Ok(expand_to_windows(
+                    &self.base.config.parent,
+                    &search_results,
+                    &children,
+                    self.base.context_budget(&req.query),
+                ));
+            }
         }
 
-        // 构建引用和上下文
-        let mut citations = Vec::new();
-        let mut contexts = Vec::new();
-
-        for (score, chunk) in search_results {
-            citations.push(Citation {
+        // 构建引用
+        let citations = search_results
+            .into_iter()
+            .map(|(score, chunk)| Citation {
                 document_id: chunk.document_id.clone(),
                 chunk_id: chunk.chunk_id.clone(),
                 page: chunk.page,
                 score,
-                snippet: if chunk.text.len() > 240 {
-                    chunk.text.chars().take(240).collect::<String>() + "..."
-                } else {
-                    chunk.text.clone()
-                },
-            });
-            contexts.push(chunk.text);
-        }
-
-        // 格式化上下文并生成回答
-        let formatted_context = self.base.format_context(&citations);
-        let answer = self
-            .base
-            .generate_answer(&formatted_context, &req.query)
-            .await?;
+                snippet: crate::context::display_snippet(&chunk.text),
+                header: chunk.header.clone(),
+                text: Some(chunk.text.clone()),
+            })
+            .collect();
 
-        let latency_ms = start_time.elapsed().as_millis() as i64;
+        Ok(citations)
+    }
 
-        info!(
-            query = %req.query,
-            results_count = citations.len(),
-            latency_ms = latency_ms,
-            "Qdrant RAG query completed"
-        );
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.base
+            .answer_with_citations(req, citations, "qdrant")
+            .await
+    }
 
-        Ok(QueryResponse {
-            answer,
-            citations,
-            contexts,
-            mode: req.mode.unwrap_or_else(|| "qdrant".to_string()),
-            latency_ms,
-        })
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.base.stream_with_citations(req, citations).await
     }
 
     #[instrument(skip(self, text))]
@@ -514,7 +638,8 @@ impl RagEngine for QdrantRagEngine {
         // 分块处理文本并生成统一结构
         let knowledge_chunks = self
             .base
-            .chunk_document(document_id, text, page, meta.clone());
+            .prepare_document(document_id, text, page, meta.clone())
+            .await;
 
         let chunks_count = knowledge_chunks.len();
 
@@ -530,7 +655,7 @@ impl RagEngine for QdrantRagEngine {
             .embed(
                 &knowledge_chunks
                     .iter()
-                    .map(|c| c.text.clone())
+                    .map(|c| c.embedding_text().to_string())
                     .collect::<Vec<_>>(),
             )
             .await
@@ -590,6 +715,9 @@ impl RagEngine for QdrantRagEngine {
                             kind: Some(qdrant_client::qdrant::value::Kind::ListValue(list_value)),
                         }
                     }
+                    serde_json::Value::Null => Value {
+                        kind: Some(qdrant_client::qdrant::value::Kind::NullValue(0)),
+                    },
                     _ => Value {
                         kind: Some(qdrant_client::qdrant::value::Kind::StringValue(
                             v.to_string(),
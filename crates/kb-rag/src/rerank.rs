@@ -581,6 +581,87 @@ impl Reranker for CompositeReranker {
     }
 }
 
+/// 重排链中单个重排器的配置（YAML 中以 `kind` 区分）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RerankerSpec {
+    Keyword {
+        #[serde(default)]
+        case_sensitive: bool,
+        #[serde(default = "default_keyword_boost")]
+        boost_factor: f32,
+    },
+    Length {
+        optimal_length: usize,
+        #[serde(default = "default_length_penalty")]
+        penalty_factor: f32,
+    },
+    Semantic {
+        #[serde(default)]
+        similarity_threshold: f32,
+        #[serde(default = "default_semantic_boost")]
+        boost_factor: f32,
+    },
+    Cohere {
+        #[serde(default = "default_cohere_key_env")]
+        api_key_env: String,
+        model: Option<String>,
+        api_url: Option<String>,
+    },
+}
+
+fn default_keyword_boost() -> f32 {
+    0.2
+}
+
+fn default_length_penalty() -> f32 {
+    0.1
+}
+
+fn default_semantic_boost() -> f32 {
+    0.5
+}
+
+fn default_cohere_key_env() -> String {
+    "COHERE_API_KEY".to_string()
+}
+
+/// 重排配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankerConfig {
+    /// 请求未显式指定 `rerank` 时是否默认重排
+    #[serde(default)]
+    pub enabled: bool,
+    /// 召回候选数相对 top_k 的倍数
+    #[serde(default = "default_candidate_multiplier")]
+    pub candidate_multiplier: usize,
+    /// 候选数上限
+    #[serde(default = "default_max_candidates")]
+    pub max_candidates: usize,
+    /// 按顺序串联的重排器；为空时从环境变量构建
+    #[serde(default)]
+    pub chain: Vec<RerankerSpec>,
+}
+
+fn default_candidate_multiplier() -> usize {
+    3
+}
+
+fn default_max_candidates() -> usize {
+    100
+}
+
+impl Default for RerankerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            candidate_multiplier: default_candidate_multiplier(),
+            max_candidates: default_max_candidates(),
+            chain: Vec::new(),
+        }
+    }
+}
+
 /// 重排器工厂
 pub struct RerankerFactory;
 
@@ -621,6 +702,63 @@ impl RerankerFactory {
         }
     }
 
+    /// 根据 YAML 配置构建重排链
+    ///
+    /// `chain` 为空时回退到 [`RerankerFactory::create_advanced_reranker_chain`]。
+    /// 语义重排器需要嵌入模型，未提供时返回配置错误。
+    pub fn from_config(
+        config: &RerankerConfig,
+        embed_model: Option<std::sync::Arc<dyn kb_llm::EmbedModel>>,
+    ) -> Result<Option<Box<dyn Reranker>>> {
+        if config.chain.is_empty() {
+            return Self::create_advanced_reranker_chain();
+        }
+
+        let mut composite = CompositeReranker::new();
+        for spec in &config.chain {
+            let reranker = match spec {
+                RerankerSpec::Keyword {
+                    case_sensitive,
+                    boost_factor,
+                } => Self::keyword_reranker(*case_sensitive, *boost_factor),
+                RerankerSpec::Length {
+                    optimal_length,
+                    penalty_factor,
+                } => Self::length_reranker(*optimal_length, *penalty_factor),
+                RerankerSpec::Semantic {
+                    similarity_threshold,
+                    boost_factor,
+                } => {
+                    let embed_model =
+                        embed_model.clone().ok_or_else(|| KbError::Configuration {
+                            key: "rerank.chain.semantic".to_string(),
+                            reason: "semantic reranker requires an embedding model".to_string(),
+                        })?;
+                    Self::semantic_reranker(embed_model, *similarity_threshold, *boost_factor)
+                }
+                RerankerSpec::Cohere {
+                    api_key_env,
+                    model,
+                    api_url,
+                } => {
+                    let api_key =
+                        std::env::var(api_key_env).map_err(|_| KbError::Configuration {
+                            key: api_key_env.clone(),
+                            reason: "environment variable not set".to_string(),
+                        })?;
+                    let mut cohere = CohereReranker::new(api_key, model.clone());
+                    if let Some(url) = api_url {
+                        cohere = cohere.with_custom_url(url.clone());
+                    }
+                    Box::new(cohere)
+                }
+            };
+            composite = composite.add_reranker(reranker);
+        }
+
+        Ok(Some(Box::new(composite)))
+    }
+
     /// 创建关键词重排器
     pub fn keyword_reranker(case_sensitive: bool, boost_factor: f32) -> Box<dyn Reranker> {
         Box::new(KeywordReranker::new(case_sensitive, boost_factor))
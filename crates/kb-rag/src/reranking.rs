@@ -0,0 +1,326 @@
+use async_trait::async_trait;
+use kb_core::{Citation, QueryRequest, QueryResponse};
+use kb_error::Result;
+use kb_llm::ChatStream;
+use std::sync::Arc;
+use tracing::{debug, instrument};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagEngineConfig, RagMeta};
+use crate::rerank::{Reranker, RerankerConfig};
+
+/// 重排包装引擎 - 为任意 `RagEngine` 增加"超量召回 + 重排"能力
+pub struct RerankingRagEngine {
+    inner: Arc<dyn RagEngine>,
+    reranker: Arc<dyn Reranker>,
+    config: RerankerConfig,
+    default_top_k: u16,
+}
+
+impl RerankingRagEngine {
+    pub fn new(
+        inner: Arc<dyn RagEngine>,
+        reranker: Arc<dyn Reranker>,
+        config: RerankerConfig,
+    ) -> Self {
+        Self {
+            inner,
+            reranker,
+            config,
+            default_top_k: RagEngineConfig::default().default_top_k,
+        }
+    }
+
+    /// 使用引擎配置中的 `enable_reranking` 与 `default_top_k` 作为默认值
+    pub fn from_engine_config(
+        inner: Arc<dyn RagEngine>,
+        reranker: Arc<dyn Reranker>,
+        engine_config: &RagEngineConfig,
+    ) -> Self {
+        let config = RerankerConfig {
+            enabled: engine_config.enable_reranking,
+            ..Default::default()
+        };
+        Self::new(inner, reranker, config).with_default_top_k(engine_config.default_top_k)
+    }
+
+    /// 设置请求未指定 top_k 时的返回条数
+    pub fn with_default_top_k(mut self, top_k: u16) -> Self {
+        self.default_top_k = top_k;
+        self
+    }
+
+    /// 当前请求是否需要重排
+    fn should_rerank(&self, req: &QueryRequest) -> bool {
+        req.rerank.unwrap_or(self.config.enabled)
+    }
+
+    /// 计算超量召回的候选数
+    fn candidate_count(&self, top_k: u16) -> u16 {
+        let multiplier = self.config.candidate_multiplier.max(1);
+        let candidates = (top_k as usize)
+            .saturating_mul(multiplier)
+            .min(self.config.max_candidates.max(top_k as usize));
+        candidates.min(u16::MAX as usize) as u16
+    }
+
+    /// 超量召回并重排，返回截断后的引用与重排耗时（毫秒）
+    #[instrument(skip(self, req))]
+    async fn retrieve_and_rerank(&self, req: &QueryRequest) -> Result<(Vec<Citation>, i64)> {
+        let top_k = req.top_k.unwrap_or(self.default_top_k);
+
+        let mut candidate_req = req.clone();
+        candidate_req.top_k = Some(self.candidate_count(top_k));
+        let candidates = self.inner.retrieve(&candidate_req).await?;
+        let candidates_count = candidates.len();
+
+        let rerank_start = std::time::Instant::now();
+        let mut reranked = self.reranker.rerank(&req.query, candidates).await?;
+        let rerank_latency_ms = rerank_start.elapsed().as_millis() as i64;
+        reranked.truncate(top_k as usize);
+
+        debug!(
+            reranker = self.reranker.name(),
+            candidates = candidates_count,
+            returned = reranked.len(),
+            rerank_latency_ms,
+            "重排完成"
+        );
+
+        Ok((reranked, rerank_latency_ms))
+    }
+}
+
+#[async_trait]
+impl RagEngine for RerankingRagEngine {
+    #[instrument(skip(self, req))]
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        if !self.should_rerank(&req) {
+            return self.inner.query(req).await;
+        }
+
+        let start_time = std::time::Instant::now();
+        let (citations, rerank_latency_ms) = self.retrieve_and_rerank(&req).await?;
+
+        let mut response = self.inner.generate(&req, citations).await?;
+        response.rerank_latency_ms = Some(rerank_latency_ms);
+        response.latency_ms = start_time.elapsed().as_millis() as i64;
+        Ok(response)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        if !self.should_rerank(req) {
+            return self.inner.retrieve(req).await;
+        }
+        Ok(self.retrieve_and_rerank(req).await?.0)
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.inner.generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.inner.generate_stream(req, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        let status = self.inner.health_check().await?;
+        if let Err(e) = self.reranker.health_check().await {
+            return Ok(HealthStatus::Degraded {
+                reason: format!("Reranker: {}", e),
+            });
+        }
+        Ok(status)
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rerank::KeywordReranker;
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    /// 记录检索请求 top_k 的测试引擎
+    struct FixedEngine {
+        requested_top_k: AtomicU16,
+    }
+
+    fn citation(id: &str, score: f32, snippet: &str) -> Citation {
+        Citation {
+            document_id: "doc".to_string(),
+            chunk_id: id.to_string(),
+            page: None,
+            score,
+            snippet: snippet.to_string(),
+            header: None,
+            text: None,
+        }
+    }
+
+    #[async_trait]
+    impl RagEngine for FixedEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            let citations = self.retrieve(&req).await?;
+            self.generate(&req, citations).await
+        }
+
+        async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+            self.requested_top_k
+                .store(req.top_k.unwrap_or(0), Ordering::SeqCst);
+            let mut results = vec![
+                citation("a", 0.9, "unrelated text"),
+                citation("b", 0.8, "another unrelated passage"),
+                citation("c", 0.7, "refund policy details"),
+            ];
+            results.truncate(req.top_k.unwrap_or(3) as usize);
+            Ok(results)
+        }
+
+        async fn generate(
+            &self,
+            _req: &QueryRequest,
+            citations: Vec<Citation>,
+        ) -> Result<QueryResponse> {
+            Ok(QueryResponse {
+                answer: "ok".to_string(),
+                citations,
+                ..Default::default()
+            })
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn engine(enabled: bool) -> (Arc<FixedEngine>, RerankingRagEngine) {
+        let inner = Arc::new(FixedEngine {
+            requested_top_k: AtomicU16::new(0),
+        });
+        let config = RerankerConfig {
+            enabled,
+            candidate_multiplier: 3,
+            ..Default::default()
+        };
+        let wrapped = RerankingRagEngine::new(
+            inner.clone(),
+            Arc::new(KeywordReranker::new(false, 5.0)),
+            config,
+        );
+        (inner, wrapped)
+    }
+
+    #[tokio::test]
+    async fn test_rerank_overfetches_and_truncates() {
+        let (inner, wrapped) = engine(false);
+        let req = QueryRequest {
+            query: "refund policy".to_string(),
+            top_k: Some(1),
+            rerank: Some(true),
+            ..Default::default()
+        };
+
+        let resp = wrapped.query(req).await.unwrap();
+        assert_eq!(inner.requested_top_k.load(Ordering::SeqCst), 3);
+        assert_eq!(resp.citations.len(), 1);
+        assert_eq!(resp.citations[0].chunk_id, "c");
+        assert!(resp.rerank_latency_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rerank_skipped_when_not_requested() {
+        let (inner, wrapped) = engine(false);
+        let req = QueryRequest {
+            query: "refund policy".to_string(),
+            top_k: Some(1),
+            ..Default::default()
+        };
+
+        let resp = wrapped.query(req).await.unwrap();
+        assert_eq!(inner.requested_top_k.load(Ordering::SeqCst), 1);
+        assert_eq!(resp.citations[0].chunk_id, "a");
+        assert!(resp.rerank_latency_ms.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_factory_builds_chain_when_not_enabled_by_default() {
+        use crate::rerank::{RerankerFactory, RerankerSpec};
+
+        // enabled 只决定是否默认重排，未启用时仍构建重排链供请求显式开启
+        let config = RerankerConfig {
+            enabled: false,
+            chain: vec![RerankerSpec::Keyword {
+                case_sensitive: false,
+                boost_factor: 5.0,
+            }],
+            ..Default::default()
+        };
+        let reranker = RerankerFactory::from_config(&config, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(reranker.name(), "composite");
+        let results = vec![
+            citation("a", 0.9, "unrelated text"),
+            citation("c", 0.7, "refund policy details"),
+        ];
+        let reranked = reranker.rerank("refund policy", results).await.unwrap();
+        assert_eq!(reranked[0].chunk_id, "c");
+    }
+
+    #[test]
+    fn test_factory_rejects_unknown_kind() {
+        let err = serde_json::from_value::<RerankerConfig>(serde_json::json!({
+            "chain": [{ "kind": "bm25" }]
+        }))
+        .unwrap_err();
+        assert!(err.to_string().contains("bm25"));
+    }
+
+    #[test]
+    fn test_factory_semantic_requires_embed_model() {
+        use crate::rerank::{RerankerFactory, RerankerSpec};
+        use kb_error::KbError;
+
+        let config = RerankerConfig {
+            chain: vec![RerankerSpec::Semantic {
+                similarity_threshold: 0.0,
+                boost_factor: 0.5,
+            }],
+            ..Default::default()
+        };
+        let err = RerankerFactory::from_config(&config, None).err().unwrap();
+        assert!(matches!(
+            err,
+            KbError::Configuration { ref key, .. } if key == "rerank.chain.semantic"
+        ));
+    }
+}
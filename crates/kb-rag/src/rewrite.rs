@@ -0,0 +1,354 @@
+use async_trait::async_trait;
+use kb_core::{Citation, ConversationTurn, QueryRequest, QueryResponse};
+use kb_error::Result;
+use kb_llm::tokens::truncate_to_tokens;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, instrument, warn};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagMeta};
+
+/// 追问改写配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 参与改写的最近轮次数
+    #[serde(default = "default_max_turns")]
+    pub max_turns: usize,
+    /// 单轮消息的 token 上限，超出部分截断
+    #[serde(default = "default_max_turn_tokens")]
+    pub max_turn_tokens: usize,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_max_turns() -> usize {
+    6
+}
+
+fn default_max_turn_tokens() -> usize {
+    300
+}
+
+impl Default for RewriteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            max_turns: default_max_turns(),
+            max_turn_tokens: default_max_turn_tokens(),
+        }
+    }
+}
+
+const REWRITE_SYSTEM_PROMPT: &str = "Rewrite the follow-up question into a standalone question that can be understood without the conversation. Resolve pronouns and references such as \"the second one\" using the conversation. Keep the language of the follow-up question. If it is already standalone, return it unchanged. Output only the question.";
+
+/// 追问改写结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// 无历史或模型认为已是独立问题，可直接去掉历史
+    Standalone,
+    /// 改写后的独立问题
+    Rewritten(String),
+    /// 未启用、调用失败或输出无效，原问题可能仍依赖历史
+    Unresolved,
+}
+
+impl Rewrite {
+    /// 改写后的问题，未改写时为 `None`
+    pub fn rewritten(self) -> Option<String> {
+        match self {
+            Rewrite::Rewritten(q) => Some(q),
+            _ => None,
+        }
+    }
+}
+
+/// 追问改写：结合最近的对话轮次把追问改写为独立问题
+pub struct QueryRewriter {
+    chat: Arc<dyn ChatModel>,
+    config: RewriteConfig,
+}
+
+impl QueryRewriter {
+    pub fn new(chat: Arc<dyn ChatModel>, config: RewriteConfig) -> Self {
+        Self { chat, config }
+    }
+
+    /// 改写追问；未改写时调用方沿用原问题
+    #[instrument(skip(self, history))]
+    pub async fn rewrite(&self, query: &str, history: &[ConversationTurn]) -> Rewrite {
+        if history.is_empty() || query.trim().is_empty() {
+            return Rewrite::Standalone;
+        }
+        if !self.config.enabled {
+            return Rewrite::Unresolved;
+        }
+        let recent = &history[history.len().saturating_sub(self.config.max_turns)..];
+        let conversation = recent
+            .iter()
+            .map(|t| {
+                format!(
+                    "{}: {}",
+                    t.role,
+                    truncate_to_tokens(t.content.trim(), self.config.max_turn_tokens)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(REWRITE_SYSTEM_PROMPT),
+            ChatMessage::user(format!(
+                "Conversation:\n{}\n\nFollow-up question: {}",
+                conversation, query
+            )),
+        ])
+        .with_temperature(0.0);
+
+        let output = match self.chat.complete(&req).await {
+            Ok(resp) => resp.content,
+            Err(e) => {
+                warn!(error = %e, "追问改写失败，沿用原问题");
+                return Rewrite::Unresolved;
+            }
+        };
+        let rewritten = clean_output(&output);
+        // 空输出或明显跑题的长回答视为失败
+        if rewritten.is_empty() || rewritten.chars().count() > query.chars().count() * 4 + 200 {
+            warn!(output = %output, "追问改写输出无效，沿用原问题");
+            return Rewrite::Unresolved;
+        }
+        if rewritten == query.trim() {
+            return Rewrite::Standalone;
+        }
+        debug!(rewritten = %rewritten, "追问已改写");
+        Rewrite::Rewritten(rewritten)
+    }
+
+    /// 改写请求：返回新请求与改写后的问题
+    ///
+    /// 改写成功或已是独立问题时去掉历史；未能改写时保留历史，回答缓存据此跳过该请求。
+    pub async fn condense(&self, req: &QueryRequest) -> (QueryRequest, Option<String>) {
+        let history = req.history.as_deref().unwrap_or_default();
+        let rewrite = self.rewrite(&req.query, history).await;
+        let mut condensed = req.clone();
+        if rewrite != Rewrite::Unresolved {
+            condensed.history = None;
+        }
+        let rewritten = rewrite.rewritten();
+        if let Some(q) = &rewritten {
+            condensed.query = q.clone();
+        }
+        (condensed, rewritten)
+    }
+}
+
+/// 去掉模型常加的前缀与引号
+fn clean_output(output: &str) -> String {
+    let line = output.trim().lines().next().unwrap_or("").trim();
+    let line = ["Standalone question:", "Question:", "问题：", "问题:"]
+        .iter()
+        .find_map(|p| line.strip_prefix(p))
+        .unwrap_or(line)
+        .trim();
+    line.trim_matches(|c| matches!(c, '"' | '“' | '”' | '「' | '」'))
+        .trim()
+        .to_string()
+}
+
+/// 检索前改写追问的 RAG 引擎包装，响应中回显改写后的问题
+///
+/// 下游引擎收到的请求不含历史，因此应位于回答缓存等包装的外层。
+pub struct ConversationalRagEngine {
+    inner: Arc<dyn RagEngine>,
+    rewriter: Arc<QueryRewriter>,
+}
+
+impl ConversationalRagEngine {
+    pub fn new(inner: Arc<dyn RagEngine>, rewriter: Arc<QueryRewriter>) -> Self {
+        Self { inner, rewriter }
+    }
+}
+
+#[async_trait]
+impl RagEngine for ConversationalRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        let (condensed, rewritten) = self.rewriter.condense(&req).await;
+        let mut resp = self.inner.query(condensed).await?;
+        resp.rewritten_query = rewritten;
+        Ok(resp)
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        let (condensed, _) = self.rewriter.condense(req).await;
+        self.inner.retrieve(&condensed).await
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        let (condensed, rewritten) = self.rewriter.condense(req).await;
+        let mut resp = self.inner.generate(&condensed, citations).await?;
+        resp.rewritten_query = rewritten;
+        Ok(resp)
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        let (condensed, _) = self.rewriter.condense(req).await;
+        self.inner.generate_stream(&condensed, citations).await
+    }
+
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.inner
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.inner.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.inner.stats().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_llm::ChatResponse;
+    use std::sync::Mutex;
+
+    /// 返回固定输出并记录请求
+    struct ScriptedChat {
+        output: &'static str,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    #[async_trait]
+    impl ChatModel for ScriptedChat {
+        async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+            self.requests.lock().unwrap().push(req.clone());
+            Ok(ChatResponse {
+                content: self.output.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    /// 以检索请求的问题作为回答
+    struct EchoEngine;
+
+    #[async_trait]
+    impl RagEngine for EchoEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            assert!(req.history.is_none());
+            Ok(QueryResponse {
+                answer: req.query,
+                ..Default::default()
+            })
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn turn(role: &str, content: &str) -> ConversationTurn {
+        ConversationTurn {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    fn engine(output: &'static str) -> (Arc<ScriptedChat>, ConversationalRagEngine) {
+        let chat = Arc::new(ScriptedChat {
+            output,
+            requests: Mutex::new(Vec::new()),
+        });
+        let rewriter = Arc::new(QueryRewriter::new(chat.clone(), RewriteConfig::default()));
+        (
+            chat,
+            ConversationalRagEngine::new(Arc::new(EchoEngine), rewriter),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_follow_up_is_rewritten_before_retrieval() {
+        let (chat, engine) = engine("问题：“退款政策的第二条是什么？”");
+        let req = QueryRequest {
+            query: "那第二条呢?".to_string(),
+            history: Some(vec![
+                turn("user", "退款政策有哪些？"),
+                turn("assistant", "1. 七天内可退款 2. 需保留发票"),
+            ]),
+            ..Default::default()
+        };
+        let resp = engine.query(req).await.unwrap();
+        assert_eq!(resp.answer, "退款政策的第二条是什么？");
+        assert_eq!(
+            resp.rewritten_query.as_deref(),
+            Some("退款政策的第二条是什么？")
+        );
+        let prompt = &chat.requests.lock().unwrap()[0].messages[1].content;
+        assert!(prompt.contains("assistant: 1. 七天内可退款"));
+        assert!(prompt.ends_with("Follow-up question: 那第二条呢?"));
+    }
+
+    #[tokio::test]
+    async fn test_without_history_skips_rewrite() {
+        let (chat, engine) = engine("ignored");
+        let req = QueryRequest {
+            query: "退款政策有哪些？".to_string(),
+            ..Default::default()
+        };
+        let resp = engine.query(req).await.unwrap();
+        assert_eq!(resp.answer, "退款政策有哪些？");
+        assert!(resp.rewritten_query.is_none());
+        assert!(chat.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_rewrite_keeps_history() {
+        let chat = Arc::new(ScriptedChat {
+            output: "",
+            requests: Mutex::new(Vec::new()),
+        });
+        let rewriter = QueryRewriter::new(chat, RewriteConfig::default());
+        let req = QueryRequest {
+            query: "那第二条呢?".to_string(),
+            history: Some(vec![turn("user", "退款政策有哪些？")]),
+            ..Default::default()
+        };
+        assert_eq!(
+            rewriter
+                .rewrite(&req.query, req.history.as_deref().unwrap())
+                .await,
+            Rewrite::Unresolved
+        );
+        let (condensed, rewritten) = rewriter.condense(&req).await;
+        assert!(rewritten.is_none());
+        assert_eq!(condensed.history.map(|h| h.len()), Some(1));
+    }
+}
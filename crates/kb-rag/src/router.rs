@@ -0,0 +1,549 @@
+//! 查询路由：`mode` 为 `auto` 时按问题特征在 lexical / graph / hybrid 之间选择，
+//! 并按 `mode` 把请求分派给对应的检索引擎
+
+use async_trait::async_trait;
+use kb_core::{Citation, QueryRequest, QueryResponse};
+use kb_error::Result;
+use kb_llm::{ChatMessage, ChatModel, ChatRequest, ChatStream};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::engine::{EngineStats, HealthStatus, RagEngine, RagMeta};
+use crate::hybrid::{HybridConfig, HybridRagEngine};
+
+/// 请求中 `mode` 取该值时自动选择检索方式
+pub const AUTO_MODE: &str = "auto";
+
+/// 路由目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Route {
+    /// 精确的编码、编号等，走词汇检索
+    Lexical,
+    /// 实体之间的关系，走图检索
+    Graph,
+    /// 其余问题
+    Hybrid,
+}
+
+impl Route {
+    /// 对应的请求 `mode`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Route::Lexical => "lexical",
+            Route::Graph => "graph",
+            Route::Hybrid => "hybrid",
+        }
+    }
+}
+
+/// 路由结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteDecision {
+    pub route: Route,
+    /// 0~1
+    pub confidence: f32,
+    /// rules 或 llm
+    pub classifier: String,
+    pub reason: String,
+}
+
+/// 查询路由配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouterConfig {
+    /// 规则置信度较低时是否请对话模型判断
+    pub llm: bool,
+    /// 规则置信度低于该值时才调用对话模型
+    pub llm_below: f32,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            llm: false,
+            llm_below: 0.6,
+        }
+    }
+}
+
+/// 关系类问题的提示词
+const RELATION_CUES: &[&str] = &[
+    "relationship",
+    "relation between",
+    "related to",
+    "connected to",
+    "connection between",
+    "depends on",
+    "depend on",
+    "dependencies of",
+    "reports to",
+    "linked to",
+    "关系",
+    "关联",
+    "之间",
+    "依赖",
+    "上下游",
+    "隶属",
+    "汇报给",
+];
+
+/// 编码中允许出现的非字母数字字符
+const CODE_PUNCTUATION: &[char] = &['-', '_', '.', '/', ':', '#'];
+
+const ROUTER_SYSTEM_PROMPT: &str = "Choose how to search a knowledge base for the user's question. Use \"lexical\" when the question is about exact codes, IDs, error numbers or quoted strings; \"graph\" when it asks about relationships or dependencies between entities; otherwise \"hybrid\". Give your confidence between 0 and 1.";
+
+/// 对话模型的路由输出
+#[derive(Debug, Deserialize, JsonSchema)]
+struct LlmRoute {
+    route: Route,
+    confidence: f32,
+}
+
+/// 查询路由器：先按规则判断，可选地在置信度较低时请对话模型判断
+pub struct QueryRouter {
+    config: RouterConfig,
+    chat: Option<Arc<dyn ChatModel>>,
+    graph: bool,
+}
+
+impl QueryRouter {
+    pub fn new(config: RouterConfig) -> Self {
+        Self {
+            config,
+            chat: None,
+            graph: true,
+        }
+    }
+
+    /// 设置用于低置信度问题的对话模型
+    pub fn with_chat_model(mut self, chat: Arc<dyn ChatModel>) -> Self {
+        self.chat = Some(chat);
+        self
+    }
+
+    /// 是否配置了可用的图检索引擎；未配置时关系类问题改走混合检索
+    pub fn with_graph(mut self, available: bool) -> Self {
+        self.graph = available;
+        self
+    }
+
+    pub async fn route(&self, query: &str) -> RouteDecision {
+        let mut decision = self.classify(query).await;
+        if decision.route == Route::Graph && !self.graph {
+            decision.route = Route::Hybrid;
+            decision.reason = format!("graph engine not configured; {}", decision.reason);
+        }
+        decision
+    }
+
+    async fn classify(&self, query: &str) -> RouteDecision {
+        let decision = classify(query);
+        let chat = match &self.chat {
+            Some(chat) if self.config.llm && decision.confidence < self.config.llm_below => chat,
+            _ => return decision,
+        };
+        let req = ChatRequest::new(vec![
+            ChatMessage::system(ROUTER_SYSTEM_PROMPT),
+            ChatMessage::user(query),
+        ])
+        .with_temperature(0.0);
+        match kb_llm::chat_json::<LlmRoute>(chat.as_ref(), req).await {
+            Ok(out) if out.confidence > decision.confidence => RouteDecision {
+                route: out.route,
+                confidence: out.confidence.clamp(0.0, 1.0),
+                classifier: "llm".to_string(),
+                reason: format!("rules suggested {}", decision.route.as_str()),
+            },
+            Ok(out) => {
+                debug!(
+                    route = out.route.as_str(),
+                    "对话模型的路由置信度不高于规则，沿用规则结果"
+                );
+                decision
+            }
+            Err(e) => {
+                warn!(error = %e, "对话模型路由失败，沿用规则结果");
+                decision
+            }
+        }
+    }
+}
+
+/// 按规则分类：关系类问题走图检索，含编码或引号内精确字符串的走词汇检索，其余走混合检索
+pub fn classify(query: &str) -> RouteDecision {
+    let lower = query.to_lowercase();
+    let rules = |route, confidence, reason: String| RouteDecision {
+        route,
+        confidence,
+        classifier: "rules".to_string(),
+        reason,
+    };
+
+    if let Some(cue) = RELATION_CUES.iter().find(|c| lower.contains(*c)) {
+        return rules(Route::Graph, 0.8, format!("relationship cue: {}", cue));
+    }
+
+    let codes = find_codes(query);
+    let quoted = has_quoted_phrase(query);
+    if !codes.is_empty() || quoted {
+        // 编码占问题主体时更确定
+        let code_chars: usize = codes.iter().map(|c| c.chars().count()).sum();
+        let total = query.chars().filter(|c| !c.is_whitespace()).count().max(1);
+        let confidence = if code_chars * 3 >= total { 0.9 } else { 0.7 };
+        let reason = if codes.is_empty() {
+            "quoted exact phrase".to_string()
+        } else {
+            format!("exact code or id: {}", codes.join(", "))
+        };
+        return rules(Route::Lexical, confidence, reason);
+    }
+
+    rules(
+        Route::Hybrid,
+        0.5,
+        "no exact identifiers or relationship cues".to_string(),
+    )
+}
+
+/// 按请求 `mode` 分派检索引擎：`lexical` 走词汇检索，`hybrid` 融合向量与词汇检索，其余走向量检索
+///
+/// 文档同时写入向量与词汇索引。应位于检索扩展、重排等包装的内层，使各模式共用同一包装链。
+/// 混合检索以 RRF 融合，RRF 得分量级只与排名有关，因此不按融合得分截断。
+/// 词汇索引在内存中，重启后为空，词汇检索无结果时退回向量检索。
+pub struct RoutedRagEngine {
+    vector: Arc<dyn RagEngine>,
+    lexical: Arc<dyn RagEngine>,
+    hybrid: Arc<dyn RagEngine>,
+}
+
+impl RoutedRagEngine {
+    pub fn new(vector: Arc<dyn RagEngine>, lexical: Arc<dyn RagEngine>) -> Self {
+        let config = HybridConfig {
+            min_score_threshold: 0.0,
+            ..Default::default()
+        };
+        let hybrid =
+            HybridRagEngine::new(vector.clone(), config).with_lexical_engine(lexical.clone());
+        Self {
+            vector,
+            lexical,
+            hybrid: Arc::new(hybrid),
+        }
+    }
+
+    fn engine(&self, req: &QueryRequest) -> &Arc<dyn RagEngine> {
+        match req.mode.as_deref() {
+            Some("lexical") => &self.lexical,
+            Some("hybrid") => &self.hybrid,
+            _ => &self.vector,
+        }
+    }
+
+    fn is_lexical(req: &QueryRequest) -> bool {
+        req.mode.as_deref() == Some("lexical")
+    }
+}
+
+#[async_trait]
+impl RagEngine for RoutedRagEngine {
+    async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+        if Self::is_lexical(&req) && self.lexical.retrieve(&req).await?.is_empty() {
+            return self.vector.query(req).await;
+        }
+        self.engine(&req).query(req).await
+    }
+
+    async fn retrieve(&self, req: &QueryRequest) -> Result<Vec<Citation>> {
+        let citations = self.engine(req).retrieve(req).await?;
+        if citations.is_empty() && Self::is_lexical(req) {
+            debug!("词汇检索无结果，退回向量检索");
+            return self.vector.retrieve(req).await;
+        }
+        Ok(citations)
+    }
+
+    async fn generate(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<QueryResponse> {
+        self.engine(req).generate(req, citations).await
+    }
+
+    async fn generate_stream(
+        &self,
+        req: &QueryRequest,
+        citations: Vec<Citation>,
+    ) -> Result<ChatStream> {
+        self.engine(req).generate_stream(req, citations).await
+    }
+
+    /// 混合引擎会依次写入向量与词汇索引
+    async fn add_document_text_with_meta(
+        &self,
+        document_id: &str,
+        text: &str,
+        page: Option<i32>,
+        meta: Option<RagMeta>,
+    ) -> Result<()> {
+        self.hybrid
+            .add_document_text_with_meta(document_id, text, page, meta)
+            .await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        self.hybrid.health_check().await
+    }
+
+    async fn stats(&self) -> Result<EngineStats> {
+        self.vector.stats().await
+    }
+}
+
+/// 提取形如 `ERR-1042`、`INV2023-001`、`v1.2.3`、`MAX_RETRIES` 或 5 位以上数字的编码
+fn find_codes(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !(c.is_ascii_alphanumeric() || CODE_PUNCTUATION.contains(&c)))
+        .map(|t| t.trim_matches(|c: char| CODE_PUNCTUATION.contains(&c)))
+        .filter(|t| is_code(t))
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_code(token: &str) -> bool {
+    if token.len() < 3 {
+        return false;
+    }
+    let digits = token.chars().filter(|c| c.is_ascii_digit()).count();
+    let letters = token.chars().filter(|c| c.is_ascii_alphabetic()).count();
+    let separators = token.len() - digits - letters;
+    if digits == token.len() {
+        // 排除年份等短数字
+        return digits >= 5;
+    }
+    if digits > 0 {
+        return letters > 0 || separators > 0;
+    }
+    // 无数字时仅把全大写且含下划线的常量名视为编码
+    token.contains('_') && !token.chars().any(|c| c.is_ascii_lowercase())
+}
+
+fn has_quoted_phrase(query: &str) -> bool {
+    [('"', '"'), ('“', '”'), ('「', '」'), ('`', '`')]
+        .iter()
+        .any(|&(open, close)| {
+            query
+                .split_once(open)
+                .and_then(|(_, rest)| rest.split_once(close))
+                .is_some_and(|(inner, _)| !inner.trim().is_empty())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kb_error::Result;
+    use kb_llm::ChatResponse;
+
+    #[test]
+    fn test_rules_route_by_features() {
+        let cases = [
+            ("ERR-1042", Route::Lexical),
+            ("错误码E1042是什么意思", Route::Lexical),
+            ("what does \"quota exceeded\" mean", Route::Lexical),
+            (
+                "How is the billing service related to the auth service?",
+                Route::Graph,
+            ),
+            ("订单系统和库存系统之间的依赖", Route::Graph),
+            ("如何申请退款", Route::Hybrid),
+            ("what changed in 2023", Route::Hybrid),
+        ];
+        for (query, route) in cases {
+            assert_eq!(classify(query).route, route, "{}", query);
+        }
+        assert_eq!(classify("ERR-1042").confidence, 0.9);
+        assert_eq!(
+            find_codes("see v1.2.3 and MAX_RETRIES."),
+            vec!["v1.2.3", "MAX_RETRIES"]
+        );
+    }
+
+    struct FixedChat(&'static str);
+
+    #[async_trait]
+    impl ChatModel for FixedChat {
+        async fn complete(&self, _req: &ChatRequest) -> Result<ChatResponse> {
+            Ok(ChatResponse {
+                content: self.0.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graph_route_requires_graph_engine() {
+        let query = "订单系统和库存系统之间的依赖";
+        let router = QueryRouter::new(RouterConfig::default());
+        assert_eq!(router.route(query).await.route, Route::Graph);
+        let decision = router.with_graph(false).route(query).await;
+        assert_eq!(decision.route, Route::Hybrid);
+        assert!(decision.reason.starts_with("graph engine not configured"));
+    }
+
+    /// 以引擎名作为回答，检索返回一条以引擎名为文档 ID、得分固定的引用
+    struct NamedEngine(&'static str, f32);
+
+    #[async_trait]
+    impl RagEngine for NamedEngine {
+        async fn query(&self, _req: QueryRequest) -> Result<QueryResponse> {
+            Ok(QueryResponse {
+                answer: self.0.to_string(),
+                ..Default::default()
+            })
+        }
+
+        async fn retrieve(&self, _req: &QueryRequest) -> Result<Vec<Citation>> {
+            Ok(vec![Citation {
+                document_id: self.0.to_string(),
+                chunk_id: format!("{}#0", self.0),
+                page: None,
+                score: self.1,
+                snippet: self.0.to_string(),
+                header: None,
+                text: None,
+            }])
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routed_engine_dispatches_by_mode() {
+        let engine = RoutedRagEngine::new(
+            Arc::new(NamedEngine("vector", 1.0)),
+            Arc::new(NamedEngine("lexical", 1.0)),
+        );
+        let req = |mode: Option<&str>| QueryRequest {
+            query: "ERR-1042".to_string(),
+            mode: mode.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(engine.query(req(None)).await.unwrap().answer, "vector");
+        assert_eq!(
+            engine.query(req(Some("rag"))).await.unwrap().answer,
+            "vector"
+        );
+        assert_eq!(
+            engine.query(req(Some("lexical"))).await.unwrap().answer,
+            "lexical"
+        );
+        let mut ids: Vec<String> = engine
+            .retrieve(&req(Some("hybrid")))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.document_id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["lexical", "vector"]);
+    }
+
+    /// 索引为空的词汇检索
+    struct EmptyEngine;
+
+    #[async_trait]
+    impl RagEngine for EmptyEngine {
+        async fn query(&self, _req: QueryRequest) -> Result<QueryResponse> {
+            Ok(QueryResponse::default())
+        }
+
+        async fn retrieve(&self, _req: &QueryRequest) -> Result<Vec<Citation>> {
+            Ok(Vec::new())
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_lexical_index_falls_back_to_vector() {
+        let engine =
+            RoutedRagEngine::new(Arc::new(NamedEngine("vector", 1.0)), Arc::new(EmptyEngine));
+        let req = QueryRequest {
+            query: "ERR-1042".to_string(),
+            mode: Some("lexical".to_string()),
+            ..Default::default()
+        };
+        let citations = engine.retrieve(&req).await.unwrap();
+        assert_eq!(citations[0].document_id, "vector");
+        assert_eq!(engine.query(req).await.unwrap().answer, "vector");
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_results_pass_answerability_gate() {
+        use crate::answerability::{AnswerabilityConfig, AnswerabilityGate, GatedRagEngine};
+        use crate::engine::RagEngineConfig;
+
+        let engine = RoutedRagEngine::new(
+            Arc::new(NamedEngine("vector", 0.82)),
+            Arc::new(NamedEngine("lexical", 0.3)),
+        );
+        let gated = GatedRagEngine::new(
+            Arc::new(engine),
+            AnswerabilityGate::new(AnswerabilityConfig::default(), &RagEngineConfig::default()),
+        );
+        let req = QueryRequest {
+            query: "如何申请退款".to_string(),
+            mode: Some("hybrid".to_string()),
+            ..Default::default()
+        };
+        let citations = gated.retrieve(&req).await.unwrap();
+        // RRF 融合后保留原始得分，相似度足够的向量结果通过门控
+        assert_eq!(citations.len(), 2);
+        let vector = citations
+            .iter()
+            .find(|c| c.document_id == "vector")
+            .unwrap();
+        assert_eq!(vector.score, 0.82);
+        assert!(gated.assess(&req, &citations).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_llm_only_overrides_low_confidence_rules() {
+        let router = QueryRouter::new(RouterConfig {
+            llm: true,
+            ..Default::default()
+        })
+        .with_chat_model(Arc::new(FixedChat(
+            r#"{"route": "graph", "confidence": 0.85}"#,
+        )));
+
+        let decision = router.route("who owns the payment gateway").await;
+        assert_eq!(decision.route, Route::Graph);
+        assert_eq!(decision.classifier, "llm");
+
+        // 规则置信度足够时不调用对话模型
+        let decision = router.route("ERR-1042").await;
+        assert_eq!(decision.route, Route::Lexical);
+        assert_eq!(decision.classifier, "rules");
+    }
+}
@@ -0,0 +1,580 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use kb_core::QueryRequest;
+use kb_error::Result;
+use kb_llm::{
+    ChatDelta, ChatMessage, ChatModel, ChatRequest, ChatResponse, ToolCall, ToolChoice,
+    ToolDefinition,
+};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+use tracing::{debug, instrument, warn};
+
+use crate::engine::{GraphRagEngine, RagEngine};
+
+/// 可供 LLM 调用的工具
+#[async_trait]
+pub trait RagTool: Send + Sync {
+    /// 工具定义（名称、描述与 JSON Schema 参数）
+    fn definition(&self) -> ToolDefinition;
+
+    /// 执行工具，返回回传给模型的文本结果
+    async fn call(&self, args: Value) -> Result<String>;
+}
+
+/// 知识库检索工具：基于任意 `RagEngine` 的检索结果
+pub struct KnowledgeBaseSearchTool {
+    engine: Arc<dyn RagEngine>,
+    default_top_k: u16,
+    filters: Option<Value>,
+}
+
+impl KnowledgeBaseSearchTool {
+    pub const NAME: &'static str = "kb_search";
+
+    pub fn new(engine: Arc<dyn RagEngine>) -> Self {
+        Self {
+            engine,
+            default_top_k: 5,
+            filters: None,
+        }
+    }
+
+    pub fn with_default_top_k(mut self, top_k: u16) -> Self {
+        self.default_top_k = top_k;
+        self
+    }
+
+    /// 固定附加的过滤条件（例如限定租户），模型无法覆盖
+    pub fn with_filters(mut self, filters: Option<Value>) -> Self {
+        self.filters = filters;
+        self
+    }
+}
+
+#[async_trait]
+impl RagTool for KnowledgeBaseSearchTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::new(
+            Self::NAME,
+            "Search the knowledge base and return the most relevant passages.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": {"type": "string", "description": "Search query"},
+                    "top_k": {"type": "integer", "minimum": 1, "maximum": 20}
+                },
+                "required": ["query"]
+            }),
+        )
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let query = required_str(&args, "query", Self::NAME)?;
+        let top_k = args
+            .get("top_k")
+            .and_then(|v| v.as_u64())
+            .map(|v| v.clamp(1, 20) as u16)
+            .unwrap_or(self.default_top_k);
+        let req = QueryRequest {
+            query: query.to_string(),
+            top_k: Some(top_k),
+            filters: self.filters.clone(),
+            ..Default::default()
+        };
+        let citations = self.engine.retrieve(&req).await?;
+        if citations.is_empty() {
+            return Ok("No relevant passages found.".to_string());
+        }
+        Ok(citations
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                format!(
+                    "[{}] (document: {}, score: {:.3})\n{}",
+                    i + 1,
+                    c.document_id,
+                    c.score,
+                    c.snippet
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+}
+
+/// 图谱实体查询工具：返回实体在知识图谱中的邻居
+pub struct GraphEntityLookupTool {
+    graph: Arc<dyn GraphRagEngine>,
+    max_hops: u8,
+}
+
+impl GraphEntityLookupTool {
+    pub const NAME: &'static str = "graph_entity_lookup";
+
+    pub fn new(graph: Arc<dyn GraphRagEngine>) -> Self {
+        Self { graph, max_hops: 2 }
+    }
+
+    pub fn with_max_hops(mut self, max_hops: u8) -> Self {
+        self.max_hops = max_hops.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl RagTool for GraphEntityLookupTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition::new(
+            Self::NAME,
+            "Look up an entity in the knowledge graph and list its related entities.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "entity": {"type": "string", "description": "Entity name"},
+                    "hops": {"type": "integer", "minimum": 1, "maximum": self.max_hops}
+                },
+                "required": ["entity"]
+            }),
+        )
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let entity = required_str(&args, "entity", Self::NAME)?;
+        let hops = args
+            .get("hops")
+            .and_then(|v| v.as_u64())
+            .map(|v| v.clamp(1, self.max_hops as u64) as u8)
+            .unwrap_or(1);
+        let neighbors = self.graph.get_entity_neighbors(entity, hops).await?;
+        if neighbors.is_empty() {
+            return Ok(format!("No related entities found for '{}'.", entity));
+        }
+        Ok(format!(
+            "Entities related to '{}': {}",
+            entity,
+            neighbors.join(", ")
+        ))
+    }
+}
+
+fn required_str<'a>(args: &'a Value, key: &str, tool: &str) -> Result<&'a str> {
+    args.get(key)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| kb_error::KbError::InvalidRequest {
+            reason: format!("{}: missing or empty argument `{}`", tool, key),
+        })
+}
+
+/// 工具集合
+#[derive(Clone, Default)]
+pub struct ToolSet {
+    tools: Vec<Arc<dyn RagTool>>,
+}
+
+impl ToolSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tool(mut self, tool: Arc<dyn RagTool>) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|t| t.definition()).collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<dyn RagTool>> {
+        self.tools.iter().find(|t| t.definition().name == name)
+    }
+
+    /// 执行一次工具调用并生成工具结果消息；执行失败时把错误回传给模型而非中断对话
+    #[instrument(skip(self, call), fields(tool = %call.name))]
+    pub async fn call(&self, call: &ToolCall) -> ChatMessage {
+        let output = match self.find(&call.name) {
+            Some(tool) => match tool.call(call.arguments.clone()).await {
+                Ok(out) => out,
+                Err(e) => {
+                    warn!(error = %e, "工具执行失败");
+                    format!("Tool error: {}", e)
+                }
+            },
+            None => format!("Tool error: unknown tool '{}'", call.name),
+        };
+        ChatMessage::tool(call.id.clone(), output)
+    }
+}
+
+/// 工具调用循环的结果
+#[derive(Debug, Clone, Default)]
+pub struct AgentRun {
+    /// 本次运行新增的消息（助手消息与工具结果），按顺序追加到对话历史即可
+    pub messages: Vec<ChatMessage>,
+    /// 最终回答；存在待处理工具调用时为空
+    pub answer: String,
+    /// 不属于本工具集、需由调用方执行的工具调用
+    pub pending_tool_calls: Vec<ToolCall>,
+}
+
+/// 流式工具循环中的事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentEvent {
+    /// 新增的对话消息：携带工具调用的助手消息或本地工具的执行结果
+    Message(ChatMessage),
+    /// 模型输出的增量文本
+    Text(String),
+}
+
+/// 基于任意 `ChatModel` 的工具调用循环
+pub struct ToolAgent {
+    chat: Arc<dyn ChatModel>,
+    tools: ToolSet,
+    max_rounds: usize,
+}
+
+impl ToolAgent {
+    pub fn new(chat: Arc<dyn ChatModel>, tools: ToolSet) -> Self {
+        Self {
+            chat,
+            tools,
+            max_rounds: 5,
+        }
+    }
+
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds.max(1);
+        self
+    }
+
+    /// 运行工具循环：本地工具自动执行并回传结果，直到模型给出回答、
+    /// 请求调用方工具（`req.tools` 中额外声明的工具）或达到轮数上限
+    #[instrument(skip(self, req))]
+    pub async fn run(&self, req: ChatRequest) -> Result<AgentRun> {
+        self.run_inner(req, None::<fn(AgentEvent) -> std::future::Ready<()>>)
+            .await
+    }
+
+    /// 同 [`ToolAgent::run`]，模型输出经 `complete_stream_with_tools` 流式返回，
+    /// 文本增量与新增消息按到达顺序交给 `on_event`
+    #[instrument(skip(self, req, on_event))]
+    pub async fn run_stream<F, Fut>(&self, req: ChatRequest, on_event: F) -> Result<AgentRun>
+    where
+        F: FnMut(AgentEvent) -> Fut + Send,
+        Fut: Future<Output = ()> + Send,
+    {
+        self.run_inner(req, Some(on_event)).await
+    }
+
+    async fn run_inner<F, Fut>(
+        &self,
+        mut req: ChatRequest,
+        mut on_event: Option<F>,
+    ) -> Result<AgentRun>
+    where
+        F: FnMut(AgentEvent) -> Fut + Send,
+        Fut: Future<Output = ()> + Send,
+    {
+        req.tools.extend(self.tools.definitions());
+        let mut run = AgentRun::default();
+
+        for round in 0..=self.max_rounds {
+            if round == self.max_rounds {
+                // 达到上限时禁止继续调用工具，强制给出回答
+                req.tool_choice = Some(ToolChoice::None);
+            }
+            let resp = match on_event.as_mut() {
+                Some(on_event) => self.stream_turn(&req, on_event).await?,
+                None => self.chat.complete(&req).await?,
+            };
+            if resp.tool_calls.is_empty() {
+                run.answer = resp.content.clone();
+                run.messages.push(resp.into_message());
+                return Ok(run);
+            }
+            if round == self.max_rounds {
+                // 部分提供商会忽略 tool_choice，此时没有可用的回答
+                return Err(kb_error::KbError::LlmService {
+                    provider: "chat".to_string(),
+                    message: format!(
+                        "model still requested {} tool call(s) after {} rounds with tool_choice=none",
+                        resp.tool_calls.len(),
+                        self.max_rounds
+                    ),
+                    retry_after: None,
+                });
+            }
+
+            debug!(round, calls = resp.tool_calls.len(), "模型请求调用工具");
+            let calls = resp.tool_calls.clone();
+            let message = resp.into_message();
+            req.messages.push(message.clone());
+            emit(&mut on_event, AgentEvent::Message(message.clone())).await;
+            run.messages.push(message);
+
+            for call in &calls {
+                if self.tools.contains(&call.name) {
+                    let result = self.tools.call(call).await;
+                    req.messages.push(result.clone());
+                    emit(&mut on_event, AgentEvent::Message(result.clone())).await;
+                    run.messages.push(result);
+                } else {
+                    run.pending_tool_calls.push(call.clone());
+                }
+            }
+            if !run.pending_tool_calls.is_empty() {
+                return Ok(run);
+            }
+        }
+        unreachable!("the last round either answers or returns an error")
+    }
+
+    /// 流式执行一轮补全，文本增量即时转发，结束后汇总为完整响应
+    async fn stream_turn<F, Fut>(&self, req: &ChatRequest, on_event: &mut F) -> Result<ChatResponse>
+    where
+        F: FnMut(AgentEvent) -> Fut + Send,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut stream = self.chat.complete_stream_with_tools(req).await?;
+        let mut resp = ChatResponse::default();
+        while let Some(delta) = stream.next().await {
+            match delta? {
+                ChatDelta::Text(text) => {
+                    resp.content.push_str(&text);
+                    on_event(AgentEvent::Text(text)).await;
+                }
+                ChatDelta::ToolCall(call) => resp.tool_calls.push(call),
+            }
+        }
+        Ok(resp)
+    }
+}
+
+async fn emit<F, Fut>(on_event: &mut Option<F>, event: AgentEvent)
+where
+    F: FnMut(AgentEvent) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    if let Some(on_event) = on_event.as_mut() {
+        on_event(event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::RagMeta;
+    use kb_core::{Citation, QueryResponse};
+    use kb_llm::ChatResponse;
+    use std::sync::Mutex;
+
+    struct FixedEngine;
+
+    #[async_trait]
+    impl RagEngine for FixedEngine {
+        async fn query(&self, req: QueryRequest) -> Result<QueryResponse> {
+            Ok(QueryResponse {
+                citations: self.retrieve(&req).await?,
+                ..Default::default()
+            })
+        }
+
+        async fn retrieve(&self, _req: &QueryRequest) -> Result<Vec<Citation>> {
+            Ok(vec![Citation {
+                document_id: "policy".to_string(),
+                chunk_id: "c1".to_string(),
+                page: None,
+                score: 0.9,
+                snippet: "七天无理由退款".to_string(),
+                header: None,
+                text: None,
+            }])
+        }
+
+        async fn add_document_text_with_meta(
+            &self,
+            _document_id: &str,
+            _text: &str,
+            _page: Option<i32>,
+            _meta: Option<RagMeta>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 按顺序返回预设响应，并记录收到的请求
+    struct ScriptedChat {
+        responses: Mutex<Vec<ChatResponse>>,
+        requests: Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedChat {
+        fn new(mut responses: Vec<ChatResponse>) -> Self {
+            responses.reverse();
+            Self {
+                responses: Mutex::new(responses),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ChatModel for ScriptedChat {
+        async fn complete(&self, req: &ChatRequest) -> Result<ChatResponse> {
+            self.requests.lock().unwrap().push(req.clone());
+            Ok(self.responses.lock().unwrap().pop().unwrap_or_default())
+        }
+    }
+
+    fn tool_call(id: &str, name: &str, arguments: Value) -> ChatResponse {
+        ChatResponse {
+            tool_calls: vec![ToolCall {
+                id: id.to_string(),
+                name: name.to_string(),
+                arguments,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn kb_tools() -> ToolSet {
+        ToolSet::new().with_tool(Arc::new(KnowledgeBaseSearchTool::new(Arc::new(
+            FixedEngine,
+        ))))
+    }
+
+    #[tokio::test]
+    async fn test_agent_executes_local_tools() {
+        let chat = Arc::new(ScriptedChat::new(vec![
+            tool_call(
+                "call_1",
+                KnowledgeBaseSearchTool::NAME,
+                json!({"query": "退款"}),
+            ),
+            ChatResponse {
+                content: "支持七天无理由退款 [1]".to_string(),
+                ..Default::default()
+            },
+        ]));
+        let agent = ToolAgent::new(chat.clone(), kb_tools());
+
+        let run = agent
+            .run(ChatRequest::new(vec![ChatMessage::user("退款政策？")]))
+            .await
+            .unwrap();
+        assert_eq!(run.answer, "支持七天无理由退款 [1]");
+        assert!(run.pending_tool_calls.is_empty());
+        assert_eq!(run.messages.len(), 3);
+        assert!(run.messages[1].content.contains("七天无理由退款"));
+
+        let requests = chat.requests.lock().unwrap();
+        assert_eq!(requests[0].tools[0].name, KnowledgeBaseSearchTool::NAME);
+        assert_eq!(requests[1].messages.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_agent_returns_unknown_tools_as_pending() {
+        let chat = Arc::new(ScriptedChat::new(vec![tool_call(
+            "call_1",
+            "time_now",
+            json!({}),
+        )]));
+        let agent = ToolAgent::new(chat, kb_tools());
+
+        let run = agent
+            .run(ChatRequest::new(vec![ChatMessage::user("几点了？")]))
+            .await
+            .unwrap();
+        assert!(run.answer.is_empty());
+        assert_eq!(run.pending_tool_calls[0].name, "time_now");
+        assert_eq!(run.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_forces_answer_after_max_rounds() {
+        let call = || tool_call("call", KnowledgeBaseSearchTool::NAME, json!({"query": "x"}));
+        let chat = Arc::new(ScriptedChat::new(vec![call(), call()]));
+        let agent = ToolAgent::new(chat.clone(), kb_tools()).with_max_rounds(1);
+
+        // 强制回答的一轮仍返回工具调用时报错，而非返回空回答
+        let result = agent
+            .run(ChatRequest::new(vec![ChatMessage::user("q")]))
+            .await;
+        assert!(result.is_err());
+        let requests = chat.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].tool_choice, Some(ToolChoice::None));
+    }
+
+    #[tokio::test]
+    async fn test_agent_streams_answer_after_tools() {
+        let chat = Arc::new(ScriptedChat::new(vec![
+            ChatResponse {
+                tool_calls: vec![
+                    ToolCall {
+                        id: "call_1".to_string(),
+                        name: KnowledgeBaseSearchTool::NAME.to_string(),
+                        arguments: json!({"query": "退款"}),
+                    },
+                    ToolCall {
+                        id: "call_2".to_string(),
+                        name: "time_now".to_string(),
+                        arguments: json!({}),
+                    },
+                    ToolCall {
+                        id: "call_3".to_string(),
+                        name: "user_locale".to_string(),
+                        arguments: json!({}),
+                    },
+                ],
+                ..Default::default()
+            },
+            ChatResponse {
+                content: "七天内可退款".to_string(),
+                ..Default::default()
+            },
+        ]));
+        let agent = ToolAgent::new(chat, kb_tools());
+        let events = Mutex::new(Vec::new());
+
+        let run = agent
+            .run_stream(ChatRequest::new(vec![ChatMessage::user("q")]), |e| {
+                events.lock().unwrap().push(e);
+                async {}
+            })
+            .await
+            .unwrap();
+        // 客户端工具全部挂起，本地工具结果先行推送
+        let pending: Vec<&str> = run
+            .pending_tool_calls
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert_eq!(pending, vec!["call_2", "call_3"]);
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[1], AgentEvent::Message(m) if m.role == kb_llm::Role::Tool));
+
+        let chat = Arc::new(ScriptedChat::new(vec![ChatResponse {
+            content: "你好".to_string(),
+            ..Default::default()
+        }]));
+        let mut texts = Vec::new();
+        let run = ToolAgent::new(chat, kb_tools())
+            .run_stream(ChatRequest::new(vec![ChatMessage::user("hi")]), |e| {
+                texts.push(e);
+                async {}
+            })
+            .await
+            .unwrap();
+        assert_eq!(run.answer, "你好");
+        assert_eq!(texts, vec![AgentEvent::Text("你好".to_string())]);
+    }
+}